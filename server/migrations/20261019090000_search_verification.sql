@@ -0,0 +1,4 @@
+-- Adding the citation and faithfulness verification results to searches
+ALTER TABLE searches
+    ADD COLUMN faithfulness_score   double precision,
+    ADD COLUMN verification         jsonb;
@@ -0,0 +1,3 @@
+-- Recording the moderation outcome of the generated answer on searches
+ALTER TABLE searches
+    ADD COLUMN moderation           jsonb;
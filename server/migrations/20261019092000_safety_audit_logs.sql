@@ -0,0 +1,18 @@
+-- Creating a table for queries blocked or flagged by the safety policy
+CREATE TABLE safety_audit_logs
+(
+    safety_audit_log_id uuid primary key            default uuid_generate_v1mc(),
+    user_id             uuid            not null    references users (user_id),
+    query               text            not null,
+    action              varchar(32)     not null,
+    scores              jsonb           not null,
+    violations          jsonb           not null,
+    created_at          timestamptz     not null    default now(),
+    updated_at          timestamptz     not null    default now()
+);
+
+-- And applying our `updated_at` trigger is as easy as this.
+SELECT trigger_updated_at('safety_audit_logs');
+
+-- And creating an index on `user_id` to make it easier to find all audit logs for a given user
+CREATE INDEX safety_audit_logs_user_id ON safety_audit_logs (user_id);
@@ -0,0 +1,3 @@
+-- Generated thread titles must not overwrite the ones set by the user
+ALTER TABLE threads
+    ADD COLUMN title_set_by_user    BOOLEAN NOT NULL DEFAULT FALSE;
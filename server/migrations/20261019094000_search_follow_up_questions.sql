@@ -0,0 +1,3 @@
+-- Suggested follow-up questions generated after the answer
+ALTER TABLE searches
+    ADD COLUMN follow_up_questions  TEXT[];
@@ -0,0 +1,27 @@
+-- Soft deletion and archiving of threads and searches
+ALTER TABLE threads
+    ADD COLUMN archived_at          TIMESTAMPTZ,
+    ADD COLUMN deleted_at           TIMESTAMPTZ;
+
+ALTER TABLE searches
+    ADD COLUMN deleted_at           TIMESTAMPTZ;
+
+-- And cascading the hard deletion of the purge job to the rows that depend on a search
+ALTER TABLE searches
+    DROP CONSTRAINT searches_thread_id_fkey,
+    ADD CONSTRAINT searches_thread_id_fkey
+        FOREIGN KEY (thread_id) REFERENCES threads (thread_id) ON DELETE CASCADE;
+
+ALTER TABLE search_sources
+    DROP CONSTRAINT search_sources_search_id_fkey,
+    ADD CONSTRAINT search_sources_search_id_fkey
+        FOREIGN KEY (search_id) REFERENCES searches (search_id) ON DELETE CASCADE;
+
+ALTER TABLE collection_searches
+    DROP CONSTRAINT collection_searches_search_id_fkey,
+    ADD CONSTRAINT collection_searches_search_id_fkey
+        FOREIGN KEY (search_id) REFERENCES searches (search_id) ON DELETE CASCADE;
+
+-- And creating indexes on `deleted_at` to make it easier to find the rows to purge
+CREATE INDEX threads_deleted_at ON threads (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX searches_deleted_at ON searches (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,11 @@
+-- Full-text search over the search history. The expressions must match the ones used in the
+-- history search query for the indexes to be used.
+CREATE INDEX searches_search_vector ON searches USING GIN ((
+    setweight(to_tsvector('english', query), 'A') ||
+    setweight(to_tsvector('english', rephrased_query), 'B') ||
+    setweight(to_tsvector('english', result), 'C')
+));
+
+CREATE INDEX threads_title_vector ON threads USING GIN ((
+    setweight(to_tsvector('english', title), 'A')
+));
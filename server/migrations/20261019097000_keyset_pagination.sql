@@ -0,0 +1,3 @@
+-- Indexes matching the keyset pagination of threads and of the searches of a thread
+CREATE INDEX threads_user_id_created_at ON threads (user_id, created_at DESC, thread_id DESC);
+CREATE INDEX searches_thread_id_created_at ON searches (thread_id, created_at DESC, search_id DESC);
@@ -0,0 +1,19 @@
+-- Creating a table for the public read-only links to threads
+CREATE TABLE shared_threads
+(
+    shared_thread_id    uuid primary key            default uuid_generate_v1mc(),
+    thread_id           uuid            not null    references threads (thread_id) on delete cascade,
+    user_id             uuid            not null    references users (user_id),
+    token               varchar(64)     not null    unique,
+    include_sources     boolean         not null,
+    expires_at          timestamptz,
+    revoked_at          timestamptz,
+    created_at          timestamptz     not null    default now(),
+    updated_at          timestamptz     not null    default now()
+);
+
+-- And applying our `updated_at` trigger is as easy as this.
+SELECT trigger_updated_at('shared_threads');
+
+-- And creating an index on `thread_id` to make it easier to find all links for a given thread
+CREATE INDEX shared_threads_thread_id ON shared_threads (thread_id);
@@ -0,0 +1,3 @@
+-- Imported references are matched to existing sources by DOI and PMID before their url
+CREATE INDEX sources_metadata_doi ON sources (lower(metadata ->> 'doi'));
+CREATE INDEX sources_metadata_pmid ON sources ((metadata ->> 'pmid'));
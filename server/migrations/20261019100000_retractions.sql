@@ -0,0 +1,21 @@
+-- Creating a table for the retraction notices imported from the Retraction Watch dataset
+CREATE TABLE retractions
+(
+    retraction_id       uuid primary key            default uuid_generate_v1mc(),
+    record_id           varchar(64)     not null    unique,
+    doi                 text,
+    pmid                varchar(16),
+    nature              varchar(32)     not null,
+    notice_doi          text,
+    reason              text,
+    retracted_on        date,
+    created_at          timestamptz     not null    default now(),
+    updated_at          timestamptz     not null    default now()
+);
+
+-- And applying our `updated_at` trigger is as easy as this.
+SELECT trigger_updated_at('retractions');
+
+-- Retrieved sources are checked against the notices by DOI and PMID
+CREATE INDEX retractions_doi ON retractions (lower(doi));
+CREATE INDEX retractions_pmid ON retractions (pmid);
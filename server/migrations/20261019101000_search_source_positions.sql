@@ -0,0 +1,11 @@
+-- Keeping the rank of each source in its search, the number it is cited by in the answer
+ALTER TABLE search_sources
+    ADD COLUMN position             INTEGER     NOT NULL    DEFAULT 0;
+
+-- Existing sources were all inserted at once, so their insertion order is the best guess left
+UPDATE search_sources ss
+SET position = ranked.position
+FROM (SELECT search_source_id,
+             row_number() OVER (PARTITION BY search_id ORDER BY created_at, search_source_id) - 1 AS position
+      FROM search_sources) ranked
+WHERE ss.search_source_id = ranked.search_source_id;
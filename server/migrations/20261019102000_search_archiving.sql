@@ -0,0 +1,3 @@
+-- Archiving searches within a thread, like threads
+ALTER TABLE searches
+    ADD COLUMN archived_at          TIMESTAMPTZ;
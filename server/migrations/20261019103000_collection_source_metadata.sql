@@ -0,0 +1,3 @@
+-- Keeping the metadata of imported references with the collection, sources are shared by all users
+ALTER TABLE collection_sources
+    ADD COLUMN metadata             JSONB;
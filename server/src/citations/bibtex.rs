@@ -0,0 +1,485 @@
+use crate::citations::{normalize_doi, Author, CitationRecord, ParsedEntry};
+use std::collections::HashMap;
+
+// letters that take each accent command, followed by the accented letter
+const ACCENTS: [(char, &str); 5] = [
+    ('"', "aäeëiïoöuüyÿAÄEËIÏOÖUÜ"),
+    ('\'', "aáeéiíoóuúyýcćnńsśzźAÁEÉIÍOÓUÚ"),
+    ('`', "aàeèiìoòuùAÀEÈIÌOÒUÙ"),
+    ('^', "aâeêiîoôuûAÂEÊIÎOÔUÛ"),
+    ('~', "aãnñoõAÃNÑOÕ"),
+];
+
+/// Escapes the characters that are special to LaTeX in a field value.
+fn escape_bibtex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '{' | '}' | '%' | '&' | '$' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Builds the citation key from the first author, the year and the first word of the title, as
+/// in `smith2021metformin`.
+fn citation_key(record: &CitationRecord) -> String {
+    let ascii_word = |text: &str| -> String {
+        text.split_whitespace()
+            .map(|word| {
+                word.chars()
+                    .filter(char::is_ascii_alphanumeric)
+                    .collect::<String>()
+                    .to_ascii_lowercase()
+            })
+            .find(|word| !word.is_empty())
+            .unwrap_or_default()
+    };
+
+    let author = record
+        .authors
+        .first()
+        .map(|author| ascii_word(&author.family))
+        .unwrap_or_default();
+    let year = record.year.as_deref().map(ascii_word).unwrap_or_default();
+    let title = ascii_word(&record.title);
+
+    let key = format!("{}{}{}", author, year, title);
+    if key.is_empty() {
+        return "source".to_string();
+    }
+    key
+}
+
+fn render_entry(record: &CitationRecord, key: &str) -> String {
+    let mut fields: Vec<(&str, String)> = vec![];
+    if !record.authors.is_empty() {
+        let authors = record
+            .authors
+            .iter()
+            .map(|author| match &author.given {
+                Some(given) => format!(
+                    "{}, {}",
+                    escape_bibtex(&author.family),
+                    escape_bibtex(given)
+                ),
+                // kept in braces so that BibTeX does not split the name of an organization
+                None => format!("{{{}}}", escape_bibtex(&author.family)),
+            })
+            .collect::<Vec<String>>()
+            .join(" and ");
+        fields.push(("author", authors));
+    }
+    fields.push(("title", escape_bibtex(&record.title)));
+
+    let entry_type = if record.is_article() {
+        let optional_fields = [
+            ("journal", &record.journal),
+            ("year", &record.year),
+            ("volume", &record.volume),
+            ("number", &record.issue),
+            ("pages", &record.pages),
+            ("doi", &record.doi),
+            ("pmid", &record.pmid),
+        ];
+        for (name, value) in optional_fields {
+            if let Some(value) = value {
+                let value = match name {
+                    "pages" => value.replace('-', "--"),
+                    _ => value.clone(),
+                };
+                fields.push((name, escape_bibtex(&value)));
+            }
+        }
+        "article"
+    } else {
+        "misc"
+    };
+
+    // urls are verbatim in BibTeX, only the braces would break the entry
+    fields.push(("url", record.url.replace(['{', '}'], "")));
+    if !record.is_article() {
+        if let Some(accessed_at) = &record.accessed_at {
+            fields.push(("urldate", accessed_at.date().to_string()));
+        }
+    }
+
+    let fields = fields
+        .into_iter()
+        .map(|(name, value)| format!("  {} = {{{}}}", name, value))
+        .collect::<Vec<String>>()
+        .join(",\n");
+    format!("@{}{{{},\n{}\n}}\n", entry_type, key, fields)
+}
+
+/// Renders the records as BibTeX entries, with unique citation keys.
+pub fn render_bibtex(records: &[CitationRecord]) -> String {
+    let mut used_keys: HashMap<String, usize> = HashMap::new();
+
+    records
+        .iter()
+        .map(|record| {
+            let key = citation_key(record);
+            let count = used_keys.entry(key.clone()).or_insert(0);
+            *count += 1;
+            // repeated keys get a letter suffix, as in `smith2021metforminb`
+            let key = match *count {
+                1 => key,
+                n => format!("{}{}", key, suffix(n)),
+            };
+            render_entry(record, &key)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// 1 is `a`, 26 is `z` and 27 is `aa`
+fn suffix(mut n: usize) -> String {
+    let mut letters = vec![];
+    while n > 0 {
+        n -= 1;
+        letters.push((b'a' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+/// Splits `text` at the `separator` characters that are outside of braces and quotes.
+fn split_top_level(text: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut depth = 0;
+    let mut in_quotes = false;
+    for c in text.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '"' if depth == 0 => in_quotes = !in_quotes,
+            _ if c == separator && depth == 0 && !in_quotes => {
+                parts.push(std::mem::take(&mut part));
+                continue;
+            }
+            _ => {}
+        }
+        part.push(c);
+    }
+    parts.push(part);
+    parts
+}
+
+/// Joins the `#`-concatenated parts of a field value without their delimiters, keeping the
+/// braces inside them. Bare words are replaced with their `@string` definition.
+fn raw_value(value: &str, strings: &HashMap<String, String>) -> String {
+    split_top_level(value, '#')
+        .iter()
+        .map(|part| {
+            let part = part.trim();
+            part.strip_prefix('{')
+                .and_then(|p| p.strip_suffix('}'))
+                .or_else(|| part.strip_prefix('"').and_then(|p| p.strip_suffix('"')))
+                .or_else(|| strings.get(&part.to_lowercase()).map(String::as_str))
+                .unwrap_or(part)
+                .to_string()
+        })
+        .collect()
+}
+
+/// Turns a raw value into plain text: LaTeX escapes and accents become characters, braces and
+/// other commands are dropped.
+fn unescape_latex(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut text = String::with_capacity(value.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match c {
+            '{' | '}' => {}
+            '\\' => {
+                let Some(&next) = chars.get(i) else {
+                    break;
+                };
+                if let Some((_, letters)) = ACCENTS.iter().find(|(accent, _)| *accent == next) {
+                    i += 1;
+                    // the accented letter may be in braces, as in \"{o}
+                    while chars.get(i) == Some(&'{') {
+                        i += 1;
+                    }
+                    let Some(&letter) = chars.get(i) else {
+                        break;
+                    };
+                    i += 1;
+                    let letters: Vec<char> = letters.chars().collect();
+                    let accented = letters
+                        .chunks(2)
+                        .find(|pair| pair[0] == letter)
+                        .map(|pair| pair[1]);
+                    text.push(accented.unwrap_or(letter));
+                } else if next.is_ascii_alphabetic() {
+                    // commands such as \emph are dropped, their argument is kept
+                    while chars.get(i).is_some_and(char::is_ascii_alphabetic) {
+                        i += 1;
+                    }
+                } else {
+                    i += 1;
+                    text.push(next);
+                }
+            }
+            _ => text.push(c),
+        }
+    }
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Splits the author field at the top-level `and`s. Names in braces are kept whole, as the names
+/// of organizations.
+fn parse_bibtex_authors(value: &str) -> Vec<Author> {
+    let mut names = vec![];
+    let mut name = vec![];
+    let mut depth = 0;
+    for word in value.split_whitespace() {
+        if depth == 0 && word.eq_ignore_ascii_case("and") {
+            names.push(std::mem::take(&mut name));
+            continue;
+        }
+        depth += word.matches('{').count() as i32 - word.matches('}').count() as i32;
+        name.push(word);
+    }
+    names.push(name);
+
+    names
+        .into_iter()
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let name = name.join(" ");
+            if name.starts_with('{') && name.ends_with('}') {
+                return Author::parse(&unescape_latex(&name));
+            }
+            let parts = split_top_level(&name, ',');
+            match parts.as_slice() {
+                // "Family, Given" and "Family, Jr, Given"
+                [family, .., given] => Author::parse(&format!(
+                    "{}, {}",
+                    unescape_latex(family),
+                    unescape_latex(given)
+                )),
+                // "Given Family"
+                _ => {
+                    let name = unescape_latex(&name);
+                    match name.rsplit_once(' ') {
+                        Some((given, family)) => Author::parse(&format!("{}, {}", family, given)),
+                        None => Author::parse(&name),
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+fn parse_entry(key: &str, fields: &HashMap<String, String>) -> Result<CitationRecord, String> {
+    let field = |names: &[&str]| {
+        names
+            .iter()
+            .filter_map(|name| fields.get(*name))
+            .map(|value| unescape_latex(value))
+            .find(|value| !value.is_empty())
+    };
+
+    let Some(title) = field(&["title"]) else {
+        return Err(format!("Entry {} has no title", key));
+    };
+    let year = field(&["year", "date"])
+        .and_then(|date| date.get(..4).map(str::to_string))
+        .filter(|year| year.chars().all(|c| c.is_ascii_digit()));
+    let pmid = field(&["pmid"]).or_else(|| {
+        match field(&["eprinttype"])
+            .map(|t| t.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("pubmed") => field(&["eprint"]),
+            _ => None,
+        }
+    });
+
+    Ok(CitationRecord {
+        title,
+        url: field(&["url"]).unwrap_or_default(),
+        authors: fields
+            .get("author")
+            .map(|authors| parse_bibtex_authors(authors))
+            .unwrap_or_default(),
+        journal: field(&["journal", "journaltitle", "booktitle"]),
+        year,
+        volume: field(&["volume"]),
+        issue: field(&["number", "issue"]),
+        pages: field(&["pages"]).map(|pages| pages.replace("--", "-")),
+        doi: field(&["doi"]).and_then(|doi| normalize_doi(&doi)),
+        pmid,
+        accessed_at: None,
+    })
+}
+
+/// Parses the entries of a BibTeX file. `@string`, `@preamble` and `@comment` blocks are skipped,
+/// and an entry that cannot be read does not stop the ones after it.
+pub fn parse_bibtex(input: &str) -> Vec<ParsedEntry> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut entries = vec![];
+    let mut strings = HashMap::new();
+    let mut i = 0;
+
+    while let Some(offset) = chars[i..].iter().position(|c| *c == '@') {
+        i += offset + 1;
+        let type_start = i;
+        while chars.get(i).is_some_and(char::is_ascii_alphabetic) {
+            i += 1;
+        }
+        let entry_type: String = chars[type_start..i]
+            .iter()
+            .collect::<String>()
+            .to_lowercase();
+        while chars.get(i).is_some_and(|c| c.is_whitespace()) {
+            i += 1;
+        }
+        let closing = match chars.get(i) {
+            Some('{') => '}',
+            Some('(') => ')',
+            // an @ outside of an entry, as in a comment
+            _ => continue,
+        };
+        i += 1;
+
+        let body_start = i;
+        let mut depth = 0;
+        while let Some(&c) = chars.get(i) {
+            match c {
+                _ if c == closing && depth == 0 => break,
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            i += 1;
+        }
+        let body: String = chars[body_start..i.min(chars.len())].iter().collect();
+        let terminated = i < chars.len();
+        i = (i + 1).min(chars.len());
+
+        if entry_type == "string" {
+            if let Some((name, value)) = body.split_once('=') {
+                let value = raw_value(value, &strings);
+                strings.insert(name.trim().to_lowercase(), value);
+            }
+            continue;
+        }
+        if matches!(entry_type.as_str(), "preamble" | "comment") {
+            continue;
+        }
+
+        let mut fields = split_top_level(&body, ',').into_iter();
+        let key = fields.next().unwrap_or_default().trim().to_string();
+        let label = match key.is_empty() {
+            true => format!("#{}", entries.len() + 1),
+            false => key,
+        };
+        if !terminated {
+            entries.push(ParsedEntry {
+                record: Err(format!("Entry {} is not closed", label)),
+                label,
+            });
+            break;
+        }
+
+        let mut values = HashMap::new();
+        let mut malformed = false;
+        for field in fields.filter(|f| !f.trim().is_empty()) {
+            match field.split_once('=') {
+                Some((name, value)) => {
+                    values.insert(name.trim().to_lowercase(), raw_value(value, &strings));
+                }
+                None => malformed = true,
+            }
+        }
+        let record = match malformed {
+            true => Err(format!("Entry {} has a field without a value", label)),
+            false => parse_entry(&label, &values),
+        };
+        entries.push(ParsedEntry { label, record });
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::citations::models::tests::{article, web_page};
+
+    #[test]
+    fn test_render_bibtex() {
+        let bibtex = render_bibtex(&[article(), article(), web_page()]);
+
+        assert!(bibtex.starts_with("@article{smith2021metformin,\n"));
+        assert!(bibtex.contains("@article{smith2021metforminb,\n"));
+        assert!(bibtex.contains("  author = {Smith, Jane A and {World Health Organization}},\n"));
+        assert!(bibtex.contains("  title = {Metformin in pregnancy: a \\{systematic\\} review},\n"));
+        assert!(bibtex.contains("  pages = {123--130},\n"));
+        assert!(bibtex.contains("  pmid = {12345678},\n"));
+        assert!(bibtex.contains(
+            "@misc{metformin,\n  title = {Metformin - NHS},\n  url = {https://www.nhs.uk/medicines/metformin/},\n  urldate = {2026-10-19}\n}\n"
+        ));
+    }
+
+    #[test]
+    fn test_parse_bibtex() {
+        let input = r#"
+            @comment{exported from a reference manager}
+            @string{dc = "Diabetes Care"}
+            @Article{smith2021,
+              author = {Smith, Jane A. and M{\"u}ller, Hans and {World Health Organization}},
+              title = {Metformin in {Pregnancy}: a \& b},
+              journal = dc,
+              year = 2021,
+              pages = {123--130},
+              doi = {https://doi.org/10.2337/dc20-1234},
+              eprint = {12345678}, eprinttype = {pubmed},
+            }
+            @misc{nhs, author = "Jane Doe", url = {https://www.nhs.uk/}}
+            @misc{broken, title = {Broken
+        "#;
+        let entries = parse_bibtex(input);
+
+        assert_eq!(entries.len(), 3);
+        let record = entries[0].record.as_ref().unwrap();
+        assert_eq!(entries[0].label, "smith2021");
+        assert_eq!(record.title, "Metformin in Pregnancy: a & b");
+        assert_eq!(record.authors.len(), 3);
+        assert_eq!(record.authors[1].family, "Müller");
+        assert_eq!(record.authors[2].family, "World Health Organization");
+        assert_eq!(record.authors[2].given, None);
+        assert_eq!(record.journal.as_deref(), Some("Diabetes Care"));
+        assert_eq!(record.year.as_deref(), Some("2021"));
+        assert_eq!(record.pages.as_deref(), Some("123-130"));
+        assert_eq!(record.doi.as_deref(), Some("10.2337/dc20-1234"));
+        assert_eq!(record.pmid.as_deref(), Some("12345678"));
+
+        assert_eq!(entries[1].record, Err("Entry nhs has no title".to_string()));
+        assert_eq!(
+            entries[2].record,
+            Err("Entry broken is not closed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_rendered_bibtex() {
+        let entries = parse_bibtex(&render_bibtex(&[article(), web_page()]));
+        let mut expected = article();
+        expected.accessed_at = None;
+
+        assert_eq!(entries[0].record, Ok(expected));
+        assert_eq!(entries[1].record.as_ref().unwrap().url, web_page().url);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::citations::CitationRecord;
+use serde_json::{json, Map, Value};
+
+fn date_parts(parts: &[i64]) -> Value {
+    json!({ "date-parts": [parts] })
+}
+
+fn render_item(index: usize, record: &CitationRecord) -> Value {
+    let mut item = Map::new();
+    item.insert("id".to_string(), json!(format!("source-{}", index + 1)));
+    let item_type = if record.is_article() {
+        "article-journal"
+    } else {
+        "webpage"
+    };
+    item.insert("type".to_string(), json!(item_type));
+    item.insert("title".to_string(), json!(record.title));
+
+    if !record.authors.is_empty() {
+        let authors: Vec<Value> = record
+            .authors
+            .iter()
+            .map(|author| match &author.given {
+                Some(given) => json!({ "family": author.family, "given": given }),
+                None => json!({ "literal": author.family }),
+            })
+            .collect();
+        item.insert("author".to_string(), json!(authors));
+    }
+
+    let optional_fields = [
+        ("container-title", &record.journal),
+        ("volume", &record.volume),
+        ("issue", &record.issue),
+        ("page", &record.pages),
+        ("DOI", &record.doi),
+        ("PMID", &record.pmid),
+    ];
+    for (name, value) in optional_fields {
+        if let Some(value) = value {
+            item.insert(name.to_string(), json!(value));
+        }
+    }
+    if let Some(year) = record
+        .year
+        .as_ref()
+        .and_then(|year| year.parse::<i64>().ok())
+    {
+        item.insert("issued".to_string(), date_parts(&[year]));
+    }
+    if let Some(accessed_at) = &record.accessed_at {
+        let date = accessed_at.date();
+        item.insert(
+            "accessed".to_string(),
+            date_parts(&[
+                date.year() as i64,
+                u8::from(date.month()) as i64,
+                date.day() as i64,
+            ]),
+        );
+    }
+    item.insert("URL".to_string(), json!(record.url));
+
+    Value::Object(item)
+}
+
+/// Renders the records as a CSL-JSON array, the input of citation processors such as citeproc.
+pub fn render_csl_json(records: &[CitationRecord]) -> String {
+    let items: Vec<Value> = records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| render_item(index, record))
+        .collect();
+    serde_json::to_string_pretty(&items).unwrap_or_else(|_| "[]".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::citations::models::tests::{article, web_page};
+
+    #[test]
+    fn test_render_csl_json() {
+        let items: Vec<Value> =
+            serde_json::from_str(&render_csl_json(&[article(), web_page()])).unwrap();
+
+        assert_eq!(items[0]["type"], "article-journal");
+        assert_eq!(
+            items[0]["author"],
+            json!([
+                { "family": "Smith", "given": "Jane A" },
+                { "literal": "World Health Organization" },
+            ])
+        );
+        assert_eq!(items[0]["issued"], json!({ "date-parts": [[2021]] }));
+        assert_eq!(items[0]["PMID"], "12345678");
+        assert_eq!(items[1]["id"], "source-2");
+        assert_eq!(items[1]["type"], "webpage");
+        assert_eq!(
+            items[1]["accessed"],
+            json!({ "date-parts": [[2026, 10, 19]] })
+        );
+        assert!(items[1].get("author").is_none());
+    }
+}
@@ -0,0 +1,11 @@
+pub use bibtex::*;
+pub use csl_json::*;
+pub use models::*;
+pub use ris::*;
+pub use styles::*;
+
+pub mod bibtex;
+pub mod csl_json;
+pub mod models;
+pub mod ris;
+pub mod styles;
@@ -0,0 +1,299 @@
+use crate::citations::{render_bibtex, render_csl_json, render_ris};
+use crate::search::data_models;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+// Keys of the bibliographic fields in `sources.metadata`. Authors are stored as
+// "Family, Given" names separated by semicolons.
+pub const METADATA_AUTHORS: &str = "authors";
+pub const METADATA_JOURNAL: &str = "journal";
+pub const METADATA_YEAR: &str = "year";
+pub const METADATA_VOLUME: &str = "volume";
+pub const METADATA_ISSUE: &str = "issue";
+pub const METADATA_PAGES: &str = "pages";
+pub const METADATA_DOI: &str = "doi";
+pub const METADATA_PMID: &str = "pmid";
+
+const DOI_URL_PREFIX: &str = "https://doi.org/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CitationFormat {
+    Bibtex,
+    Ris,
+    CslJson,
+}
+
+impl CitationFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            CitationFormat::Bibtex => "application/x-bibtex; charset=utf-8",
+            CitationFormat::Ris => "application/x-research-info-systems; charset=utf-8",
+            CitationFormat::CslJson => "application/vnd.citationstyles.csl+json",
+        }
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            CitationFormat::Bibtex => "bib",
+            CitationFormat::Ris => "ris",
+            CitationFormat::CslJson => "json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Author {
+    pub family: String,
+    pub given: Option<String>,
+}
+
+impl Author {
+    /// Parses "Family, Given". Names without a comma, such as organizations, are kept whole as
+    /// the family name.
+    pub fn parse(name: &str) -> Option<Self> {
+        let (family, given) = match name.split_once(',') {
+            Some((family, given)) => (family.trim(), Some(given.trim())),
+            None => (name.trim(), None),
+        };
+        if family.is_empty() {
+            return None;
+        }
+        Some(Author {
+            family: family.to_string(),
+            given: given.filter(|g| !g.is_empty()).map(str::to_string),
+        })
+    }
+}
+
+/// Bibliographic view of a source. Web sources only have a title and a url, and are cited as
+/// web pages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CitationRecord {
+    pub title: String,
+    pub url: String,
+    pub authors: Vec<Author>,
+    pub journal: Option<String>,
+    pub year: Option<String>,
+    pub volume: Option<String>,
+    pub issue: Option<String>,
+    pub pages: Option<String>,
+    pub doi: Option<String>,
+    pub pmid: Option<String>,
+    /// When the source was retrieved, as the access date of web pages
+    pub accessed_at: Option<OffsetDateTime>,
+}
+
+impl CitationRecord {
+    pub fn from_source(source: &data_models::Source) -> Self {
+        let field = |key: &str| {
+            source
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get(key))
+                .and_then(|value| value.as_str())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+
+        CitationRecord {
+            title: source.title.clone(),
+            url: source.url.clone(),
+            authors: field(METADATA_AUTHORS)
+                .map(|authors| authors.split(';').filter_map(Author::parse).collect())
+                .unwrap_or_default(),
+            journal: field(METADATA_JOURNAL),
+            year: field(METADATA_YEAR),
+            volume: field(METADATA_VOLUME),
+            issue: field(METADATA_ISSUE),
+            pages: field(METADATA_PAGES),
+            doi: field(METADATA_DOI),
+            pmid: field(METADATA_PMID),
+            accessed_at: Some(source.created_at.0),
+        }
+    }
+
+    /// Whether the source is a journal article rather than a web page.
+    pub fn is_article(&self) -> bool {
+        self.journal.is_some() || self.doi.is_some() || self.pmid.is_some()
+    }
+
+    /// The bibliographic fields, as stored in `sources.metadata`.
+    pub fn to_metadata(&self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        if !self.authors.is_empty() {
+            let authors = self
+                .authors
+                .iter()
+                .map(|author| match &author.given {
+                    Some(given) => format!("{}, {}", author.family, given),
+                    None => author.family.clone(),
+                })
+                .collect::<Vec<String>>()
+                .join("; ");
+            metadata.insert(METADATA_AUTHORS.to_string(), authors);
+        }
+
+        let fields = [
+            (METADATA_JOURNAL, &self.journal),
+            (METADATA_YEAR, &self.year),
+            (METADATA_VOLUME, &self.volume),
+            (METADATA_ISSUE, &self.issue),
+            (METADATA_PAGES, &self.pages),
+            (METADATA_DOI, &self.doi),
+            (METADATA_PMID, &self.pmid),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                metadata.insert(key.to_string(), value.clone());
+            }
+        }
+        metadata
+    }
+
+    /// The url of the source, falling back to the PubMed page and then to the DOI resolver for
+    /// imported references that have none.
+    pub fn source_url(&self, pubmed_url_prefix: &str) -> Option<String> {
+        if !self.url.is_empty() {
+            return Some(self.url.clone());
+        }
+        if let Some(pmid) = &self.pmid {
+            return Some(format!("{}/{}", pubmed_url_prefix, pmid));
+        }
+        self.doi
+            .as_ref()
+            .map(|doi| format!("{}{}", DOI_URL_PREFIX, doi))
+    }
+}
+
+/// An entry of an imported reference file, or why it could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedEntry {
+    /// Citation key of the entry, or its position in the file, as in `#2`, when it has none
+    pub label: String,
+    pub record: Result<CitationRecord, String>,
+}
+
+/// Normalizes a DOI given as a resolver url or with a `doi:` prefix.
+pub fn normalize_doi(doi: &str) -> Option<String> {
+    let doi = doi.trim();
+    let lowercase = doi.to_ascii_lowercase();
+    let prefix_length = [
+        "https://doi.org/",
+        "http://doi.org/",
+        "https://dx.doi.org/",
+        "http://dx.doi.org/",
+        "doi:",
+    ]
+    .iter()
+    .find(|prefix| lowercase.starts_with(*prefix))
+    .map(|prefix| prefix.len())
+    .unwrap_or(0);
+    let doi = doi[prefix_length..].trim();
+    if doi.is_empty() {
+        return None;
+    }
+    Some(doi.to_string())
+}
+
+/// Renders the records in the format, as the bytes of the exported file.
+#[tracing::instrument(level = "info", skip(records))]
+pub fn render_citations(format: CitationFormat, records: &[CitationRecord]) -> Vec<u8> {
+    match format {
+        CitationFormat::Bibtex => render_bibtex(records).into_bytes(),
+        CitationFormat::Ris => render_ris(records).into_bytes(),
+        CitationFormat::CslJson => render_csl_json(records).into_bytes(),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    pub fn article() -> CitationRecord {
+        CitationRecord {
+            title: "Metformin in pregnancy: a {systematic} review".to_string(),
+            url: "https://pubmed.ncbi.nlm.nih.gov/12345678".to_string(),
+            authors: vec![
+                Author::parse("Smith, Jane A").unwrap(),
+                Author::parse("World Health Organization").unwrap(),
+            ],
+            journal: Some("Diabetes Care".to_string()),
+            year: Some("2021".to_string()),
+            volume: Some("44".to_string()),
+            issue: Some("3".to_string()),
+            pages: Some("123-130".to_string()),
+            doi: Some("10.2337/dc20-1234".to_string()),
+            pmid: Some("12345678".to_string()),
+            accessed_at: Some(datetime!(2026-10-19 09:30 UTC)),
+        }
+    }
+
+    pub fn web_page() -> CitationRecord {
+        CitationRecord {
+            title: "Metformin - NHS".to_string(),
+            url: "https://www.nhs.uk/medicines/metformin/".to_string(),
+            authors: vec![],
+            journal: None,
+            year: None,
+            volume: None,
+            issue: None,
+            pages: None,
+            doi: None,
+            pmid: None,
+            accessed_at: Some(datetime!(2026-10-19 09:30 UTC)),
+        }
+    }
+
+    #[test]
+    fn test_from_source_metadata() {
+        let source = data_models::Source {
+            source_id: uuid::Uuid::new_v4(),
+            url: "https://pubmed.ncbi.nlm.nih.gov/12345678".to_string(),
+            title: "Metformin in pregnancy".to_string(),
+            description: None,
+            source_type: data_models::SourceType::Url,
+            metadata: Some(serde_json::json!({
+                "authors": "Smith, Jane A; World Health Organization;",
+                "pmid": "12345678",
+                "doi": "",
+            })),
+            created_at: datetime!(2026-10-19 09:30 UTC).into(),
+            updated_at: datetime!(2026-10-19 09:30 UTC).into(),
+        };
+        let record = CitationRecord::from_source(&source);
+
+        assert_eq!(record.authors.len(), 2);
+        assert_eq!(record.authors[0].given.as_deref(), Some("Jane A"));
+        assert_eq!(record.authors[1].family, "World Health Organization");
+        assert_eq!(record.pmid.as_deref(), Some("12345678"));
+        assert_eq!(record.doi, None);
+        assert!(record.is_article());
+    }
+
+    #[test]
+    fn test_source_url() {
+        let mut record = web_page();
+        record.url = "".to_string();
+        assert_eq!(record.source_url("https://pubmed.ncbi.nlm.nih.gov"), None);
+
+        record.doi = normalize_doi("https://doi.org/10.2337/dc20-1234");
+        assert_eq!(
+            record
+                .source_url("https://pubmed.ncbi.nlm.nih.gov")
+                .unwrap(),
+            "https://doi.org/10.2337/dc20-1234"
+        );
+
+        record.pmid = Some("12345678".to_string());
+        assert_eq!(
+            record
+                .source_url("https://pubmed.ncbi.nlm.nih.gov")
+                .unwrap(),
+            "https://pubmed.ncbi.nlm.nih.gov/12345678"
+        );
+    }
+}
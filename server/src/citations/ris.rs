@@ -0,0 +1,238 @@
+use crate::citations::{normalize_doi, Author, CitationRecord, ParsedEntry};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+// a tag line, as in `AU  - Smith, Jane`. Some exporters drop the space after the dash.
+static TAG_LINE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([A-Z][A-Z0-9]) {1,2}- ?(.*)$").expect("Invalid tag line regex"));
+
+fn push_tag(lines: &mut Vec<String>, tag: &str, value: &str) {
+    // a tag holds a single line
+    let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if !value.is_empty() {
+        lines.push(format!("{}  - {}", tag, value));
+    }
+}
+
+fn render_entry(record: &CitationRecord) -> String {
+    let mut lines = vec![];
+    let is_article = record.is_article();
+    push_tag(&mut lines, "TY", if is_article { "JOUR" } else { "ELEC" });
+
+    for author in &record.authors {
+        let name = match &author.given {
+            Some(given) => format!("{}, {}", author.family, given),
+            None => author.family.clone(),
+        };
+        push_tag(&mut lines, "AU", &name);
+    }
+    push_tag(&mut lines, "TI", &record.title);
+
+    if is_article {
+        if let Some(journal) = &record.journal {
+            push_tag(&mut lines, "T2", journal);
+        }
+        if let Some(year) = &record.year {
+            push_tag(&mut lines, "PY", year);
+        }
+        if let Some(volume) = &record.volume {
+            push_tag(&mut lines, "VL", volume);
+        }
+        if let Some(issue) = &record.issue {
+            push_tag(&mut lines, "IS", issue);
+        }
+        if let Some(pages) = &record.pages {
+            match pages.split_once('-') {
+                Some((start, end)) => {
+                    push_tag(&mut lines, "SP", start);
+                    push_tag(&mut lines, "EP", end);
+                }
+                None => push_tag(&mut lines, "SP", pages),
+            }
+        }
+        if let Some(doi) = &record.doi {
+            push_tag(&mut lines, "DO", doi);
+        }
+        if let Some(pmid) = &record.pmid {
+            push_tag(&mut lines, "AN", pmid);
+        }
+    } else if let Some(accessed_at) = &record.accessed_at {
+        let date = accessed_at.date();
+        let access_date = format!(
+            "{}/{:02}/{:02}",
+            date.year(),
+            u8::from(date.month()),
+            date.day()
+        );
+        push_tag(&mut lines, "Y2", &access_date);
+    }
+    push_tag(&mut lines, "UR", &record.url);
+    // the end tag is the only one without a value
+    lines.push("ER  - ".to_string());
+
+    lines.join("\r\n") + "\r\n"
+}
+
+/// Renders the records as RIS entries, with the CRLF line endings of the format.
+pub fn render_ris(records: &[CitationRecord]) -> String {
+    records
+        .iter()
+        .map(render_entry)
+        .collect::<Vec<String>>()
+        .join("\r\n")
+}
+
+fn parse_entry(label: &str, tags: &[(String, String)]) -> Result<CitationRecord, String> {
+    // the first non-empty value of the tags, in order of preference
+    let tag = |names: &[&str]| {
+        names.iter().find_map(|name| {
+            tags.iter()
+                .find(|(tag, value)| tag == name && !value.is_empty())
+                .map(|(_, value)| value.clone())
+        })
+    };
+
+    let Some(title) = tag(&["TI", "T1", "CT"]) else {
+        return Err(format!("Entry {} has no title", label));
+    };
+    let authors = tags
+        .iter()
+        .filter(|(tag, _)| matches!(tag.as_str(), "AU" | "A1"))
+        .filter_map(|(_, name)| Author::parse(name))
+        .collect();
+    let year = tag(&["PY", "Y1", "DA"])
+        .and_then(|date| date.get(..4).map(str::to_string))
+        .filter(|year| year.chars().all(|c| c.is_ascii_digit()));
+    let pages = match (tag(&["SP"]), tag(&["EP"])) {
+        (Some(start), Some(end)) => Some(format!("{}-{}", start, end)),
+        (start, _) => start,
+    };
+    // PubMed and the exporters that follow it put the PMID in the accession number
+    let pmid = tag(&["AN"]).filter(|an| an.chars().all(|c| c.is_ascii_digit()));
+
+    Ok(CitationRecord {
+        title,
+        url: tag(&["UR"]).unwrap_or_default(),
+        authors,
+        journal: tag(&["T2", "JF", "JO", "JA"]),
+        year,
+        volume: tag(&["VL"]),
+        issue: tag(&["IS"]),
+        pages,
+        doi: tag(&["DO"]).and_then(|doi| normalize_doi(&doi)),
+        pmid,
+        accessed_at: None,
+    })
+}
+
+/// Parses the entries of a RIS file. An entry runs from its `TY` tag to its `ER` tag, and lines
+/// without a tag continue the value before them.
+pub fn parse_ris(input: &str) -> Vec<ParsedEntry> {
+    let mut entries = vec![];
+    let mut tags: Option<Vec<(String, String)>> = None;
+
+    let finish = |tags: Vec<(String, String)>, entries: &mut Vec<ParsedEntry>| {
+        let label = format!("#{}", entries.len() + 1);
+        let record = parse_entry(&label, &tags);
+        entries.push(ParsedEntry { label, record });
+    };
+
+    // files saved on Windows may start with a byte order mark
+    for line in input.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim_end();
+        let Some(captures) = TAG_LINE_REGEX.captures(line) else {
+            if let Some((_, value)) = tags.as_mut().and_then(|tags| tags.last_mut()) {
+                if !line.trim().is_empty() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            }
+            continue;
+        };
+        let tag = captures[1].to_string();
+        let value = captures[2].trim().to_string();
+
+        match tag.as_str() {
+            "TY" => {
+                // an entry without its ER tag ends at the next one
+                if let Some(tags) = tags.take() {
+                    finish(tags, &mut entries);
+                }
+                tags = Some(vec![(tag, value)]);
+            }
+            "ER" => {
+                if let Some(tags) = tags.take() {
+                    finish(tags, &mut entries);
+                }
+            }
+            _ => {
+                if let Some(tags) = tags.as_mut() {
+                    tags.push((tag, value));
+                }
+            }
+        }
+    }
+    if let Some(tags) = tags.take() {
+        finish(tags, &mut entries);
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::citations::models::tests::{article, web_page};
+
+    #[test]
+    fn test_render_ris() {
+        let ris = render_ris(&[article(), web_page()]);
+
+        assert!(ris.starts_with("TY  - JOUR\r\nAU  - Smith, Jane A\r\nAU  - World Health Organization\r\nTI  - Metformin in pregnancy: a {systematic} review\r\nT2  - Diabetes Care\r\n"));
+        assert!(
+            ris.contains("SP  - 123\r\nEP  - 130\r\nDO  - 10.2337/dc20-1234\r\nAN  - 12345678\r\n")
+        );
+        assert!(ris.ends_with("TY  - ELEC\r\nTI  - Metformin - NHS\r\nY2  - 2026/10/19\r\nUR  - https://www.nhs.uk/medicines/metformin/\r\nER  - \r\n"));
+    }
+
+    #[test]
+    fn test_parse_ris() {
+        let input = "\u{feff}TY  - JOUR\r\n\
+            AU  - Smith, Jane A\r\n\
+            TI  - Metformin in pregnancy:\r\n\
+            a systematic review\r\n\
+            JO  - Diabetes Care\r\n\
+            PY  - 2021/03/01/\r\n\
+            SP  - 123\r\n\
+            EP  - 130\r\n\
+            DO  - doi:10.2337/dc20-1234\r\n\
+            AN  - 12345678\r\n\
+            ER  - \r\n\
+            \r\n\
+            TY  - ELEC\r\n\
+            UR  - https://www.nhs.uk/\r\n\
+            ER  -\r\n";
+        let entries = parse_ris(input);
+
+        assert_eq!(entries.len(), 2);
+        let record = entries[0].record.as_ref().unwrap();
+        assert_eq!(record.title, "Metformin in pregnancy: a systematic review");
+        assert_eq!(record.authors[0].family, "Smith");
+        assert_eq!(record.journal.as_deref(), Some("Diabetes Care"));
+        assert_eq!(record.year.as_deref(), Some("2021"));
+        assert_eq!(record.pages.as_deref(), Some("123-130"));
+        assert_eq!(record.doi.as_deref(), Some("10.2337/dc20-1234"));
+        assert_eq!(record.pmid.as_deref(), Some("12345678"));
+        assert_eq!(entries[1].record, Err("Entry #2 has no title".to_string()));
+    }
+
+    #[test]
+    fn test_parse_rendered_ris() {
+        let entries = parse_ris(&render_ris(&[article(), web_page()]));
+        let mut expected = article();
+        expected.accessed_at = None;
+
+        assert_eq!(entries[0].record, Ok(expected));
+        assert_eq!(entries[1].record.as_ref().unwrap().url, web_page().url);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::citations::{Author, CitationRecord};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CitationStyle {
+    Vancouver,
+    Ama,
+    Apa,
+}
+
+/// Initials of the given names, as `JA` for "Jane Anne" and `JP` for "Jean-Paul".
+fn compact_initials(given: &str) -> String {
+    given
+        .split(|c: char| c.is_whitespace() || c == '-' || c == '.')
+        .filter_map(|name| name.chars().next())
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+/// Initials of the given names in APA style, as `J. A.` for "Jane Anne" and `J.-P.` for
+/// "Jean-Paul".
+fn dotted_initials(given: &str) -> String {
+    given
+        .split_whitespace()
+        .map(|name| {
+            name.split('-')
+                .filter_map(|part| part.chars().find(|c| c.is_alphabetic()))
+                .map(|c| format!("{}.", c.to_uppercase()))
+                .collect::<Vec<String>>()
+                .join("-")
+        })
+        .filter(|initials| !initials.is_empty())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Authors as `Smith JA, Doe J`, the first `shown` of them followed by `et al` when there are
+/// more than `max`.
+fn medical_authors(authors: &[Author], max: usize, shown: usize) -> String {
+    let name = |author: &Author| match &author.given {
+        Some(given) => format!("{} {}", author.family, compact_initials(given)),
+        None => author.family.clone(),
+    };
+    if authors.len() > max {
+        let names: Vec<String> = authors.iter().take(shown).map(name).collect();
+        return format!("{}, et al", names.join(", "));
+    }
+    authors.iter().map(name).collect::<Vec<String>>().join(", ")
+}
+
+/// Authors as `Smith, J. A., & Doe, J.`, with the first 19 and the last of them when there are
+/// more than 20.
+fn apa_authors(authors: &[Author]) -> String {
+    let names: Vec<String> = authors
+        .iter()
+        .map(|author| match &author.given {
+            Some(given) => format!("{}, {}", author.family, dotted_initials(given)),
+            None => author.family.clone(),
+        })
+        .collect();
+    match names.as_slice() {
+        [] => String::new(),
+        [name] => name.clone(),
+        [first, second] => format!("{}, & {}", first, second),
+        [first @ .., last] if names.len() > 20 => {
+            format!("{}, . . . {}", first[..19].join(", "), last)
+        }
+        [first @ .., last] => format!("{}, & {}", first.join(", "), last),
+    }
+}
+
+/// Ends the text with a period, unless it already ends with punctuation.
+fn sentence(text: &str) -> String {
+    let text = text.trim();
+    match text.ends_with(['.', '?', '!']) {
+        true => text.to_string(),
+        false => format!("{}.", text),
+    }
+}
+
+/// Drops the leading digits the last page shares with the first, as in `123-30` for `123-130`.
+fn elide_pages(pages: &str) -> String {
+    let Some((first, last)) = pages.split_once('-') else {
+        return pages.to_string();
+    };
+    let (first, last) = (first.trim(), last.trim());
+    let numeric = |page: &str| !page.is_empty() && page.chars().all(|c| c.is_ascii_digit());
+    if !numeric(first) || !numeric(last) || first.len() != last.len() {
+        return format!("{}-{}", first, last);
+    }
+    let shared = first
+        .chars()
+        .zip(last.chars())
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(last.len() - 1);
+    format!("{}-{}", first, &last[shared..])
+}
+
+/// The `;volume(issue):pages` that follow the year in Vancouver and AMA styles.
+fn medical_locator(record: &CitationRecord, pages: Option<String>) -> String {
+    let mut locator = String::new();
+    if let Some(volume) = &record.volume {
+        locator.push_str(&format!(";{}", volume));
+    }
+    if let Some(issue) = &record.issue {
+        locator.push_str(&format!("({})", issue));
+    }
+    if let Some(pages) = pages {
+        locator.push_str(&format!(":{}", pages));
+    }
+    locator
+}
+
+fn vancouver(record: &CitationRecord) -> String {
+    let mut parts = vec![];
+    if !record.authors.is_empty() {
+        parts.push(sentence(&medical_authors(&record.authors, 6, 6)));
+    }
+
+    if !record.is_article() {
+        parts.push(format!("{} [Internet].", record.title.trim()));
+        if let Some(accessed_at) = &record.accessed_at {
+            let date = accessed_at.date();
+            parts.push(format!(
+                "[cited {} {} {}].",
+                date.year(),
+                &MONTHS[u8::from(date.month()) as usize - 1][..3],
+                date.day()
+            ));
+        }
+        parts.push(format!("Available from: {}", record.url));
+        return parts.join(" ");
+    }
+
+    parts.push(sentence(&record.title));
+    if let Some(journal) = &record.journal {
+        parts.push(sentence(journal));
+    }
+    if let Some(year) = &record.year {
+        let pages = record.pages.as_deref().map(elide_pages);
+        parts.push(format!("{}{}.", year, medical_locator(record, pages)));
+    }
+    if let Some(doi) = &record.doi {
+        parts.push(format!("doi:{}.", doi));
+    }
+    if let Some(pmid) = &record.pmid {
+        parts.push(format!("PMID: {}.", pmid));
+    }
+    parts.join(" ")
+}
+
+fn long_date(date_time: &OffsetDateTime) -> String {
+    let date = date_time.date();
+    format!(
+        "{} {}, {}",
+        MONTHS[u8::from(date.month()) as usize - 1],
+        date.day(),
+        date.year()
+    )
+}
+
+fn ama(record: &CitationRecord) -> String {
+    let mut parts = vec![];
+    if !record.authors.is_empty() {
+        parts.push(sentence(&medical_authors(&record.authors, 6, 3)));
+    }
+    parts.push(sentence(&record.title));
+
+    if !record.is_article() {
+        if let Some(accessed_at) = &record.accessed_at {
+            parts.push(format!("Accessed {}.", long_date(accessed_at)));
+        }
+        parts.push(record.url.clone());
+        return parts.join(" ");
+    }
+
+    if let Some(journal) = &record.journal {
+        parts.push(sentence(journal));
+    }
+    if let Some(year) = &record.year {
+        parts.push(format!(
+            "{}{}.",
+            year,
+            medical_locator(record, record.pages.clone())
+        ));
+    }
+    match &record.doi {
+        Some(doi) => parts.push(format!("doi:{}", doi)),
+        None => parts.push(record.url.clone()),
+    }
+    parts.join(" ")
+}
+
+fn apa(record: &CitationRecord) -> String {
+    let year = format!("({}).", record.year.as_deref().unwrap_or("n.d."));
+    // without authors, the title takes their place
+    let mut parts = match record.authors.is_empty() {
+        true => vec![sentence(&record.title), year],
+        false => vec![
+            sentence(&apa_authors(&record.authors)),
+            year,
+            sentence(&record.title),
+        ],
+    };
+
+    if let Some(journal) = &record.journal {
+        let mut source = journal.trim().to_string();
+        if let Some(volume) = &record.volume {
+            source.push_str(&format!(", {}", volume));
+        }
+        if let Some(issue) = &record.issue {
+            source.push_str(&format!("({})", issue));
+        }
+        if let Some(pages) = &record.pages {
+            source.push_str(&format!(", {}", pages.replace('-', "\u{2013}")));
+        }
+        parts.push(format!("{}.", source));
+    }
+    match &record.doi {
+        Some(doi) => parts.push(format!("https://doi.org/{}", doi)),
+        None => parts.push(record.url.clone()),
+    }
+    parts.join(" ")
+}
+
+/// Formats the reference of the source in the style, as plain text.
+pub fn format_citation(style: CitationStyle, record: &CitationRecord) -> String {
+    match style {
+        CitationStyle::Vancouver => vancouver(record),
+        CitationStyle::Ama => ama(record),
+        CitationStyle::Apa => apa(record),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::citations::models::tests::{article, web_page};
+
+    #[test]
+    fn test_format_article() {
+        assert_eq!(
+            format_citation(CitationStyle::Vancouver, &article()),
+            "Smith JA, World Health Organization. Metformin in pregnancy: a {systematic} review. \
+            Diabetes Care. 2021;44(3):123-30. doi:10.2337/dc20-1234. PMID: 12345678."
+        );
+        assert_eq!(
+            format_citation(CitationStyle::Ama, &article()),
+            "Smith JA, World Health Organization. Metformin in pregnancy: a {systematic} review. \
+            Diabetes Care. 2021;44(3):123-130. doi:10.2337/dc20-1234"
+        );
+        assert_eq!(
+            format_citation(CitationStyle::Apa, &article()),
+            "Smith, J. A., & World Health Organization. (2021). Metformin in pregnancy: a \
+            {systematic} review. Diabetes Care, 44(3), 123\u{2013}130. \
+            https://doi.org/10.2337/dc20-1234"
+        );
+    }
+
+    #[test]
+    fn test_format_web_page() {
+        assert_eq!(
+            format_citation(CitationStyle::Vancouver, &web_page()),
+            "Metformin - NHS [Internet]. [cited 2026 Oct 19]. \
+            Available from: https://www.nhs.uk/medicines/metformin/"
+        );
+        assert_eq!(
+            format_citation(CitationStyle::Ama, &web_page()),
+            "Metformin - NHS. Accessed October 19, 2026. https://www.nhs.uk/medicines/metformin/"
+        );
+        assert_eq!(
+            format_citation(CitationStyle::Apa, &web_page()),
+            "Metformin - NHS. (n.d.). https://www.nhs.uk/medicines/metformin/"
+        );
+    }
+
+    #[test]
+    fn test_authors() {
+        let authors: Vec<Author> = (1..=8)
+            .map(|n| Author::parse(&format!("Author{}, Jean-Paul", n)).unwrap())
+            .collect();
+
+        assert_eq!(
+            medical_authors(&authors, 6, 3),
+            "Author1 JP, Author2 JP, Author3 JP, et al"
+        );
+        assert_eq!(
+            apa_authors(&authors[..3]),
+            "Author1, J.-P., Author2, J.-P., & Author3, J.-P."
+        );
+        assert_eq!(elide_pages("1234-1239"), "1234-9");
+        assert_eq!(elide_pages("e123-e130"), "e123-e130");
+    }
+}
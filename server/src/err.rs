@@ -54,6 +54,7 @@ impl AppError {
                 SearchError::ToxicQuery(_) => "toxic_query".to_string(),
                 SearchError::InvalidData(_) => "invalid_data".to_string(),
                 SearchError::NoResults(_) | SearchError::NoSources(_) => "no_results".to_string(),
+                SearchError::DependencyUnavailable(_) => "service_unavailable".to_string(),
                 _ => "internal_server_error".to_string(),
             },
             AppError::UserError(err) => match err {
@@ -94,6 +95,7 @@ impl AppError {
                     StatusCode::UNPROCESSABLE_ENTITY
                 }
                 SearchError::NoResults(_) | SearchError::NoSources(_) => StatusCode::NOT_FOUND,
+                SearchError::DependencyUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
             },
             AppError::AuthError(err) => match err {
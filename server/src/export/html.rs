@@ -0,0 +1,118 @@
+use crate::export::{format_timestamp, ExportDocument};
+use pulldown_cmark::{html, Event, Parser, Tag};
+
+const STYLE: &str = "body{font-family:Helvetica,Arial,sans-serif;max-width:48rem;margin:2rem auto;\
+padding:0 1rem;line-height:1.5;color:#1a1a1a}h2{margin-top:2.5rem}\
+.timestamp{color:#666;font-size:.875rem}ol.sources{font-size:.875rem}";
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Relative destinations have no scheme, so a colon before the first path, query or fragment
+// delimiter marks one
+fn is_safe_destination(destination: &str) -> bool {
+    let destination = destination.trim().to_ascii_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| destination.starts_with(scheme))
+        || !destination
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or_default()
+            .contains(':')
+}
+
+/// Renders the Markdown of an answer. Raw HTML in the answer is shown as text, never rendered,
+/// and links or images to anything but http(s) or mailto keep only their text.
+fn answer_to_html(answer: &str) -> String {
+    let parser = Parser::new(answer).filter_map(|event| match event {
+        Event::Html(html) => Some(Event::Text(html)),
+        Event::Start(Tag::Link(_, ref destination, _) | Tag::Image(_, ref destination, _))
+        | Event::End(Tag::Link(_, ref destination, _) | Tag::Image(_, ref destination, _))
+            if !is_safe_destination(destination) =>
+        {
+            None
+        }
+        event => Some(event),
+    });
+    let mut answer_html = String::new();
+    html::push_html(&mut answer_html, parser);
+    answer_html
+}
+
+/// Renders the document as a standalone HTML page, with its styles inlined.
+pub fn render_html(document: &ExportDocument) -> String {
+    let title = escape_html(&document.title);
+    let mut body = format!(
+        "<h1>{}</h1>\n<p class=\"timestamp\">Exported on {}</p>\n",
+        title,
+        format_timestamp(&document.exported_at)
+    );
+
+    for search in &document.searches {
+        body.push_str(&format!(
+            "<section>\n<h2>{}</h2>\n<p class=\"timestamp\">Asked on {}</p>\n{}",
+            escape_html(search.query.trim()),
+            format_timestamp(&search.created_at),
+            answer_to_html(&search.answer)
+        ));
+        if !search.sources.is_empty() {
+            body.push_str("<h3>Sources</h3>\n<ol class=\"sources\">\n");
+            for source in &search.sources {
+                body.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a></li>\n",
+                    escape_html(&source.url),
+                    escape_html(source.label())
+                ));
+            }
+            body.push_str("</ol>\n");
+        }
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+        <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        title, STYLE, body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer_to_html_escapes_raw_html() {
+        assert_eq!(
+            answer_to_html("Metformin is **safe** <script>alert(1)</script>"),
+            "<p>Metformin is <strong>safe</strong> &lt;script&gt;alert(1)&lt;/script&gt;</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_answer_to_html_drops_unsafe_destinations() {
+        assert_eq!(
+            answer_to_html(
+                "See [the trial](https://example.com/trial), [this](javascript:alert(1)) and \
+                ![a chart](data:image/png;base64,AAAA)."
+            ),
+            "<p>See <a href=\"https://example.com/trial\">the trial</a>, this and a chart.</p>\n"
+        );
+        assert!(is_safe_destination("mailto:info@example.com"));
+        assert!(is_safe_destination("/threads/1?page=2"));
+        assert!(!is_safe_destination(" JavaScript:alert(1)"));
+        assert!(!is_safe_destination("vbscript:msgbox"));
+    }
+}
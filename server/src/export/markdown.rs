@@ -0,0 +1,94 @@
+use crate::export::{format_timestamp, ExportDocument};
+
+// Brackets in a title would end the text of its link early
+fn escape_link_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Spaces and parentheses would end the destination of a link early, and angle brackets could
+// turn it into an autolink
+fn escape_link_destination(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            ' ' => escaped.push_str("%20"),
+            '(' => escaped.push_str("%28"),
+            ')' => escaped.push_str("%29"),
+            '<' => escaped.push_str("%3C"),
+            '>' => escaped.push_str("%3E"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the document as Markdown. The answers are Markdown already, so they are kept as is.
+pub fn render_markdown(document: &ExportDocument) -> String {
+    let mut markdown = format!(
+        "# {}\n\n_Exported on {}_\n",
+        document.title,
+        format_timestamp(&document.exported_at)
+    );
+
+    for search in &document.searches {
+        markdown.push_str(&format!(
+            "\n## {}\n\n_Asked on {}_\n\n{}\n",
+            search.query.trim(),
+            format_timestamp(&search.created_at),
+            search.answer.trim()
+        ));
+        if !search.sources.is_empty() {
+            markdown.push_str("\n### Sources\n\n");
+            for (index, source) in search.sources.iter().enumerate() {
+                markdown.push_str(&format!(
+                    "{}. [{}]({})\n",
+                    index + 1,
+                    escape_link_text(source.label()),
+                    escape_link_destination(&source.url)
+                ));
+            }
+        }
+    }
+
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{ExportedSearch, ExportedSource};
+    use time::macros::datetime;
+
+    #[test]
+    fn test_render_markdown() {
+        let document = ExportDocument {
+            title: "Metformin in pregnancy".to_string(),
+            searches: vec![ExportedSearch {
+                query: "Is metformin safe in pregnancy?".to_string(),
+                answer: "Metformin is considered safe [1].".to_string(),
+                sources: vec![ExportedSource {
+                    title: "Metformin and pregnancy outcomes [published correction]".to_string(),
+                    url: "https://example.com/wiki/Metformin_(drug) overview".to_string(),
+                    citation: None,
+                }],
+                created_at: datetime!(2026-10-19 09:30 UTC),
+            }],
+            exported_at: datetime!(2026-10-20 10:00 UTC),
+        };
+
+        assert_eq!(
+            render_markdown(&document),
+            "# Metformin in pregnancy\n\n_Exported on 2026-10-20 10:00 UTC_\n\
+            \n## Is metformin safe in pregnancy?\n\n_Asked on 2026-10-19 09:30 UTC_\n\n\
+            Metformin is considered safe [1].\n\
+            \n### Sources\n\n1. [Metformin and pregnancy outcomes \\[published correction\\]](https://example.com/wiki/Metformin_%28drug%29%20overview)\n"
+        );
+    }
+}
@@ -0,0 +1,9 @@
+pub use html::*;
+pub use markdown::*;
+pub use models::*;
+pub use pdf::*;
+
+pub mod html;
+pub mod markdown;
+pub mod models;
+pub mod pdf;
@@ -0,0 +1,113 @@
+use crate::export::{render_html, render_markdown, render_pdf};
+use serde::{Deserialize, Serialize};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Pdf,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+            ExportFormat::Html => "text/html; charset=utf-8",
+            ExportFormat::Pdf => "application/pdf",
+        }
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportedSource {
+    pub title: String,
+    pub url: String,
+    /// Reference in the requested citation style, listed in place of the title
+    pub citation: Option<String>,
+}
+
+impl ExportedSource {
+    pub fn label(&self) -> &str {
+        self.citation.as_deref().unwrap_or(&self.title)
+    }
+}
+
+/// A search as exported, its sources numbered from 1 in the order the answer cites them.
+#[derive(Debug, Clone)]
+pub struct ExportedSearch {
+    pub query: String,
+    pub answer: String,
+    pub sources: Vec<ExportedSource>,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportDocument {
+    pub title: String,
+    pub searches: Vec<ExportedSearch>,
+    pub exported_at: OffsetDateTime,
+}
+
+pub fn format_timestamp(timestamp: &OffsetDateTime) -> String {
+    timestamp
+        .format(format_description!(
+            "[year]-[month]-[day] [hour]:[minute] UTC"
+        ))
+        .unwrap_or_default()
+}
+
+/// Names the exported file after the title, keeping it to characters that are safe in a
+/// `Content-Disposition` header.
+pub fn export_file_name(title: &str, format: ExportFormat) -> String {
+    let mut name = String::new();
+    for c in title.chars().filter(|c| c.is_ascii()).take(60) {
+        match c.is_ascii_alphanumeric() {
+            true => name.push(c.to_ascii_lowercase()),
+            false if !name.ends_with('-') => name.push('-'),
+            false => {}
+        }
+    }
+    let name = name.trim_matches('-');
+    let name = if name.is_empty() { "export" } else { name };
+
+    format!("{}.{}", name, format.file_extension())
+}
+
+/// Renders the document in the format, as the bytes of the exported file.
+#[tracing::instrument(level = "info", skip(document))]
+pub fn render_export(format: ExportFormat, document: &ExportDocument) -> Vec<u8> {
+    match format {
+        ExportFormat::Markdown => render_markdown(document).into_bytes(),
+        ExportFormat::Html => render_html(document).into_bytes(),
+        ExportFormat::Pdf => render_pdf(document),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_file_name() {
+        assert_eq!(
+            export_file_name("Is metformin safe in pregnancy?", ExportFormat::Pdf),
+            "is-metformin-safe-in-pregnancy.pdf"
+        );
+        assert_eq!(
+            export_file_name("\"; rm -rf", ExportFormat::Html),
+            "rm-rf.html"
+        );
+        assert_eq!(export_file_name("ß", ExportFormat::Markdown), "export.md");
+    }
+}
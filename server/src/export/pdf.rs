@@ -0,0 +1,408 @@
+use crate::export::{format_timestamp, ExportDocument};
+use pdf_writer::{Content, Name, Pdf, Rect, Ref, Str, TextStr};
+use pulldown_cmark::{Event, Parser, Tag};
+
+// A4, in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 56.0;
+const LINE_SPACING: f32 = 1.4;
+const LIST_INDENT: f32 = 12.0;
+
+// Advance widths of the characters from ' ' to '~', in thousandths of the font size, from the
+// AFM metrics of the standard fonts. The oblique font has the widths of the regular one.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611, 975, 722, 722, 722, 722, 667,
+    611, 778, 722, 278, 556, 722, 611, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 333, 278, 333, 584, 556, 333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556,
+    278, 889, 611, 611, 611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+// used for the characters outside of the tables
+const DEFAULT_WIDTH: u16 = 556;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FontStyle {
+    Regular,
+    Bold,
+    Italic,
+}
+
+impl FontStyle {
+    const ALL: [FontStyle; 3] = [FontStyle::Regular, FontStyle::Bold, FontStyle::Italic];
+
+    fn resource_name(&self) -> Name<'static> {
+        match self {
+            FontStyle::Regular => Name(b"F1"),
+            FontStyle::Bold => Name(b"F2"),
+            FontStyle::Italic => Name(b"F3"),
+        }
+    }
+
+    fn base_font(&self) -> Name<'static> {
+        match self {
+            FontStyle::Regular => Name(b"Helvetica"),
+            FontStyle::Bold => Name(b"Helvetica-Bold"),
+            FontStyle::Italic => Name(b"Helvetica-Oblique"),
+        }
+    }
+
+    fn char_width(&self, c: char, size: f32) -> f32 {
+        let widths = match self {
+            FontStyle::Bold => &HELVETICA_BOLD_WIDTHS,
+            _ => &HELVETICA_WIDTHS,
+        };
+        let units = match c {
+            ' '..='~' => widths[c as usize - ' ' as usize],
+            _ => DEFAULT_WIDTH,
+        };
+        units as f32 * size / 1000.0
+    }
+
+    fn text_width(&self, text: &str, size: f32) -> f32 {
+        text.chars().map(|c| self.char_width(c, size)).sum()
+    }
+}
+
+/// Spells out the common characters that WinAnsiEncoding does not cover, such as the symbols and
+/// Greek letters of medical texts.
+fn transliterate(text: &str) -> String {
+    let mut transliterated = String::with_capacity(text.len());
+    for c in text.chars() {
+        let replacement = match c {
+            '\u{2265}' => ">=",
+            '\u{2264}' => "<=",
+            '\u{2260}' => "!=",
+            '\u{2248}' => "~",
+            '\u{2212}' | '\u{2010}' | '\u{2011}' => "-",
+            '\u{2192}' => "->",
+            '\u{2190}' => "<-",
+            '\u{2191}' => "increased",
+            '\u{2193}' => "decreased",
+            '\u{3bc}' => "\u{b5}",
+            '\u{3b1}' => "alpha",
+            '\u{3b2}' => "beta",
+            '\u{3b3}' => "gamma",
+            '\u{3b4}' => "delta",
+            '\u{394}' => "Delta",
+            '\u{3b5}' => "epsilon",
+            '\u{3b8}' => "theta",
+            '\u{3ba}' => "kappa",
+            '\u{3bb}' => "lambda",
+            '\u{3c0}' => "pi",
+            '\u{3c3}' => "sigma",
+            '\u{3c4}' => "tau",
+            '\u{3c7}' => "chi",
+            '\u{3c9}' => "omega",
+            '\u{2070}' => "^0",
+            '\u{2074}' => "^4",
+            '\u{2075}' => "^5",
+            '\u{2076}' => "^6",
+            '\u{2077}' => "^7",
+            '\u{2078}' => "^8",
+            '\u{2079}' => "^9",
+            '\u{207b}' => "^-",
+            _ => {
+                transliterated.push(c);
+                continue;
+            }
+        };
+        transliterated.push_str(replacement);
+    }
+    transliterated
+}
+
+/// Encodes the text in WinAnsiEncoding, the encoding of the standard fonts. Characters it does
+/// not cover are replaced with `?`, once `transliterate` has spelled out the common ones.
+fn encode_win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' | '\u{a0}'..='\u{ff}' => c as u8,
+            '\u{20ac}' => 0x80,
+            '\u{201a}' => 0x82,
+            '\u{201e}' => 0x84,
+            '\u{2026}' => 0x85,
+            '\u{2020}' => 0x86,
+            '\u{2021}' => 0x87,
+            '\u{2030}' => 0x89,
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201c}' => 0x93,
+            '\u{201d}' => 0x94,
+            '\u{2022}' => 0x95,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            '\u{2122}' => 0x99,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Breaks the text into lines that fit `max_width`, between words when possible.
+fn wrap_text(style: FontStyle, size: f32, text: &str, max_width: f32) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut line_width = 0.0;
+    let space_width = style.char_width(' ', size);
+
+    for word in text.split_whitespace() {
+        let word_width = style.text_width(word, size);
+        if !line.is_empty() && line_width + space_width + word_width <= max_width {
+            line.push(' ');
+            line.push_str(word);
+            line_width += space_width + word_width;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        line_width = 0.0;
+        // words longer than a line, such as urls, are broken anywhere
+        for c in word.chars() {
+            let char_width = style.char_width(c, size);
+            if !line.is_empty() && line_width + char_width > max_width {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0.0;
+            }
+            line.push(c);
+            line_width += char_width;
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Flattens the Markdown of an answer into paragraphs of plain text, with the nesting depth of
+/// the list they are in.
+fn markdown_to_paragraphs(markdown: &str) -> Vec<(String, usize)> {
+    let mut paragraphs = vec![];
+    let mut paragraph = String::new();
+    // the next number of each open list, `None` for bullet lists
+    let mut lists: Vec<Option<u64>> = vec![];
+
+    let mut flush = |paragraph: &mut String, depth: usize| {
+        let text = paragraph.trim().to_string();
+        if !text.is_empty() {
+            paragraphs.push((text, depth));
+        }
+        paragraph.clear();
+    };
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                flush(&mut paragraph, lists.len());
+                lists.push(start);
+            }
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut paragraph, lists.len());
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        paragraph.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => paragraph.push_str("- "),
+                }
+            }
+            Event::Text(text) | Event::Code(text) | Event::Html(text) => paragraph.push_str(&text),
+            Event::SoftBreak => paragraph.push(' '),
+            Event::HardBreak
+            | Event::End(Tag::Paragraph)
+            | Event::End(Tag::Heading(..))
+            | Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::Item) => flush(&mut paragraph, lists.len()),
+            _ => {}
+        }
+    }
+    flush(&mut paragraph, lists.len());
+
+    paragraphs
+}
+
+/// Lays out the lines of text top to bottom, starting a new page when one is full.
+struct PageLayout {
+    pages: Vec<Content>,
+    content: Content,
+    y: f32,
+}
+
+impl PageLayout {
+    fn new() -> Self {
+        Self {
+            pages: vec![],
+            content: Content::new(),
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    fn write(&mut self, style: FontStyle, size: f32, text: &str, indent: f32, space_before: f32) {
+        let line_height = size * LINE_SPACING;
+        let max_width = PAGE_WIDTH - 2.0 * MARGIN - indent;
+        self.y -= space_before;
+
+        for line in wrap_text(style, size, &transliterate(text), max_width) {
+            if self.y - line_height < MARGIN {
+                let content = std::mem::replace(&mut self.content, Content::new());
+                self.pages.push(content);
+                self.y = PAGE_HEIGHT - MARGIN;
+            }
+            self.y -= line_height;
+            self.content
+                .begin_text()
+                .set_font(style.resource_name(), size)
+                .next_line(MARGIN + indent, self.y)
+                .show(Str(&encode_win_ansi(&line)))
+                .end_text();
+        }
+    }
+
+    fn finish(mut self) -> Vec<Content> {
+        self.pages.push(self.content);
+        self.pages
+    }
+}
+
+/// Renders the document as a PDF with the standard Helvetica fonts, so that no font has to be
+/// embedded.
+pub fn render_pdf(document: &ExportDocument) -> Vec<u8> {
+    let mut layout = PageLayout::new();
+    layout.write(FontStyle::Bold, 18.0, &document.title, 0.0, 0.0);
+    layout.write(
+        FontStyle::Italic,
+        9.0,
+        &format!("Exported on {}", format_timestamp(&document.exported_at)),
+        0.0,
+        4.0,
+    );
+
+    for search in &document.searches {
+        layout.write(FontStyle::Bold, 13.0, search.query.trim(), 0.0, 18.0);
+        layout.write(
+            FontStyle::Italic,
+            9.0,
+            &format!("Asked on {}", format_timestamp(&search.created_at)),
+            0.0,
+            2.0,
+        );
+        for (paragraph, depth) in markdown_to_paragraphs(&search.answer) {
+            let indent = depth as f32 * LIST_INDENT;
+            layout.write(FontStyle::Regular, 10.5, &paragraph, indent, 6.0);
+        }
+
+        if !search.sources.is_empty() {
+            layout.write(FontStyle::Bold, 11.0, "Sources", 0.0, 10.0);
+            for (index, source) in search.sources.iter().enumerate() {
+                let title = format!("{}. {}", index + 1, source.label());
+                layout.write(FontStyle::Regular, 9.0, &title, 0.0, 3.0);
+                layout.write(FontStyle::Italic, 8.5, &source.url, LIST_INDENT, 0.0);
+            }
+        }
+    }
+
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let document_info_id = Ref::new(3);
+    let font_ids = [Ref::new(4), Ref::new(5), Ref::new(6)];
+    let mut next_id = 7;
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.document_info(document_info_id)
+        .title(TextStr(&document.title));
+    for (style, font_id) in FontStyle::ALL.iter().zip(font_ids) {
+        pdf.type1_font(font_id)
+            .base_font(style.base_font())
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+    }
+
+    let mut page_ids = vec![];
+    for content in layout.finish() {
+        let page_id = Ref::new(next_id);
+        let content_id = Ref::new(next_id + 1);
+        next_id += 2;
+        page_ids.push(page_id);
+
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+            .parent(page_tree_id)
+            .contents(content_id);
+        let mut resources = page.resources();
+        let mut fonts = resources.fonts();
+        for (style, font_id) in FontStyle::ALL.iter().zip(font_ids) {
+            fonts.pair(style.resource_name(), font_id);
+        }
+        drop(fonts);
+        drop(resources);
+        drop(page);
+
+        pdf.stream(content_id, &content.finish());
+    }
+    pdf.pages(page_tree_id)
+        .count(page_ids.len() as i32)
+        .kids(page_ids);
+
+    pdf.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_text() {
+        let lines = wrap_text(
+            FontStyle::Regular,
+            10.0,
+            "Metformin is considered safe during pregnancy",
+            100.0,
+        );
+
+        assert_eq!(
+            lines,
+            vec!["Metformin is", "considered safe", "during pregnancy"]
+        );
+        assert!(lines
+            .iter()
+            .all(|line| FontStyle::Regular.text_width(line, 10.0) <= 100.0));
+    }
+
+    #[test]
+    fn test_encode_win_ansi() {
+        assert_eq!(
+            encode_win_ansi(&transliterate(
+                "HbA1c \u{2265} 7% in \u{3b2}-cell \u{2014} 5 \u{3bc}g"
+            )),
+            b"HbA1c >= 7% in beta-cell \x97 5 \xb5g".to_vec()
+        );
+        assert_eq!(
+            transliterate("10\u{2075} cells/\u{3bc}L"),
+            "10^5 cells/\u{b5}L"
+        );
+        assert_eq!(encode_win_ansi("\u{4e2d}"), b"?".to_vec());
+    }
+
+    #[test]
+    fn test_markdown_to_paragraphs() {
+        assert_eq!(
+            markdown_to_paragraphs("Metformin is **safe** [1].\n\n1. Lowers glucose\n2. Cheap"),
+            vec![
+                ("Metformin is safe [1].".to_string(), 0),
+                ("1. Lowers glucose".to_string(), 1),
+                ("2. Cheap".to_string(), 1),
+            ]
+        );
+    }
+}
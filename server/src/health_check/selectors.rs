@@ -1,6 +1,26 @@
+use crate::resilience::{CircuitState, Dependencies};
+use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
+use axum::Json;
 
-pub async fn health_check() -> impl IntoResponse {
-    (StatusCode::OK, "Healthy!")
+/// Reports the circuit state of every outbound dependency. The server itself stays available
+/// while a dependency is down, so an open circuit only marks it as degraded.
+pub async fn health_check(State(dependencies): State<Dependencies>) -> impl IntoResponse {
+    let circuit_states = dependencies.circuit_states();
+    let status = match circuit_states
+        .values()
+        .all(|state| *state == CircuitState::Closed)
+    {
+        true => "healthy",
+        false => "degraded",
+    };
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "status": status,
+            "dependencies": circuit_states,
+        })),
+    )
 }
@@ -4,11 +4,14 @@ use startup::Application;
 
 pub mod auth;
 pub mod cache;
+pub mod citations;
 pub mod custom_types;
 mod err;
+pub mod export;
 mod health_check;
 pub mod llms;
 pub mod rag;
+pub mod resilience;
 pub mod routing;
 pub mod search;
 pub mod secrets;
@@ -0,0 +1,262 @@
+use crate::llms::{extract_citations, query_rephraser, split_sentences, tokenizer};
+use crate::rag::Source;
+use crate::resilience::Dependency;
+use crate::search::SearchError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationMemorySettings {
+    pub enabled: bool,
+    /// Tokens the memory may take in the rephraser and summarizer prompts
+    pub max_tokens: usize,
+    pub max_entities: usize,
+    pub max_cited_sources: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CitedSource {
+    pub title: String,
+    pub url: String,
+}
+
+/// Rolling memory of a thread, stored in `threads.context`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConversationMemory {
+    pub summary: String,
+    pub entities: Vec<String>,
+    pub cited_sources: Vec<CitedSource>,
+    pub turns: usize,
+}
+
+#[derive(Debug)]
+pub struct ConversationTurn {
+    pub query: String,
+    pub answer: String,
+    pub cited_sources: Vec<CitedSource>,
+}
+
+/// Returns the sources that the answer cites, in order of first citation.
+pub fn cited_sources(answer: &str, sources: &[Source]) -> Vec<CitedSource> {
+    let mut cited_sources: Vec<CitedSource> = vec![];
+    for number in split_sentences(answer)
+        .iter()
+        .flat_map(|sentence| extract_citations(sentence))
+    {
+        let Some(source) = number.checked_sub(1).and_then(|index| sources.get(index)) else {
+            continue;
+        };
+        if !cited_sources.iter().any(|cited| cited.url == source.url) {
+            cited_sources.push(CitedSource {
+                title: source.title.clone(),
+                url: source.url.clone(),
+            });
+        }
+    }
+    cited_sources
+}
+
+/// Formats the memory for the rephraser and summarizer prompts, empty when there is nothing to
+/// remember yet.
+pub fn format_conversation_memory(
+    model: &str,
+    settings: &ConversationMemorySettings,
+    memory: &ConversationMemory,
+) -> Result<String, SearchError> {
+    if !settings.enabled || memory.turns == 0 {
+        return Ok(String::new());
+    }
+
+    let mut lines = vec![format!("Conversation summary: {}", memory.summary)];
+    if !memory.entities.is_empty() {
+        lines.push(format!("Key entities: {}", memory.entities.join(", ")));
+    }
+    if !memory.cited_sources.is_empty() {
+        lines.push(format!(
+            "Sources already cited: {}",
+            memory
+                .cited_sources
+                .iter()
+                .map(|source| format!("{} ({})", source.title, source.url))
+                .collect::<Vec<String>>()
+                .join("; ")
+        ));
+    }
+
+    tokenizer::truncate_to_tokens(model, &lines.join("\n"), settings.max_tokens)
+}
+
+#[tracing::instrument(level = "info", ret)]
+fn prepare_memory_prompt(memory: &ConversationMemory, turn: &ConversationTurn) -> String {
+    format!("[INST] You keep the memory of a conversation between a user and a scientific medical assistant. Update the summary of the conversation with the latest question and answer in a few sentences, keeping what matters to understand follow-up questions. Then list the key medical entities discussed so far, such as conditions, drugs, procedures and populations.\n\n---\n\nFollow the following format.\n\nSummary: ${{summary}}\n\nEntities: ${{entities separated by semicolons}}\n\n---\n\nPrevious summary: {}\n\nPrevious entities: {}\n\nLatest question: {}\n\nLatest answer: {}\n\nSummary: [/INST]",
+        memory.summary,
+        memory.entities.join("; "),
+        turn.query,
+        turn.answer,
+    )
+}
+
+/// Parses `Summary: ... Entities: a; b` out of the completion. The prompt already ends with
+/// `Summary:`, so the label is optional.
+fn parse_memory_completion(completion: &str) -> Option<(String, Vec<String>)> {
+    let completion = completion.trim();
+    let completion = completion.strip_prefix("Summary:").unwrap_or(completion);
+    let (summary, entities) = match completion.find("Entities:") {
+        Some(index) => (
+            &completion[..index],
+            &completion[index + "Entities:".len()..],
+        ),
+        None => (completion, ""),
+    };
+
+    let summary = summary.trim().to_string();
+    if summary.is_empty() {
+        return None;
+    }
+    let entities = entities
+        .split([';', '\n'])
+        .map(|entity| entity.trim().to_string())
+        .filter(|entity| !entity.is_empty())
+        .collect();
+
+    Some((summary, entities))
+}
+
+fn merge_entities(previous: &[String], latest: Vec<String>, max_entities: usize) -> Vec<String> {
+    let mut entities: Vec<String> = vec![];
+    // the latest entities come first, so the ones that fell out of the conversation are dropped
+    for entity in latest.into_iter().chain(previous.iter().cloned()) {
+        if !entities.iter().any(|e| e.eq_ignore_ascii_case(&entity)) {
+            entities.push(entity);
+        }
+    }
+    entities.truncate(max_entities);
+    entities
+}
+
+fn merge_cited_sources(
+    previous: &[CitedSource],
+    latest: Vec<CitedSource>,
+    max_cited_sources: usize,
+) -> Vec<CitedSource> {
+    let mut cited_sources: Vec<CitedSource> = previous
+        .iter()
+        .filter(|source| !latest.iter().any(|l| l.url == source.url))
+        .cloned()
+        .collect();
+    cited_sources.extend(latest);
+
+    let excess = cited_sources.len().saturating_sub(max_cited_sources);
+    cited_sources.split_off(excess)
+}
+
+/// Folds the latest turn into the memory. When the rephraser model is unavailable, the question
+/// is appended to the summary as is, so that the memory still covers the turn.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn update_conversation_memory(
+    dependency: &Dependency,
+    rephraser_settings: &query_rephraser::QueryRephraserSettings,
+    settings: &ConversationMemorySettings,
+    memory: ConversationMemory,
+    turn: ConversationTurn,
+) -> Result<ConversationMemory, SearchError> {
+    let prompt = prepare_memory_prompt(&memory, &turn);
+    let completion =
+        query_rephraser::complete_prompt(dependency, rephraser_settings, &prompt).await;
+
+    let (summary, latest_entities) =
+        match completion.as_deref().ok().and_then(parse_memory_completion) {
+            Some(parsed) => parsed,
+            None => {
+                tracing::warn!("Failed to summarize conversation, appending the question instead");
+                let summary = format!("{} The user asked: {}", memory.summary, turn.query);
+                (summary.trim().to_string(), vec![])
+            }
+        };
+
+    Ok(ConversationMemory {
+        summary: tokenizer::truncate_to_tokens(
+            &rephraser_settings.model,
+            &summary,
+            settings.max_tokens,
+        )?,
+        entities: merge_entities(&memory.entities, latest_entities, settings.max_entities),
+        cited_sources: merge_cited_sources(
+            &memory.cited_sources,
+            turn.cited_sources,
+            settings.max_cited_sources,
+        ),
+        turns: memory.turns + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SourceType;
+    use std::collections::HashMap;
+
+    fn source(url: &str) -> Source {
+        Source {
+            url: url.to_string(),
+            title: format!("Title of {}", url),
+            description: String::new(),
+            source_type: SourceType::Url,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_cited_sources() {
+        let sources = vec![source("a"), source("b"), source("c")];
+        let cited = cited_sources("Metformin is safe [3]. It lowers glucose [1, 3].", &sources);
+
+        assert_eq!(
+            cited.iter().map(|s| s.url.as_str()).collect::<Vec<_>>(),
+            vec!["c", "a"]
+        );
+    }
+
+    #[test]
+    fn test_parse_memory_completion() {
+        let (summary, entities) = parse_memory_completion(
+            " The user asked about metformin in pregnancy.\n\nEntities: metformin; pregnancy",
+        )
+        .unwrap();
+
+        assert_eq!(summary, "The user asked about metformin in pregnancy.");
+        assert_eq!(entities, vec!["metformin", "pregnancy"]);
+        assert!(parse_memory_completion("Summary: ").is_none());
+    }
+
+    #[test]
+    fn test_merge_keeps_latest_within_limits() {
+        let entities = merge_entities(
+            &["Metformin".to_string(), "insulin".to_string()],
+            vec!["metformin".to_string(), "pregnancy".to_string()],
+            2,
+        );
+        assert_eq!(entities, vec!["metformin", "pregnancy"]);
+
+        let cited = merge_cited_sources(
+            &[
+                CitedSource {
+                    title: "A".to_string(),
+                    url: "a".to_string(),
+                },
+                CitedSource {
+                    title: "B".to_string(),
+                    url: "b".to_string(),
+                },
+            ],
+            vec![CitedSource {
+                title: "A".to_string(),
+                url: "a".to_string(),
+            }],
+            2,
+        );
+        assert_eq!(
+            cited.iter().map(|s| s.url.as_str()).collect::<Vec<_>>(),
+            vec!["b", "a"]
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::resilience::Dependency;
+use crate::search::SearchError;
+use crate::secrets::Secret;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrisisRule {
+    pub category: String,
+    /// Matched case-insensitively. They are compiled with the settings, so an invalid pattern
+    /// fails at startup.
+    #[serde(with = "crisis_patterns")]
+    pub patterns: Vec<Regex>,
+}
+
+mod crisis_patterns {
+    use regex::{Regex, RegexBuilder};
+    use serde::{de::Error, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(patterns: &[Regex], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(patterns.len()))?;
+        for pattern in patterns {
+            seq.serialize_element(pattern.as_str())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Regex>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|pattern| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| {
+                        D::Error::custom(format!("Invalid crisis pattern '{}': {}", pattern, e))
+                    })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrisisResponse {
+    pub locale: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrisisSettings {
+    pub enabled: bool,
+    pub rules: Vec<CrisisRule>,
+    pub classifier_url: Option<String>,
+    pub classifier_auth_token: Secret<String>,
+    pub classifier_label: String,
+    pub classifier_threshold: f64,
+    pub default_locale: String,
+    pub responses: Vec<CrisisResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CrisisDetectionSource {
+    Rule,
+    Classifier,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrisisDetection {
+    pub category: String,
+    pub source: CrisisDetectionSource,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CrisisClassifierInput {
+    pub inputs: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CrisisClassifierScore {
+    pub label: String,
+    pub score: f64,
+}
+
+pub fn match_crisis_rules(settings: &CrisisSettings, query: &str) -> Option<CrisisDetection> {
+    settings.rules.iter().find_map(|rule| {
+        let matched = rule.patterns.iter().any(|pattern| pattern.is_match(query));
+        matched.then(|| CrisisDetection {
+            category: rule.category.clone(),
+            source: CrisisDetectionSource::Rule,
+        })
+    })
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+async fn classify_crisis(
+    dependency: &Dependency,
+    settings: &CrisisSettings,
+    classifier_url: &str,
+    query: &str,
+) -> Result<Option<CrisisDetection>, SearchError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        HeaderName::from_bytes(b"Authorization")?,
+        HeaderValue::from_str(settings.classifier_auth_token.expose())?,
+    );
+    let classifier_input = CrisisClassifierInput {
+        inputs: query.to_string(),
+    };
+
+    let classifier_api_response: Vec<CrisisClassifierScore> = dependency
+        .call(true, || async {
+            let response = dependency
+                .client()
+                .post(classifier_url)
+                .json(&classifier_input)
+                .headers(headers.clone())
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.json().await?)
+        })
+        .await?;
+
+    let detection = classifier_api_response
+        .into_iter()
+        .find(|x| x.label == settings.classifier_label && x.score > settings.classifier_threshold)
+        .map(|x| CrisisDetection {
+            category: x.label,
+            source: CrisisDetectionSource::Classifier,
+        });
+    Ok(detection)
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn detect_crisis(
+    dependency: &Dependency,
+    settings: &CrisisSettings,
+    query: &str,
+) -> Result<Option<CrisisDetection>, SearchError> {
+    if !settings.enabled {
+        return Ok(None);
+    }
+    if let Some(detection) = match_crisis_rules(settings, query) {
+        return Ok(Some(detection));
+    }
+
+    match &settings.classifier_url {
+        Some(classifier_url) => classify_crisis(dependency, settings, classifier_url, query).await,
+        None => Ok(None),
+    }
+}
+
+/// Picks the response for the first language in an `Accept-Language` header that has one,
+/// matching the full locale (`en-GB`) before the language (`en`), and falls back to the default.
+pub fn crisis_response<'a>(settings: &'a CrisisSettings, accept_language: &str) -> &'a str {
+    let find = |locale: &str| {
+        settings
+            .responses
+            .iter()
+            .find(|r| r.locale.eq_ignore_ascii_case(locale))
+    };
+
+    accept_language
+        .split(',')
+        .filter_map(|tag| tag.split(';').next())
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty() && *tag != "*")
+        .find_map(|tag| find(tag).or_else(|| tag.split('-').next().and_then(find)))
+        .or_else(|| find(&settings.default_locale))
+        .map(|r| r.message.as_str())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::RegexBuilder;
+
+    fn settings() -> CrisisSettings {
+        CrisisSettings {
+            enabled: true,
+            rules: vec![CrisisRule {
+                category: String::from("suicidal_ideation"),
+                patterns: vec![RegexBuilder::new(r"\bkill(ing)? myself\b")
+                    .case_insensitive(true)
+                    .build()
+                    .unwrap()],
+            }],
+            classifier_url: None,
+            classifier_auth_token: Secret::new(String::new()),
+            classifier_label: String::from("crisis"),
+            classifier_threshold: 0.8,
+            default_locale: String::from("en"),
+            responses: vec![
+                CrisisResponse {
+                    locale: String::from("en"),
+                    message: String::from("en message"),
+                },
+                CrisisResponse {
+                    locale: String::from("en-GB"),
+                    message: String::from("en-GB message"),
+                },
+                CrisisResponse {
+                    locale: String::from("nl"),
+                    message: String::from("nl message"),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_match_crisis_rules() {
+        let settings = settings();
+
+        let detection = match_crisis_rules(&settings, "I keep thinking about Killing myself");
+        assert_eq!(detection.unwrap().category, "suicidal_ideation");
+        assert!(match_crisis_rules(&settings, "suicide rates in adolescents").is_none());
+    }
+
+    #[test]
+    fn test_parse_crisis_rule() {
+        let rule: CrisisRule = serde_json::from_value(serde_json::json!({
+            "category": "self_harm",
+            "patterns": [r"\bcut(ting)? myself\b"],
+        }))
+        .unwrap();
+        assert!(rule.patterns[0].is_match("I have been Cutting myself"));
+
+        let rule = serde_json::from_value::<CrisisRule>(serde_json::json!({
+            "category": "self_harm",
+            "patterns": [r"\bcut(ting myself"],
+        }));
+        assert!(rule.is_err());
+    }
+
+    #[test]
+    fn test_crisis_response_locale() {
+        let settings = settings();
+
+        assert_eq!(
+            crisis_response(&settings, "en-GB,en;q=0.9"),
+            "en-GB message"
+        );
+        assert_eq!(crisis_response(&settings, "nl-BE;q=0.8"), "nl message");
+        assert_eq!(crisis_response(&settings, "fr-FR, *"), "en message");
+        assert_eq!(crisis_response(&settings, ""), "en message");
+    }
+}
@@ -0,0 +1,161 @@
+use crate::llms::{split_sentences, tokenizer, PromptCompressionInput, PromptCompressionOutput};
+use crate::search::SearchError;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+const QUERY_OVERLAP_WEIGHT: f64 = 0.7;
+const EMBEDDING_SIMILARITY_WEIGHT: f64 = 0.3;
+
+const STOP_WORDS: &[&str] = &[
+    "about", "and", "are", "can", "does", "for", "from", "has", "have", "how", "into", "its",
+    "not", "the", "that", "their", "there", "these", "this", "those", "was", "were", "what",
+    "when", "which", "who", "why", "will", "with",
+];
+
+static SOURCE_MARKER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*(\[\d+\])\s*").expect("Invalid source marker regex"));
+
+#[derive(Debug)]
+struct CandidateSentence {
+    context_index: usize,
+    position: usize,
+    text: String,
+    tokens: usize,
+    score: f64,
+}
+
+fn terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() >= 3)
+        .map(|term| term.to_lowercase())
+        .filter(|term| !STOP_WORDS.contains(&term.as_str()))
+        .collect()
+}
+
+/// Compresses the context in-process when the prompt compression service is unavailable, by
+/// keeping the sentences that best match the query within `target_token` tokens of the model.
+/// `similarities` holds the embedding similarity of each context text to the query, when known.
+/// Selected sentences keep their source marker and original order, so that they can be cited.
+pub fn compress_extractively(
+    model: &str,
+    input: &PromptCompressionInput,
+    similarities: &[Option<f64>],
+) -> Result<PromptCompressionOutput, SearchError> {
+    let query_terms = terms(&input.query);
+    let mut markers = vec![];
+    let mut candidates = vec![];
+
+    for (context_index, context_text) in input.context_texts_list.iter().enumerate() {
+        let (marker, body) = match SOURCE_MARKER_REGEX.captures(context_text) {
+            Some(captures) => (captures[1].to_string(), &context_text[captures[0].len()..]),
+            None => (String::new(), context_text.as_str()),
+        };
+        markers.push(marker);
+
+        let similarity = similarities
+            .get(context_index)
+            .copied()
+            .flatten()
+            .unwrap_or(0.0);
+        let sentences = split_sentences(body)
+            .into_iter()
+            .map(|sentence| sentence.trim().to_string())
+            .filter(|sentence| !sentence.is_empty());
+
+        for (position, sentence) in sentences.enumerate() {
+            let overlap = match query_terms.is_empty() {
+                true => 0.0,
+                false => {
+                    terms(&sentence).intersection(&query_terms).count() as f64
+                        / query_terms.len() as f64
+                }
+            };
+            candidates.push(CandidateSentence {
+                context_index,
+                position,
+                tokens: tokenizer::count_tokens(model, &sentence)?,
+                text: sentence,
+                score: QUERY_OVERLAP_WEIGHT * overlap + EMBEDDING_SIMILARITY_WEIGHT * similarity,
+            });
+        }
+    }
+
+    // ties go to the higher ranked source and the earlier sentence
+    candidates.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then(a.context_index.cmp(&b.context_index))
+            .then(a.position.cmp(&b.position))
+    });
+
+    let mut remaining_tokens = input.target_token as usize;
+    let mut selected: Vec<CandidateSentence> = candidates
+        .into_iter()
+        .filter(|candidate| {
+            if candidate.tokens > remaining_tokens {
+                return false;
+            }
+            remaining_tokens -= candidate.tokens;
+            true
+        })
+        .collect();
+    selected.sort_by_key(|candidate| (candidate.context_index, candidate.position));
+
+    let mut contexts: Vec<String> = vec![];
+    let mut current_index = None;
+    for candidate in selected {
+        if current_index != Some(candidate.context_index) {
+            current_index = Some(candidate.context_index);
+            contexts.push(markers[candidate.context_index].clone());
+        }
+        if let Some(context) = contexts.last_mut() {
+            if !context.is_empty() {
+                context.push(' ');
+            }
+            context.push_str(&candidate.text);
+        }
+    }
+
+    Ok(PromptCompressionOutput {
+        compressed_prompt: contexts.join("\n\n"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(target_token: u16) -> PromptCompressionInput {
+        PromptCompressionInput {
+            query: String::from("Is metformin safe in pregnancy?"),
+            target_token,
+            context_texts_list: vec![
+                String::from("[1] Aspirin reduces fever. Metformin use in pregnancy appears safe."),
+                String::from("[2] The weather was sunny. Pregnancy outcomes were unchanged."),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_compress_extractively_keeps_markers_and_order() {
+        let output = compress_extractively("gpt-4o", &input(100), &[None, None]).unwrap();
+        assert_eq!(
+            output.compressed_prompt,
+            "[1] Aspirin reduces fever. Metformin use in pregnancy appears safe.\n\n\
+                [2] The weather was sunny. Pregnancy outcomes were unchanged."
+        );
+    }
+
+    #[test]
+    fn test_compress_extractively_respects_token_budget() {
+        let output = compress_extractively("gpt-4o", &input(15), &[None, Some(0.9)]).unwrap();
+        assert_eq!(
+            output.compressed_prompt,
+            "[1] Metformin use in pregnancy appears safe.\n\n\
+                [2] Pregnancy outcomes were unchanged."
+        );
+    }
+}
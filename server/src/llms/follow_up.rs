@@ -0,0 +1,123 @@
+use crate::llms::query_rephraser;
+use crate::rag::Source;
+use crate::resilience::Dependency;
+use crate::search::SearchError;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// the prompt only needs enough of each source to tell what it covers
+const MAX_SOURCE_CHARS: usize = 300;
+
+// numbering and bullets the model puts in front of each question
+static LIST_MARKER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*(?:\d+[.)]|[-*•])\s*").expect("Invalid list marker regex"));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FollowUpSettings {
+    pub enabled: bool,
+    /// Fewer questions than this are not worth suggesting
+    pub min_questions: usize,
+    pub max_questions: usize,
+}
+
+#[tracing::instrument(level = "info", skip(sources), ret)]
+fn prepare_follow_up_prompt(
+    settings: &FollowUpSettings,
+    query: &str,
+    answer: &str,
+    sources: &[Source],
+) -> String {
+    let sources = sources
+        .iter()
+        .enumerate()
+        .map(|(index, source)| {
+            let description: String = source.description.chars().take(MAX_SOURCE_CHARS).collect();
+            format!("[{}] {}: {}", index + 1, source.title, description)
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!("[INST] You suggest follow-up questions to a user of a scientific medical assistant. Given the question, the answer and the sources the answer is based on, write between {} and {} short questions the user could ask next. Each question must be answerable from the sources, must not repeat the question, and must stand on its own.\n\n---\n\nFollow the following format.\n\nQuestions: ${{one question per line}}\n\n---\n\nQuestion: {}\n\nAnswer: {}\n\nSources:\n{}\n\nQuestions: [/INST]",
+        settings.min_questions,
+        settings.max_questions,
+        query,
+        answer,
+        sources,
+    )
+}
+
+/// Parses one question per line out of the completion, dropping the list markers, the lines that
+/// are not questions and the repeats of the original question.
+fn parse_follow_up_questions(
+    settings: &FollowUpSettings,
+    query: &str,
+    completion: &str,
+) -> Vec<String> {
+    let mut questions: Vec<String> = vec![];
+    for line in completion.lines() {
+        let line = line.trim();
+        let line = line.strip_prefix("Questions:").unwrap_or(line);
+        let question = LIST_MARKER_REGEX.replace(line, "").trim().to_string();
+        if !question.ends_with('?')
+            || question.eq_ignore_ascii_case(query.trim())
+            || questions.iter().any(|q| q.eq_ignore_ascii_case(&question))
+        {
+            continue;
+        }
+        questions.push(question);
+    }
+    questions.truncate(settings.max_questions);
+    questions
+}
+
+/// Generates follow-up questions grounded in the answer and its sources. Returns no question when
+/// the model gives fewer than `min_questions`.
+#[tracing::instrument(level = "info", skip(sources), ret, err)]
+pub async fn generate_follow_up_questions(
+    dependency: &Dependency,
+    rephraser_settings: &query_rephraser::QueryRephraserSettings,
+    settings: &FollowUpSettings,
+    query: &str,
+    answer: &str,
+    sources: &[Source],
+) -> Result<Vec<String>, SearchError> {
+    let prompt = prepare_follow_up_prompt(settings, query, answer, sources);
+    let completion =
+        query_rephraser::complete_prompt(dependency, rephraser_settings, &prompt).await?;
+
+    let questions = parse_follow_up_questions(settings, query, &completion);
+    if questions.len() < settings.min_questions {
+        return Ok(vec![]);
+    }
+    Ok(questions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_follow_up_questions() {
+        let settings = FollowUpSettings {
+            enabled: true,
+            min_questions: 3,
+            max_questions: 3,
+        };
+        let completion = "1. Is metformin safe in pregnancy?\n\
+            2) What are the side effects of metformin?\n\
+            - what are the side effects of metformin?\n\
+            Here are some more questions:\n\
+            * How does metformin compare to insulin?\n\
+            4. Does metformin cause weight loss?";
+
+        assert_eq!(
+            parse_follow_up_questions(&settings, "Is metformin safe in pregnancy?", completion),
+            vec![
+                "What are the side effects of metformin?",
+                "How does metformin compare to insulin?",
+                "Does metformin cause weight loss?",
+            ]
+        );
+    }
+}
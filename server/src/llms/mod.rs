@@ -1,11 +1,31 @@
+pub use conversation_memory::*;
+pub use crisis::*;
+pub use extractive_compression::*;
+pub use follow_up::*;
 pub use models::*;
+pub use moderation::*;
 pub use prompt_compression::*;
 pub use query_rephraser::*;
+pub use redaction::*;
+pub use safety::*;
 pub use summarizer::*;
+pub use thread_title::*;
+pub use tokenizer::*;
 pub use toxicity::*;
+pub use verification::*;
 
+pub mod conversation_memory;
+pub mod crisis;
+pub mod extractive_compression;
+pub mod follow_up;
 pub mod models;
+pub mod moderation;
 pub mod prompt_compression;
 pub mod query_rephraser;
+pub mod redaction;
+pub mod safety;
 pub mod summarizer;
+pub mod thread_title;
+pub mod tokenizer;
 pub mod toxicity;
+pub mod verification;
@@ -14,4 +14,7 @@ pub struct OpenAISettings {
     pub api_url: String,
     pub model: String,
     pub api_key: Secret<String>,
+    pub target_context_tokens: usize,
+    pub max_context_tokens: usize,
+    pub max_history_tokens: usize,
 }
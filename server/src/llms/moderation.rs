@@ -0,0 +1,275 @@
+use crate::llms::{toxicity, LLMSettings};
+use crate::resilience::Dependency;
+use crate::search::SearchError;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Stop,
+    Redact,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationSettings {
+    pub enabled: bool,
+    pub window_size: usize,
+    pub action: ModerationAction,
+    pub redaction_text: String,
+    pub stop_text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlaggedSentence {
+    pub sentence: String,
+    pub toxicity_score: f64,
+    pub action: ModerationAction,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModerationOutcome {
+    pub checked_sentences: usize,
+    pub flagged_sentences: Vec<FlaggedSentence>,
+    /// Released without a score, because the classifier could not be reached
+    #[serde(default)]
+    pub unchecked_sentences: Vec<String>,
+    pub stopped: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ModerationStep {
+    Continue(String),
+    Stop(String),
+}
+
+/// Splits off the complete sentences at the start of `text`, keeping their trailing whitespace so
+/// that the released text can be streamed unchanged. Returns the sentences and the remainder.
+pub fn split_complete_sentences(text: &str) -> (Vec<String>, String) {
+    let mut sentences = vec![];
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let at_boundary = match c {
+            '\n' => true,
+            '.' | '!' | '?' => chars
+                .peek()
+                .map(|(_, next)| next.is_whitespace())
+                .unwrap_or(false),
+            _ => false,
+        };
+        if !at_boundary {
+            continue;
+        }
+
+        let mut end = index + c.len_utf8();
+        while let Some((next_index, next)) = chars.peek() {
+            if !next.is_whitespace() {
+                break;
+            }
+            end = next_index + next.len_utf8();
+            chars.next();
+        }
+        sentences.push(text[start..end].to_string());
+        start = end;
+    }
+
+    (sentences, text[start..].to_string())
+}
+
+/// Moderates the generated answer while it streams. Text is held back until its sentence is
+/// complete, then the sentence is scored together with the preceding sentences in the window.
+#[derive(Debug)]
+pub struct AnswerModerator {
+    dependency: Dependency,
+    llm_settings: LLMSettings,
+    settings: ModerationSettings,
+    pending: String,
+    window: VecDeque<String>,
+    outcome: ModerationOutcome,
+}
+
+impl AnswerModerator {
+    pub fn new(
+        dependency: Dependency,
+        llm_settings: LLMSettings,
+        settings: ModerationSettings,
+    ) -> Self {
+        AnswerModerator {
+            dependency,
+            llm_settings,
+            settings,
+            pending: String::new(),
+            window: VecDeque::new(),
+            outcome: ModerationOutcome::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    pub fn outcome(&self) -> &ModerationOutcome {
+        &self.outcome
+    }
+
+    pub async fn push(&mut self, text: &str) -> Result<ModerationStep, SearchError> {
+        if !self.settings.enabled || self.outcome.stopped {
+            return Ok(ModerationStep::Continue(text.to_string()));
+        }
+
+        self.pending.push_str(text);
+        let (sentences, remainder) = split_complete_sentences(&self.pending);
+        self.pending = remainder;
+
+        self.moderate_sentences(sentences).await
+    }
+
+    pub async fn finish(&mut self) -> Result<ModerationStep, SearchError> {
+        if !self.settings.enabled || self.outcome.stopped {
+            return Ok(ModerationStep::Continue(String::new()));
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        self.moderate_sentences(vec![pending]).await
+    }
+
+    async fn moderate_sentences(
+        &mut self,
+        sentences: Vec<String>,
+    ) -> Result<ModerationStep, SearchError> {
+        let mut released = String::new();
+
+        for sentence in sentences {
+            let trimmed = sentence.trim();
+            if trimmed.is_empty() {
+                released.push_str(&sentence);
+                continue;
+            }
+
+            self.window.push_back(trimmed.to_string());
+            while self.window.len() > self.settings.window_size.max(1) {
+                self.window.pop_front();
+            }
+            let window_text = self.window.iter().cloned().collect::<Vec<_>>().join(" ");
+
+            // the query check fails open as well, so an unavailable classifier does not block answers
+            let toxicity_score = match toxicity::predict_toxicity_score(
+                &self.dependency,
+                &self.llm_settings,
+                toxicity::ToxicityInput {
+                    inputs: window_text,
+                },
+            )
+            .await
+            {
+                Ok(score) => score,
+                Err(e) => {
+                    tracing::warn!("Released an answer sentence without moderation: {}", e);
+                    self.outcome.unchecked_sentences.push(trimmed.to_string());
+                    released.push_str(&sentence);
+                    continue;
+                }
+            };
+            self.outcome.checked_sentences += 1;
+
+            if toxicity_score <= self.llm_settings.toxicity_threshold {
+                released.push_str(&sentence);
+                continue;
+            }
+
+            self.outcome.flagged_sentences.push(FlaggedSentence {
+                sentence: trimmed.to_string(),
+                toxicity_score,
+                action: self.settings.action.clone(),
+            });
+            // keep the flagged sentence out of the window so it does not taint the next ones
+            self.window.pop_back();
+
+            match self.settings.action {
+                ModerationAction::Stop => {
+                    self.outcome.stopped = true;
+                    self.pending.clear();
+                    released.push_str(&self.settings.stop_text);
+                    return Ok(ModerationStep::Stop(released));
+                }
+                ModerationAction::Redact => {
+                    released.push_str(&sentence.replace(trimmed, &self.settings.redaction_text));
+                }
+            }
+        }
+
+        Ok(ModerationStep::Continue(released))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resilience::DependencySettings;
+    use crate::secrets::Secret;
+
+    #[test]
+    fn test_split_complete_sentences() {
+        let (sentences, remainder) =
+            split_complete_sentences("Aspirin is 2.5 mg. It helps [1]!\nThis one is not");
+        assert_eq!(sentences, vec!["Aspirin is 2.5 mg. ", "It helps [1]!\n"]);
+        assert_eq!(remainder, "This one is not");
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_classifier_leaves_sentences_unchecked() {
+        let dependency = Dependency::new(
+            "toxicity",
+            &DependencySettings {
+                connect_timeout_ms: 100,
+                read_timeout_ms: 100,
+                timeout_ms: 100,
+                max_retries: 0,
+                retry_base_delay_ms: 0,
+                failure_threshold: 5,
+                reset_timeout_ms: 60_000,
+            },
+        )
+        .unwrap();
+        let llm_settings = LLMSettings {
+            prompt_compression_url: String::new(),
+            // nothing listens on the discard port
+            toxicity_url: String::from("http://127.0.0.1:9"),
+            toxicity_threshold: 0.5,
+            toxicity_auth_token: Secret::new(String::new()),
+        };
+        let settings = ModerationSettings {
+            enabled: true,
+            window_size: 2,
+            action: ModerationAction::Stop,
+            redaction_text: String::from("[redacted]"),
+            stop_text: String::from("[stopped]"),
+        };
+        let mut moderator = AnswerModerator::new(dependency, llm_settings, settings);
+
+        let step = moderator.push("Aspirin helps [1]. It is ").await.unwrap();
+        assert_eq!(
+            step,
+            ModerationStep::Continue("Aspirin helps [1]. ".to_string())
+        );
+        let step = moderator.finish().await.unwrap();
+        assert_eq!(step, ModerationStep::Continue("It is ".to_string()));
+
+        let outcome = moderator.outcome();
+        assert_eq!(outcome.checked_sentences, 0);
+        assert_eq!(
+            outcome.unchecked_sentences,
+            vec!["Aspirin helps [1].", "It is"]
+        );
+        assert!(!outcome.stopped);
+    }
+
+    #[test]
+    fn test_split_complete_sentences_waits_for_whitespace() {
+        let (sentences, remainder) = split_complete_sentences("Ends with a period.");
+        assert!(sentences.is_empty());
+        assert_eq!(remainder, "Ends with a period.");
+    }
+}
@@ -1,9 +1,9 @@
 use crate::llms::LLMSettings;
+use crate::resilience::Dependency;
 use crate::search::SearchError;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PromptCompressionInput {
     pub query: String,
     pub target_token: u16,
@@ -22,17 +22,25 @@ pub struct PromptCompressionAPIResponse {
 
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn compress(
+    dependency: &Dependency,
     llm_settings: &LLMSettings,
     prompt_compression_input: PromptCompressionInput,
 ) -> Result<PromptCompressionOutput, SearchError> {
-    let client = Client::new();
-    let response = client
-        .post(llm_settings.prompt_compression_url.as_str())
-        .json(&prompt_compression_input)
-        .send()
-        .await?;
+    let prompt_compression_response = dependency
+        .call(true, || async {
+            let response = dependency
+                .client()
+                .post(llm_settings.prompt_compression_url.as_str())
+                .json(&prompt_compression_input)
+                .send()
+                .await?;
 
-    let prompt_compression_response = response.json::<PromptCompressionAPIResponse>().await?;
+            if !response.status().is_success() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.json::<PromptCompressionAPIResponse>().await?)
+        })
+        .await?;
 
     Ok(prompt_compression_response.response)
 }
@@ -1,9 +1,7 @@
+use crate::resilience::Dependency;
 use crate::search::SearchError;
 use crate::secrets::Secret;
-use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue},
-    Client,
-};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +28,8 @@ pub struct QueryResult {
 pub struct QueryRephraserInput {
     pub query: String,
     pub previous_context: Vec<QueryResult>,
+    /// Memory of the earlier turns of the thread, empty when there is none
+    pub conversation_memory: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,47 +55,76 @@ pub struct QueryRephraserOutput {
 #[tracing::instrument(level = "info", ret)]
 fn prepare_rephrase_query_prompt(query_rephraser_input: &QueryRephraserInput) -> String {
     "[INST] Rephrase the input text based on the context and the final sentence. So that it can be understood without the context. Return the rephrased question only\n\n---\n\nFollow the following format.\n\nContext: contains the chat history\n\nQuestion: ${question}\n\nReasoning: Let's think step by step in order to ${produce the answer}. We ...\n\nAnswer: Given a chat history and the latest user question, which might reference the context from the chat history, formulate a standalone question that can be understood from the history without needing the chat history. DO NOT ANSWER THE QUESTION - just reformulate it and return the rephrased question only \n\n---\n\nContext: ".to_string()
+        + query_rephraser_input.conversation_memory.as_str()
+        + if query_rephraser_input.conversation_memory.is_empty() { "" } else { "\n" }
         + query_rephraser_input.previous_context.iter().map(|x| format!("{}: {}", x.query, x.result)).collect::<Vec<String>>().join("\n").as_str()
         + "\n\nQuestion: "
         + query_rephraser_input.query.as_str()
         + "\n\nReasoning: Let's think step by step in order to...\n\nAnswer: [/INST]"
 }
 
+/// Sends the prompt to the completion API of the rephraser model and returns the generated text.
 #[tracing::instrument(level = "info", ret, err)]
-pub async fn rephrase_query(
+pub async fn complete_prompt(
+    dependency: &Dependency,
     settings: &QueryRephraserSettings,
-    query_rephraser_input: &QueryRephraserInput,
-) -> Result<QueryRephraserOutput, SearchError> {
-    let client = Client::new();
+    prompt: &str,
+) -> Result<String, SearchError> {
     let mut headers = HeaderMap::new();
     headers.insert(
         HeaderName::from_bytes(b"Authorization")?,
         HeaderValue::from_str(settings.api_key.expose())?,
     );
 
-    let prompt = prepare_rephrase_query_prompt(query_rephraser_input);
+    let request_body = serde_json::json!({
+        "model": settings.model,
+        "prompt": prompt,
+        "max_tokens": settings.max_tokens,
+        "temperature": settings.temperature,
+        "top_p": settings.top_p,
+        "top_k": settings.top_k,
+        "repetition_penalty": settings.repetition_penalty,
+        "n": settings.n,
+        "stop": settings.stop
+    });
 
-    let response = client
-        .post(&settings.api_url)
-        .json(&serde_json::json!({
-            "model": settings.model,
-            "prompt": prompt,
-            "max_tokens": settings.max_tokens,
-            "temperature": settings.temperature,
-            "top_p": settings.top_p,
-            "top_k": settings.top_k,
-            "repetition_penalty": settings.repetition_penalty,
-            "n": settings.n,
-            "stop": settings.stop
-        }))
-        .headers(headers)
-        .send()
+    let response_bytes = dependency
+        .call(true, || async {
+            let response = dependency
+                .client()
+                .post(&settings.api_url)
+                .json(&request_body)
+                .headers(headers.clone())
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.bytes().await?)
+        })
         .await?;
 
-    let response_body =
-        serde_json::from_slice::<QueryRephraserAPIResponse>(&response.bytes().await?)?;
+    let response_body = serde_json::from_slice::<QueryRephraserAPIResponse>(&response_bytes)?;
+    let choice = response_body
+        .output
+        .choices
+        .into_iter()
+        .next()
+        .ok_or_else(|| SearchError::Other("Completion returned no choices".to_string()))?;
+
+    Ok(choice.text.trim().to_string())
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn rephrase_query(
+    dependency: &Dependency,
+    settings: &QueryRephraserSettings,
+    query_rephraser_input: &QueryRephraserInput,
+) -> Result<QueryRephraserOutput, SearchError> {
+    let prompt = prepare_rephrase_query_prompt(query_rephraser_input);
 
     Ok(QueryRephraserOutput {
-        rephrased_query: response_body.output.choices[0].text.trim().to_string(),
+        rephrased_query: complete_prompt(dependency, settings, &prompt).await?,
     })
 }
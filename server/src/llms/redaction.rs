@@ -0,0 +1,133 @@
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StoredQuery {
+    Original,
+    Redacted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionSettings {
+    pub enabled: bool,
+    pub stored_query: StoredQuery,
+}
+
+const MONTH: &str = r"(?:jan|feb|mar|apr|may|jun|jul|aug|sep|sept|oct|nov|dec)[a-z]*\.?";
+
+// Each rule replaces its `pii` group, or the whole match when there is none, with the placeholder.
+// Rules run in order, so the more specific ones come first.
+static REDACTION_RULES: Lazy<Vec<(Regex, &'static str)>> = Lazy::new(|| {
+    let date = format!(
+        r"(?:\d{{1,2}}[/.-]\d{{1,2}}[/.-]\d{{2,4}}|\d{{4}}-\d{{2}}-\d{{2}}|{MONTH}\s+\d{{1,2}},?\s+\d{{4}}|\d{{1,2}}\s+{MONTH}\s+\d{{4}})"
+    );
+    let rules = vec![
+        (
+            r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}".to_string(),
+            "[EMAIL]",
+        ),
+        (
+            r"(?i)\b(?:mrn|medical record(?:\s+number|\s+no\.?)?|patient\s+id|hospital\s+number)[\s:#]*(?P<pii>[a-z0-9-]*\d[a-z0-9-]*)".to_string(),
+            "[MRN]",
+        ),
+        (
+            format!(r"(?i)\b(?:dob|d\.o\.b\.?|date\s+of\s+birth|born(?:\s+on)?)[\s:]*(?P<pii>{date})"),
+            "[DATE_OF_BIRTH]",
+        ),
+        (
+            r"\+\d{1,3}[\s.-]?(?:\(?\d{1,4}\)?[\s.-]?){2,4}\d{2,4}\b".to_string(),
+            "[PHONE]",
+        ),
+        (
+            r"(?:\(\d{3}\)\s?|\b\d{3}[\s.-])\d{3}[\s.-]\d{4}\b".to_string(),
+            "[PHONE]",
+        ),
+        (
+            r"\b\d{1,5}\s+(?:[A-Z][a-z]+\s+){1,3}(?:Street|St|Avenue|Ave|Road|Rd|Boulevard|Blvd|Lane|Ln|Drive|Dr|Court|Ct|Way|Place|Pl|Terrace|Square|Sq)\b\.?".to_string(),
+            "[ADDRESS]",
+        ),
+        (
+            r"\b(?:Mr|Mrs|Ms|Miss|Mx)\.?\s+(?P<pii>[A-Z][a-z]+(?:\s+[A-Z][a-z]+)?)".to_string(),
+            "[NAME]",
+        ),
+        (
+            r"\b(?i:patient|pt\.?|name(?:d|\s+is)?)[\s:]+(?P<pii>[A-Z][a-z]+\s+[A-Z][a-z]+)".to_string(),
+            "[NAME]",
+        ),
+    ];
+
+    rules
+        .into_iter()
+        .map(|(pattern, placeholder)| {
+            (
+                Regex::new(&pattern).expect("Invalid redaction regex"),
+                placeholder,
+            )
+        })
+        .collect()
+});
+
+/// Replaces names, dates of birth, MRNs, phone numbers, emails and addresses in the text with
+/// typed placeholders such as `[NAME]`, so that it can be sent to third-party services.
+pub fn redact_pii(settings: &RedactionSettings, text: &str) -> String {
+    if !settings.enabled {
+        return text.to_string();
+    }
+
+    REDACTION_RULES
+        .iter()
+        .fold(text.to_string(), |text, (regex, placeholder)| {
+            regex
+                .replace_all(&text, |captures: &Captures| {
+                    let whole = captures.get(0).expect("Match without group 0");
+                    match captures.name("pii") {
+                        Some(pii) => format!(
+                            "{}{}{}",
+                            &whole.as_str()[..pii.start() - whole.start()],
+                            placeholder,
+                            &whole.as_str()[pii.end() - whole.start()..]
+                        ),
+                        None => placeholder.to_string(),
+                    }
+                })
+                .into_owned()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> RedactionSettings {
+        RedactionSettings {
+            enabled: true,
+            stored_query: StoredQuery::Redacted,
+        }
+    }
+
+    #[test]
+    fn test_redact_pii() {
+        let redacted = redact_pii(
+            &settings(),
+            "Mrs. Jane Doe, DOB 03/04/1956, MRN: A123456, lives at 12 Baker Street, \
+                call (555) 123-4567 or jane.doe@example.com. Is metformin safe?",
+        );
+        assert_eq!(
+            redacted,
+            "Mrs. [NAME], DOB [DATE_OF_BIRTH], MRN: [MRN], lives at [ADDRESS], \
+                call [PHONE] or [EMAIL]. Is metformin safe?"
+        );
+    }
+
+    #[test]
+    fn test_redact_pii_keeps_clinical_text() {
+        let query = "Patient with 1000-2000 mg daily dose since 2019-05-01, is 120/80 normal?";
+        assert_eq!(redact_pii(&settings(), query), query);
+        assert_eq!(
+            redact_pii(&settings(), "patient John Smith born on March 3, 1954"),
+            "patient [NAME] born on [DATE_OF_BIRTH]"
+        );
+    }
+}
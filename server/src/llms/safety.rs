@@ -0,0 +1,153 @@
+use crate::llms::{toxicity, LLMSettings};
+use crate::resilience::Dependency;
+use crate::search::SearchError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Actions ordered by severity, so that the most severe one wins when several labels fire.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SafetyAction {
+    Warn,
+    SafeCompletion,
+    Block,
+}
+
+impl SafetyAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SafetyAction::Warn => "warn",
+            SafetyAction::SafeCompletion => "safe_completion",
+            SafetyAction::Block => "block",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyRule {
+    pub label: String,
+    pub threshold: f64,
+    pub action: SafetyAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyPolicySettings {
+    pub safe_completion_template: String,
+    pub rules: Vec<SafetyRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyViolation {
+    pub label: String,
+    pub score: f64,
+    pub threshold: f64,
+    pub action: SafetyAction,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SafetyAssessment {
+    pub scores: HashMap<String, f64>,
+    pub violations: Vec<SafetyViolation>,
+}
+
+impl SafetyAssessment {
+    pub fn action(&self) -> Option<SafetyAction> {
+        self.violations.iter().map(|v| v.action.clone()).max()
+    }
+}
+
+pub fn evaluate_safety_policy(
+    policy: &SafetyPolicySettings,
+    scores: HashMap<String, f64>,
+) -> SafetyAssessment {
+    let violations = policy
+        .rules
+        .iter()
+        .filter_map(|rule| {
+            let score = *scores.get(&rule.label)?;
+            if score <= rule.threshold {
+                return None;
+            }
+            Some(SafetyViolation {
+                label: rule.label.clone(),
+                score,
+                threshold: rule.threshold,
+                action: rule.action.clone(),
+            })
+        })
+        .collect();
+
+    SafetyAssessment { scores, violations }
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn assess_query_safety(
+    dependency: &Dependency,
+    llm_settings: &LLMSettings,
+    policy: &SafetyPolicySettings,
+    query: &str,
+) -> Result<SafetyAssessment, SearchError> {
+    let scores = toxicity::predict_toxicity_scores(
+        dependency,
+        llm_settings,
+        toxicity::ToxicityInput {
+            inputs: query.to_string(),
+        },
+    )
+    .await?;
+
+    Ok(evaluate_safety_policy(policy, scores))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> SafetyPolicySettings {
+        SafetyPolicySettings {
+            safe_completion_template: String::from("Safe answer"),
+            rules: vec![
+                SafetyRule {
+                    label: String::from("insult"),
+                    threshold: 0.5,
+                    action: SafetyAction::Warn,
+                },
+                SafetyRule {
+                    label: String::from("self_harm"),
+                    threshold: 0.5,
+                    action: SafetyAction::SafeCompletion,
+                },
+                SafetyRule {
+                    label: String::from("threat"),
+                    threshold: 0.7,
+                    action: SafetyAction::Block,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_most_severe_action_wins() {
+        let scores = HashMap::from([
+            (String::from("insult"), 0.9),
+            (String::from("self_harm"), 0.6),
+            (String::from("threat"), 0.2),
+        ]);
+        let assessment = evaluate_safety_policy(&policy(), scores);
+
+        assert_eq!(assessment.violations.len(), 2);
+        assert_eq!(assessment.action(), Some(SafetyAction::SafeCompletion));
+    }
+
+    #[test]
+    fn test_no_violation_below_thresholds() {
+        let scores = HashMap::from([
+            (String::from("insult"), 0.1),
+            (String::from("unknown_label"), 0.99),
+        ]);
+        let assessment = evaluate_safety_policy(&policy(), scores);
+
+        assert!(assessment.violations.is_empty());
+        assert_eq!(assessment.action(), None);
+    }
+}
@@ -1,12 +1,20 @@
-use crate::llms::OpenAISettings;
+use crate::llms::{moderation, tokenizer, OpenAISettings};
+use crate::resilience::Dependency;
 use crate::search::{api_models, SearchError};
 use futures::StreamExt;
+use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
 
+// every chat message is wrapped in a few tokens for its role and delimiters
+const TOKENS_PER_MESSAGE: usize = 4;
+
+// citation markers of earlier answers refer to sources the summarizer no longer sees
+static CITATION_MARKER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\s*\[\d+(?:\s*,\s*\d+)*\]").expect("Invalid citation marker regex"));
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SummarizerSettings {
     pub api_url: String,
@@ -14,6 +22,11 @@ pub struct SummarizerSettings {
     pub max_new_tokens: u16,
     pub temperature: f32,
     pub top_p: f32,
+    pub target_context_tokens: usize,
+    pub max_context_tokens: usize,
+    /// Share of the context window kept free when tiktoken does not know the model, as its
+    /// tokens are then counted with `cl100k_base`
+    pub tokenizer_margin: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,6 +47,16 @@ struct SummarizerAPIInput {
 pub struct SummarizerInput {
     pub query: String,
     pub retrieved_result: String,
+    /// Memory of the earlier turns of the thread, empty when there is none
+    pub conversation_memory: String,
+    /// Earlier questions and answers of the thread, oldest first
+    pub chat_history: Vec<ChatTurn>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatTurn {
+    pub query: String,
+    pub answer: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -48,6 +71,16 @@ pub struct SummarizerStreamOutput {
     pub generated_text: Option<String>,
 }
 
+fn conversation_memory_section(summarizer_input: &SummarizerInput) -> String {
+    match summarizer_input.conversation_memory.is_empty() {
+        true => String::new(),
+        false => format!(
+            "Earlier in the conversation: {}\n\n",
+            summarizer_input.conversation_memory
+        ),
+    }
+}
+
 #[tracing::instrument(level = "info", ret)]
 fn prepare_llm_context_string(
     settings: &SummarizerSettings,
@@ -58,7 +91,9 @@ fn prepare_llm_context_string(
         The solution draft follows the format \"Thought, Action, Action Input, Observation\", where the 'Thought' statements describe a reasoning sequence. The rest of the text is information obtained to complement the reasoning sequence, and it is 100% accurate OR you can use a single \"Final Answer\" format.
         Your task is to write an answer to the question based on the solution draft, and the following guidelines:
         The text should have an educative and assistant-like tone, be accurate, follow the same reasoning sequence than the solution draft and explain how any conclusion is reached.
-        Question: {}\n\nSolution draft: {}\n\nAnswer:", summarizer_input.query, summarizer_input.retrieved_result),
+        The solution draft marks each source with its number in square brackets. End every sentence that relies on a source with the numbers of the sources it relies on, e.g. [1] or [2, 3].
+        When a summary of the earlier conversation is given, use it to understand the question, but only rely on the solution draft for the answer.
+        {}Question: {}\n\nSolution draft: {}\n\nAnswer:", conversation_memory_section(&summarizer_input), summarizer_input.query, summarizer_input.retrieved_result),
         parameters: SummarizerParams {
             model: Some(settings.model.clone()),
             max_new_tokens: Some(settings.max_new_tokens),
@@ -68,28 +103,96 @@ fn prepare_llm_context_string(
     }
 }
 
+/// Tokens the LLM prompt takes besides the retrieved context, including the generated answer.
+pub fn llm_prompt_tokens(settings: &SummarizerSettings, query: &str) -> Result<usize, SearchError> {
+    let prompt = prepare_llm_context_string(
+        settings,
+        SummarizerInput {
+            query: query.to_string(),
+            retrieved_result: String::new(),
+            conversation_memory: String::new(),
+            chat_history: vec![],
+        },
+    );
+
+    Ok(
+        tokenizer::count_tokens(&settings.model, &prompt.inputs)?
+            + settings.max_new_tokens as usize,
+    )
+}
+
+/// Streams text released by the moderator to the client and appends it to the stored result.
+#[tracing::instrument(level = "info", skip(update_processor, tx), ret, err)]
+async fn send_answer_text(
+    released: String,
+    update_processor: &api_models::UpdateResultProcessor,
+    tx: &Sender<api_models::SearchStreamEvent>,
+    buffer: &mut String,
+    answer: &mut String,
+) -> Result<(), SearchError> {
+    if released.is_empty() {
+        return Ok(());
+    }
+
+    let mut search = update_processor
+        .process(released.clone())
+        .await
+        .map_err(|e| SearchError::Other(format!("Failed to process update: {}", e)))?;
+
+    answer.push_str(&released);
+    buffer.push_str(&released);
+    search.result = buffer.clone();
+    let tx_response = tx
+        .send(
+            api_models::SearchByIdResponse {
+                search,
+                sources: vec![],
+                citations: None,
+                facets: None,
+            }
+            .into(),
+        )
+        .await;
+
+    if tx_response.is_ok() {
+        buffer.clear();
+    }
+
+    Ok(())
+}
+
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn generate_text_with_llm(
+    dependency: &Dependency,
     settings: SummarizerSettings,
     summarizer_input: SummarizerInput,
     update_processor: api_models::UpdateResultProcessor,
-    tx: Sender<api_models::SearchByIdResponse>,
-) -> Result<(), SearchError> {
+    moderator: &mut moderation::AnswerModerator,
+    tx: Sender<api_models::SearchStreamEvent>,
+) -> Result<String, SearchError> {
     let summarizer_input = prepare_llm_context_string(&settings, summarizer_input);
-    let client = Client::new();
 
-    let response = client
-        .post(settings.api_url.as_str())
-        .json(&summarizer_input)
-        .send()
+    // generation is not retried, and the stream itself is bounded by the read timeout
+    let response = dependency
+        .call(false, || async {
+            let response = dependency
+                .client()
+                .post(settings.api_url.as_str())
+                .json(&summarizer_input)
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response)
+        })
         .await?;
 
     // stream the response
-    if !response.status().is_success() {
-        response.error_for_status_ref()?;
-    }
     let mut stream = response.bytes_stream();
     let mut buffer = String::new();
+    let mut answer = String::new();
 
     while let Some(chunk) = stream.next().await {
         // remove `data` from the start of the chunk and `\n\n` from the end
@@ -99,27 +202,29 @@ pub async fn generate_text_with_llm(
         let summarizer_api_response = serde_json::from_slice::<SummarizerStreamOutput>(chunk)?;
 
         if !summarizer_api_response.token.special {
-            let mut search = update_processor
-                .process(summarizer_api_response.token.text.clone())
-                .await
-                .map_err(|e| SearchError::Other(format!("Failed to process update: {}", e)))?;
-
-            buffer.push_str(&summarizer_api_response.token.text);
-            search.result = buffer.clone();
-            let tx_response = tx
-                .send(api_models::SearchByIdResponse {
-                    search,
-                    sources: vec![],
-                })
-                .await;
-
-            if tx_response.is_ok() {
-                buffer.clear();
+            match moderator.push(&summarizer_api_response.token.text).await? {
+                moderation::ModerationStep::Continue(released) => {
+                    send_answer_text(released, &update_processor, &tx, &mut buffer, &mut answer)
+                        .await?;
+                }
+                moderation::ModerationStep::Stop(released) => {
+                    send_answer_text(released, &update_processor, &tx, &mut buffer, &mut answer)
+                        .await?;
+                    return Ok(answer);
+                }
             }
         }
     }
 
-    Ok(())
+    // a flagged last sentence releases the stop text in its place
+    match moderator.finish().await? {
+        moderation::ModerationStep::Continue(released)
+        | moderation::ModerationStep::Stop(released) => {
+            send_answer_text(released, &update_processor, &tx, &mut buffer, &mut answer).await?;
+        }
+    }
+
+    Ok(answer)
 }
 
 #[tracing::instrument(level = "info", ret)]
@@ -131,61 +236,136 @@ fn prepare_openai_input(
     The solution draft follows the format \"Thought, Action, Action Input, Observation\", where the 'Thought' statements describe a reasoning sequence. The rest of the text is information obtained to complement the reasoning sequence, and it is 100% accurate OR you can use a single \"Final Answer\" format.
     Your task is to write an answer to the question based on the solution draft, and the following guidelines:
     The text should have an educative and assistant-like tone, be accurate, follow the same reasoning sequence than the solution draft and explain how any conclusion is reached.
+    The solution draft marks each source with its number in square brackets. End every sentence that relies on a source with the numbers of the sources it relies on, e.g. [1] or [2, 3].
+    When a summary of the earlier conversation is given, use it to understand the question, but only rely on the solution draft for the answer.
     Question: {}\n\nSolution draft: {}\n\nAnswer: ";
 
     let user_input = format!(
-        "Question: {}\n\nSolution draft: {}",
-        summarizer_input.query, summarizer_input.retrieved_result
+        "{}Question: {}\n\nSolution draft: {}",
+        conversation_memory_section(&summarizer_input),
+        summarizer_input.query,
+        summarizer_input.retrieved_result
     );
 
+    let mut messages = vec![serde_json::json!({
+        "role": "system",
+        "content": system_role
+    })];
+    for turn in summarizer_input.chat_history {
+        messages.push(serde_json::json!({
+            "role": "user",
+            "content": turn.query
+        }));
+        messages.push(serde_json::json!({
+            "role": "assistant",
+            "content": turn.answer
+        }));
+    }
+    messages.push(serde_json::json!({
+        "role": "user",
+        "content": user_input
+    }));
+
     serde_json::json!({
         "model": settings.model,
         "stream": true,
-        "messages": [
-            {
-                "role": "system",
-                "content": system_role
-            },
-            {
-                "role": "user",
-                "content": user_input
-            }
-        ]
+        "messages": messages
+    })
+}
+
+/// Keeps the most recent turns of the chat history that fit in `max_history_tokens`.
+pub fn select_chat_history(
+    settings: &OpenAISettings,
+    chat_history: Vec<ChatTurn>,
+) -> Result<Vec<ChatTurn>, SearchError> {
+    let mut remaining_tokens = settings.max_history_tokens;
+    let mut selected = vec![];
+
+    for turn in chat_history.into_iter().rev() {
+        let turn = ChatTurn {
+            answer: CITATION_MARKER_REGEX
+                .replace_all(&turn.answer, "")
+                .into_owned(),
+            ..turn
+        };
+        let tokens = tokenizer::count_tokens(&settings.model, &turn.query)?
+            + tokenizer::count_tokens(&settings.model, &turn.answer)?
+            + 2 * TOKENS_PER_MESSAGE;
+        if tokens > remaining_tokens {
+            break;
+        }
+        remaining_tokens -= tokens;
+        selected.push(turn);
+    }
+    selected.reverse();
+
+    Ok(selected)
+}
+
+/// Tokens the OpenAI prompt takes besides the retrieved context and the chat history.
+pub fn openai_prompt_tokens(settings: &OpenAISettings, query: &str) -> Result<usize, SearchError> {
+    let input = prepare_openai_input(
+        settings,
+        SummarizerInput {
+            query: query.to_string(),
+            retrieved_result: String::new(),
+            conversation_memory: String::new(),
+            chat_history: vec![],
+        },
+    );
+    let messages = input["messages"].as_array().cloned().unwrap_or_default();
+
+    messages.iter().try_fold(0, |tokens, message| {
+        let content = message["content"].as_str().unwrap_or_default();
+        Ok(tokens + tokenizer::count_tokens(&settings.model, content)? + TOKENS_PER_MESSAGE)
     })
 }
 
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn generate_text_with_openai(
+    dependency: &Dependency,
     settings: OpenAISettings,
     summarizer_input: SummarizerInput,
     update_processor: api_models::UpdateResultProcessor,
     stream_regex: Regex,
-    tx: Sender<api_models::SearchByIdResponse>,
-) -> Result<(), SearchError> {
+    moderator: &mut moderation::AnswerModerator,
+    tx: Sender<api_models::SearchStreamEvent>,
+) -> Result<String, SearchError> {
     let mut headers = HeaderMap::new();
     headers.insert(
         HeaderName::from_bytes(b"Authorization")?,
         HeaderValue::from_str(settings.api_key.expose())?,
     );
 
-    let client = Client::new();
+    let summarizer_input = SummarizerInput {
+        chat_history: select_chat_history(&settings, summarizer_input.chat_history)?,
+        ..summarizer_input
+    };
     let summarizer_input = prepare_openai_input(&settings, summarizer_input);
 
-    let response = client
-        .post(settings.api_url.as_str())
-        .json(&summarizer_input)
-        .headers(headers)
-        .send()
+    // generation is not retried, and the stream itself is bounded by the read timeout
+    let response = dependency
+        .call(false, || async {
+            let response = dependency
+                .client()
+                .post(settings.api_url.as_str())
+                .json(&summarizer_input)
+                .headers(headers.clone())
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response)
+        })
         .await?;
 
     // stream the response
-    if !response.status().is_success() {
-        response.error_for_status_ref()?;
-    }
-
     let mut stream = response.bytes_stream();
     let mut stream_data = String::new();
     let mut buffer = String::new();
+    let mut answer = String::new();
 
     while let Some(chunk) = stream.next().await {
         let chunk = chunk?;
@@ -212,24 +392,94 @@ pub async fn generate_text_with_openai(
             stream_data = stream_data.split_off(last_index);
         }
 
-        let mut search = update_processor
-            .process(parsed_chunk.clone())
-            .await
-            .map_err(|e| SearchError::Other(format!("Failed to process update: {}", e)))?;
+        match moderator.push(&parsed_chunk).await? {
+            moderation::ModerationStep::Continue(released) => {
+                send_answer_text(released, &update_processor, &tx, &mut buffer, &mut answer)
+                    .await?;
+            }
+            moderation::ModerationStep::Stop(released) => {
+                send_answer_text(released, &update_processor, &tx, &mut buffer, &mut answer)
+                    .await?;
+                return Ok(answer);
+            }
+        }
+    }
+
+    // a flagged last sentence releases the stop text in its place
+    match moderator.finish().await? {
+        moderation::ModerationStep::Continue(released)
+        | moderation::ModerationStep::Stop(released) => {
+            send_answer_text(released, &update_processor, &tx, &mut buffer, &mut answer).await?;
+        }
+    }
 
-        buffer.push_str(&parsed_chunk);
-        search.result = buffer.clone();
-        let tx_response = tx
-            .send(api_models::SearchByIdResponse {
-                search,
-                sources: vec![],
-            })
-            .await;
+    Ok(answer)
+}
 
-        if tx_response.is_ok() {
-            buffer.clear();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::Secret;
+
+    fn settings(max_history_tokens: usize) -> OpenAISettings {
+        OpenAISettings {
+            api_url: String::new(),
+            model: String::from("gpt-4o"),
+            api_key: Secret::new(String::new()),
+            target_context_tokens: 300,
+            max_context_tokens: 128000,
+            max_history_tokens,
         }
     }
 
-    Ok(())
+    fn turn(query: &str, answer: &str) -> ChatTurn {
+        ChatTurn {
+            query: query.to_string(),
+            answer: answer.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_select_chat_history_keeps_recent_turns() {
+        let history = vec![
+            turn("Is aspirin safe?", "Aspirin is generally safe [1]."),
+            turn("What about metformin?", "Metformin lowers glucose [1, 2]."),
+        ];
+
+        let selected = select_chat_history(&settings(1000), history.clone()).unwrap();
+        assert_eq!(
+            selected,
+            vec![
+                turn("Is aspirin safe?", "Aspirin is generally safe."),
+                turn("What about metformin?", "Metformin lowers glucose."),
+            ]
+        );
+
+        let selected = select_chat_history(&settings(20), history).unwrap();
+        assert_eq!(
+            selected,
+            vec![turn("What about metformin?", "Metformin lowers glucose.")]
+        );
+    }
+
+    #[test]
+    fn test_prepare_openai_input_orders_messages() {
+        let input = prepare_openai_input(
+            &settings(1000),
+            SummarizerInput {
+                query: String::from("And in pregnancy?"),
+                retrieved_result: String::from("[1] Metformin is safe in pregnancy."),
+                conversation_memory: String::new(),
+                chat_history: vec![turn("What about metformin?", "Metformin lowers glucose.")],
+            },
+        );
+        let roles = input["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+    }
 }
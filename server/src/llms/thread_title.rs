@@ -0,0 +1,92 @@
+use crate::llms::query_rephraser;
+use crate::resilience::Dependency;
+use crate::search::SearchError;
+use serde::{Deserialize, Serialize};
+
+// the answer is only there to disambiguate the question, its opening is enough
+const MAX_ANSWER_CHARS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadTitleSettings {
+    pub enabled: bool,
+    /// Characters the title may take, at most the 255 of `threads.title`
+    pub max_length: usize,
+}
+
+#[tracing::instrument(level = "info", ret)]
+fn prepare_title_prompt(query: &str, answer: &str) -> String {
+    let answer: String = answer.chars().take(MAX_ANSWER_CHARS).collect();
+    format!("[INST] Write a short descriptive title for a conversation between a user and a scientific medical assistant, given its first question and answer. The title names the topic in a few words, without quotes and without ending punctuation.\n\n---\n\nFollow the following format.\n\nTitle: ${{title}}\n\n---\n\nQuestion: {}\n\nAnswer: {}\n\nTitle: [/INST]",
+        query,
+        answer,
+    )
+}
+
+/// Keeps the first line of the completion without its label, quotes and ending punctuation, cut
+/// at a word boundary to `max_length` characters.
+fn clean_title(completion: &str, max_length: usize) -> Option<String> {
+    let line = completion.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line.strip_prefix("Title:").unwrap_or(line);
+    let title = line
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .trim_matches(|c: char| matches!(c, '"' | '\'' | '*' | '.' | ' '))
+        .to_string();
+    if title.is_empty() {
+        return None;
+    }
+    if title.chars().count() <= max_length {
+        return Some(title);
+    }
+
+    let truncated: String = title.chars().take(max_length).collect();
+    let truncated = match truncated.rfind(' ') {
+        Some(index) if index > 0 => truncated[..index].to_string(),
+        _ => truncated,
+    };
+    Some(
+        truncated
+            .trim_end_matches([',', ';', ':', '-', ' '])
+            .to_string(),
+    )
+}
+
+/// Generates the title of a thread from its first question and answer. Returns `None` when the
+/// model gives nothing usable, in which case the thread keeps the question as its title.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn generate_thread_title(
+    dependency: &Dependency,
+    rephraser_settings: &query_rephraser::QueryRephraserSettings,
+    settings: &ThreadTitleSettings,
+    query: &str,
+    answer: &str,
+) -> Result<Option<String>, SearchError> {
+    let prompt = prepare_title_prompt(query, answer);
+    let completion =
+        query_rephraser::complete_prompt(dependency, rephraser_settings, &prompt).await?;
+
+    Ok(clean_title(&completion, settings.max_length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_title() {
+        assert_eq!(
+            clean_title(
+                " Title: \"Metformin use in pregnancy.\"\nExplanation: ...",
+                80
+            )
+            .unwrap(),
+            "Metformin use in pregnancy"
+        );
+        assert_eq!(
+            clean_title("Metformin use during the first trimester", 24).unwrap(),
+            "Metformin use during"
+        );
+        assert!(clean_title("\n  \"\" \n", 80).is_none());
+    }
+}
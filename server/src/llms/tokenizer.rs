@@ -0,0 +1,90 @@
+use crate::search::SearchError;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tiktoken_rs::CoreBPE;
+
+// loading a BPE takes a while, so each one is loaded once per model
+static TOKENIZERS: Lazy<Mutex<HashMap<String, Arc<CoreBPE>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Whether tiktoken has the tokenizer of the model, so that its tokens are counted exactly.
+pub fn has_model_tokenizer(model: &str) -> bool {
+    tiktoken_rs::tokenizer::get_tokenizer(model).is_some()
+}
+
+/// Returns the tokenizer of the model. Models unknown to tiktoken, such as the self-hosted
+/// summarizer, are counted with `cl100k_base`, which under-counts the tokens of Llama and Mistral
+/// models, so their budget keeps a margin (see `SummarizerSettings::tokenizer_margin`).
+fn tokenizer_for_model(model: &str) -> Result<Arc<CoreBPE>, SearchError> {
+    let mut tokenizers = TOKENIZERS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(tokenizer) = tokenizers.get(model) {
+        return Ok(Arc::clone(tokenizer));
+    }
+
+    let tokenizer = match has_model_tokenizer(model) {
+        true => tiktoken_rs::get_bpe_from_model(model),
+        false => {
+            tracing::warn!(
+                "No tokenizer for model '{}', counting its tokens with cl100k_base",
+                model
+            );
+            tiktoken_rs::cl100k_base()
+        }
+    }
+    .map_err(|e| SearchError::Other(format!("Failed to load tokenizer: {}", e)))?;
+    let tokenizer = Arc::new(tokenizer);
+    tokenizers.insert(model.to_string(), Arc::clone(&tokenizer));
+
+    Ok(tokenizer)
+}
+
+pub fn count_tokens(model: &str, text: &str) -> Result<usize, SearchError> {
+    Ok(tokenizer_for_model(model)?.encode_ordinary(text).len())
+}
+
+/// Cuts the text down to its first `max_tokens` tokens of the model.
+pub fn truncate_to_tokens(
+    model: &str,
+    text: &str,
+    max_tokens: usize,
+) -> Result<String, SearchError> {
+    let tokenizer = tokenizer_for_model(model)?;
+    let mut tokens = tokenizer.encode_ordinary(text);
+    if tokens.len() <= max_tokens {
+        return Ok(text.to_string());
+    }
+
+    tokens.truncate(max_tokens);
+    // the cut can fall inside a multi-byte character, so drop tokens until the text decodes
+    while !tokens.is_empty() {
+        if let Ok(truncated) = tokenizer.decode(tokens.clone()) {
+            return Ok(truncated);
+        }
+        tokens.pop();
+    }
+
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_model_tokenizer() {
+        assert!(has_model_tokenizer("gpt-4o"));
+        assert!(!has_model_tokenizer("mistralai/Mistral-7B-Instruct-v0.2"));
+    }
+
+    #[test]
+    fn test_truncate_to_tokens() {
+        let text = "Metformin is considered safe during pregnancy.";
+        let count = count_tokens("gpt-4o", text).unwrap();
+
+        assert_eq!(truncate_to_tokens("gpt-4o", text, count).unwrap(), text);
+        let truncated = truncate_to_tokens("gpt-4o", text, 3).unwrap();
+        assert_eq!(count_tokens("gpt-4o", &truncated).unwrap(), 3);
+        assert!(text.starts_with(&truncated));
+    }
+}
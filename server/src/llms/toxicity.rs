@@ -1,8 +1,9 @@
 use crate::llms::LLMSettings;
+use crate::resilience::Dependency;
 use crate::search::SearchError;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ToxicityInput {
@@ -19,31 +20,56 @@ struct ToxicityScore {
 }
 
 #[tracing::instrument(level = "info", ret, err)]
-pub async fn predict_toxicity(
+pub async fn predict_toxicity_scores(
+    dependency: &Dependency,
     llm_settings: &LLMSettings,
     toxicity_input: ToxicityInput,
-) -> Result<bool, SearchError> {
+) -> Result<HashMap<String, f64>, SearchError> {
     let mut headers = HeaderMap::new();
     headers.insert(
         HeaderName::from_bytes(b"Authorization")?,
         HeaderValue::from_str(llm_settings.toxicity_auth_token.expose())?,
     );
-    let client = Client::new();
 
-    let response = client
-        .post(llm_settings.toxicity_url.as_str())
-        .json(&toxicity_input)
-        .headers(headers)
-        .send()
+    let toxicity_api_response: Vec<ToxicityScore> = dependency
+        .call(true, || async {
+            let response = dependency
+                .client()
+                .post(llm_settings.toxicity_url.as_str())
+                .json(&toxicity_input)
+                .headers(headers.clone())
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.json().await?)
+        })
         .await?;
-    let toxicity_api_response: Vec<ToxicityScore> = response.json().await?;
 
-    let toxicity_score = toxicity_api_response
+    Ok(toxicity_api_response
         .into_iter()
-        .find(|x| x.label == *"toxic")
-        .unwrap_or(ToxicityScore {
-            score: 0.0,
-            label: String::from(""),
-        });
-    Ok(toxicity_score.score > llm_settings.toxicity_threshold)
+        .map(|x| (x.label, x.score))
+        .collect())
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn predict_toxicity_score(
+    dependency: &Dependency,
+    llm_settings: &LLMSettings,
+    toxicity_input: ToxicityInput,
+) -> Result<f64, SearchError> {
+    let toxicity_scores = predict_toxicity_scores(dependency, llm_settings, toxicity_input).await?;
+    Ok(toxicity_scores.get("toxic").copied().unwrap_or(0.0))
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn predict_toxicity(
+    dependency: &Dependency,
+    llm_settings: &LLMSettings,
+    toxicity_input: ToxicityInput,
+) -> Result<bool, SearchError> {
+    let toxicity_score = predict_toxicity_score(dependency, llm_settings, toxicity_input).await?;
+    Ok(toxicity_score > llm_settings.toxicity_threshold)
 }
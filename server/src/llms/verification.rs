@@ -0,0 +1,319 @@
+use crate::rag::Source;
+use crate::resilience::Dependency;
+use crate::search::SearchError;
+use crate::secrets::Secret;
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+static CITATION_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\[(\d+(?:\s*,\s*\d+)*)\]").expect("Invalid citation regex"));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationSettings {
+    pub enabled: bool,
+    pub api_url: String,
+    pub auth_token: Secret<String>,
+    pub entailment_threshold: f64,
+    pub contradiction_threshold: f64,
+    /// NLI requests in flight at once, for all the sentences of an answer
+    pub max_concurrent_requests: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NLIPair {
+    pub text: String,
+    pub text_pair: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NLIInput {
+    pub inputs: NLIPair,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NLIScore {
+    pub label: String,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SupportLabel {
+    Supported,
+    Unsupported,
+    Contradicted,
+    Uncited,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SentenceVerification {
+    pub sentence: String,
+    pub citations: Vec<usize>,
+    pub label: SupportLabel,
+    pub entailment_score: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationOutput {
+    pub faithfulness_score: f64,
+    pub sentences: Vec<SentenceVerification>,
+}
+
+/// Splits the generated answer into sentences, keeping trailing citation markers such as
+/// `[1]` or `[2, 3]` attached to the sentence they belong to.
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = vec![];
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            sentences.push(std::mem::take(&mut current));
+            continue;
+        }
+        current.push(c);
+
+        let is_terminator = matches!(c, '.' | '!' | '?');
+        let at_boundary = chars
+            .peek()
+            .map(|next| next.is_whitespace())
+            .unwrap_or(true);
+        if !is_terminator || !at_boundary {
+            continue;
+        }
+
+        // citation markers written after the terminator belong to the current sentence
+        let rest = chars.clone().collect::<String>();
+        let trimmed = rest.trim_start();
+        if let Some(m) = CITATION_REGEX.find(trimmed) {
+            if m.start() == 0 {
+                let skip = rest.len() - trimmed.len() + m.end();
+                current.push(' ');
+                current.push_str(m.as_str());
+                for _ in 0..rest[..skip].chars().count() {
+                    chars.next();
+                }
+            }
+        }
+        sentences.push(std::mem::take(&mut current));
+    }
+    sentences.push(current);
+
+    sentences
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Returns the 1-based source numbers cited in the sentence, in order of appearance.
+pub fn extract_citations(sentence: &str) -> Vec<usize> {
+    let mut citations = vec![];
+    for capture in CITATION_REGEX.captures_iter(sentence) {
+        for number in capture[1].split(',') {
+            if let Ok(number) = number.trim().parse::<usize>() {
+                if !citations.contains(&number) {
+                    citations.push(number);
+                }
+            }
+        }
+    }
+    citations
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+async fn predict_entailment(
+    dependency: &Dependency,
+    settings: &VerificationSettings,
+    premise: &str,
+    hypothesis: &str,
+) -> Result<(f64, f64), SearchError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        HeaderName::from_bytes(b"Authorization")?,
+        HeaderValue::from_str(settings.auth_token.expose())?,
+    );
+    let nli_input = NLIInput {
+        inputs: NLIPair {
+            text: premise.to_string(),
+            text_pair: hypothesis.to_string(),
+        },
+    };
+
+    let nli_api_response: Vec<NLIScore> = dependency
+        .call(true, || async {
+            let response = dependency
+                .client()
+                .post(settings.api_url.as_str())
+                .json(&nli_input)
+                .headers(headers.clone())
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.json().await?)
+        })
+        .await?;
+
+    let score_of = |label: &str| {
+        nli_api_response
+            .iter()
+            .find(|x| x.label.eq_ignore_ascii_case(label))
+            .map(|x| x.score)
+            .unwrap_or(0.0)
+    };
+    Ok((score_of("entailment"), score_of("contradiction")))
+}
+
+/// The index of a sentence, with its entailment and contradiction scores against one of its sources
+type SentenceScores = (usize, (f64, f64));
+
+/// A sentence of the answer, with the descriptions of the sources it cites.
+struct CitedSentence<'a> {
+    sentence: String,
+    citations: Vec<usize>,
+    hypothesis: String,
+    premises: Vec<&'a str>,
+}
+
+fn cite_sentence(sentence: String, sources: &[Source]) -> CitedSentence<'_> {
+    let citations = extract_citations(&sentence);
+    let hypothesis = CITATION_REGEX.replace_all(&sentence, "").trim().to_string();
+    let premises = citations
+        .iter()
+        .filter_map(|number| number.checked_sub(1).and_then(|index| sources.get(index)))
+        .map(|source| source.description.as_str())
+        .collect::<Vec<&str>>();
+
+    CitedSentence {
+        sentence,
+        citations,
+        hypothesis,
+        premises,
+    }
+}
+
+fn label_sentence(
+    settings: &VerificationSettings,
+    cited: CitedSentence,
+    scores: &[(f64, f64)],
+) -> SentenceVerification {
+    if cited.premises.is_empty() {
+        return SentenceVerification {
+            sentence: cited.sentence,
+            citations: cited.citations,
+            label: SupportLabel::Uncited,
+            entailment_score: 0.0,
+        };
+    }
+
+    let entailment_score = scores.iter().map(|s| s.0).fold(0.0, f64::max);
+    let contradiction_score = scores.iter().map(|s| s.1).fold(0.0, f64::max);
+
+    let label = if entailment_score >= settings.entailment_threshold {
+        SupportLabel::Supported
+    } else if contradiction_score >= settings.contradiction_threshold {
+        SupportLabel::Contradicted
+    } else {
+        SupportLabel::Unsupported
+    };
+
+    SentenceVerification {
+        sentence: cited.sentence,
+        citations: cited.citations,
+        label,
+        entailment_score,
+    }
+}
+
+/// Checks every cited sentence against each of its sources, with at most
+/// `max_concurrent_requests` requests to the NLI service at once.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn verify_answer(
+    dependency: &Dependency,
+    settings: &VerificationSettings,
+    answer: &str,
+    sources: &[Source],
+) -> Result<VerificationOutput, SearchError> {
+    let cited_sentences: Vec<CitedSentence> = split_sentences(answer)
+        .into_iter()
+        .map(|sentence| cite_sentence(sentence, sources))
+        .collect();
+
+    // futures only start when polled, so the stream bounds the requests in flight
+    let predictions: Vec<_> = cited_sentences
+        .iter()
+        .enumerate()
+        .flat_map(|(index, cited)| {
+            cited.premises.iter().map(move |premise| async move {
+                predict_entailment(dependency, settings, premise, &cited.hypothesis)
+                    .await
+                    .map(|scores| (index, scores))
+            })
+        })
+        .collect();
+    let predictions: Vec<Result<SentenceScores, SearchError>> = stream::iter(predictions)
+        .buffer_unordered(settings.max_concurrent_requests.max(1))
+        .collect()
+        .await;
+
+    let mut scores: Vec<Vec<(f64, f64)>> = vec![vec![]; cited_sentences.len()];
+    for prediction in predictions {
+        let (index, prediction_scores) = prediction?;
+        scores[index].push(prediction_scores);
+    }
+    let sentences: Vec<SentenceVerification> = cited_sentences
+        .into_iter()
+        .zip(scores)
+        .map(|(cited, scores)| label_sentence(settings, cited, &scores))
+        .collect();
+
+    // Uncited sentences count against the score, as they are claims without support
+    let supported_count = sentences
+        .iter()
+        .filter(|s| s.label == SupportLabel::Supported)
+        .count();
+    let faithfulness_score = match sentences.len() {
+        0 => 0.0,
+        total => supported_count as f64 / total as f64,
+    };
+
+    Ok(VerificationOutput {
+        faithfulness_score,
+        sentences,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_sentences_keeps_citations() {
+        let sentences = split_sentences(
+            "Metformin lowers glucose [1]. It is first-line therapy. [2, 3]\nDoses vary by 2.5 mg?",
+        );
+        assert_eq!(
+            sentences,
+            vec![
+                "Metformin lowers glucose [1].",
+                "It is first-line therapy. [2, 3]",
+                "Doses vary by 2.5 mg?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_citations() {
+        assert_eq!(
+            extract_citations("A [1] and B [2, 3] and [1]."),
+            vec![1, 2, 3]
+        );
+        assert!(extract_citations("No citations here.").is_empty());
+    }
+}
@@ -1,10 +1,11 @@
-use crate::rag::{RetrievedResult, Source};
+use crate::rag::{RetrievedResult, SearchFilters, Source};
+use crate::resilience::Dependency;
 use crate::search::{SearchError, SourceType};
 use crate::secrets::Secret;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use time::OffsetDateTime;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BraveSettings {
@@ -95,25 +96,47 @@ struct BraveAPIResponse {
 
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn web_search(
+    dependency: &Dependency,
     brave_settings: &BraveSettings,
     brave_api_config: &BraveAPIConfig,
     search_query: &str,
+    filters: &SearchFilters,
 ) -> Result<Vec<RetrievedResult>, SearchError> {
-    let api_url = brave_settings.url.clone() + "?q=" + search_query;
-
-    let client = Client::new();
-    let response = client
-        .get(&api_url)
-        .query(&brave_api_config.queries)
-        .headers(brave_api_config.headers.clone())
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        response.error_for_status_ref()?;
+    let operators = filters.brave_query_operators();
+    let query = match operators.is_empty() {
+        true => search_query.to_string(),
+        false => format!("{} {}", search_query, operators),
+    };
+
+    let mut queries = brave_api_config.queries.clone();
+    queries.push(("q".to_string(), query));
+    // Brave searches a single language, the others are left to the post-filter
+    if let [language] = filters.languages.as_slice() {
+        queries.retain(|(key, _)| key != "search_lang");
+        queries.push(("search_lang".to_string(), language.clone()));
+    }
+    if let Some(freshness) = filters.brave_freshness(OffsetDateTime::now_utc().date()) {
+        queries.push(("freshness".to_string(), freshness));
     }
 
-    let brave_response: BraveAPIResponse = serde_json::from_value(response.json().await?)?;
+    let response_body: serde_json::Value = dependency
+        .call(true, || async {
+            let response = dependency
+                .client()
+                .get(&brave_settings.url)
+                .query(&queries)
+                .headers(brave_api_config.headers.clone())
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.json().await?)
+        })
+        .await?;
+
+    let brave_response: BraveAPIResponse = serde_json::from_value(response_body)?;
 
     let retrieved_results: Vec<RetrievedResult> = brave_response
         .web
@@ -147,5 +170,6 @@ fn convert_to_retrieved_result(result: BraveWebSearchResult) -> RetrievedResult
                 ),
             ]),
         },
+        similarity: None,
     }
 }
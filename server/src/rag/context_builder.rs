@@ -0,0 +1,223 @@
+use crate::llms::{summarizer, tokenizer};
+use crate::rag::RetrievedResult;
+use crate::search::SearchError;
+use crate::settings::Settings;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextBudget {
+    /// Model whose tokenizer counts the context
+    pub model: String,
+    /// Tokens the compressed context is reduced to
+    pub target_tokens: usize,
+    /// Tokens the context can take before the prompt overflows the model's context window
+    pub max_tokens: usize,
+}
+
+/// Computes the context budget for the query. The summarizer model is only picked after the
+/// context is built, so the budget is the tightest one among the models that can be picked.
+/// Room for the conversation memory is kept aside, as it is added to the prompt afterwards.
+#[tracing::instrument(level = "info", skip(settings), ret, err)]
+pub fn context_budget(settings: &Settings, query: &str) -> Result<ContextBudget, SearchError> {
+    let memory_tokens = match settings.conversation_memory.enabled {
+        true => settings.conversation_memory.max_tokens,
+        false => 0,
+    };
+    let mut budgets = vec![];
+    if settings.search.beta_usage_ratio > 0.0 {
+        let max_context_tokens = match tokenizer::has_model_tokenizer(&settings.summarizer.model) {
+            true => settings.summarizer.max_context_tokens,
+            false => {
+                let margin = settings.summarizer.tokenizer_margin.clamp(0.0, 1.0);
+                (settings.summarizer.max_context_tokens as f64 * (1.0 - margin)) as usize
+            }
+        };
+        let available_tokens = max_context_tokens
+            .saturating_sub(summarizer::llm_prompt_tokens(&settings.summarizer, query)?)
+            .saturating_sub(memory_tokens);
+        budgets.push(ContextBudget {
+            model: settings.summarizer.model.clone(),
+            target_tokens: settings
+                .summarizer
+                .target_context_tokens
+                .min(available_tokens),
+            max_tokens: available_tokens,
+        });
+    }
+    if settings.search.beta_usage_ratio < 1.0 {
+        let available_tokens = settings
+            .openai
+            .max_context_tokens
+            .saturating_sub(summarizer::openai_prompt_tokens(&settings.openai, query)?)
+            .saturating_sub(settings.openai.max_history_tokens)
+            .saturating_sub(memory_tokens);
+        budgets.push(ContextBudget {
+            model: settings.openai.model.clone(),
+            target_tokens: settings.openai.target_context_tokens.min(available_tokens),
+            max_tokens: available_tokens,
+        });
+    }
+
+    let target_tokens = budgets.iter().map(|b| b.target_tokens).min();
+    budgets
+        .into_iter()
+        .min_by_key(|b| b.max_tokens)
+        .map(|budget| ContextBudget {
+            target_tokens: target_tokens.unwrap_or(budget.target_tokens),
+            ..budget
+        })
+        .ok_or_else(|| SearchError::Other("No summarizer model to budget for".to_string()))
+}
+
+/// Splits `max_tokens` between the sources by rank, the source at rank `i` getting a share
+/// weighted by `1 / (i + 1)`. Tokens that a short source does not need go to the sources after it.
+pub fn allocate_source_budgets(max_tokens: usize, source_tokens: &[usize]) -> Vec<usize> {
+    let weights: Vec<f64> = (0..source_tokens.len())
+        .map(|rank| 1.0 / (rank + 1) as f64)
+        .collect();
+    let mut remaining_weight: f64 = weights.iter().sum();
+    let mut remaining_tokens = max_tokens;
+
+    source_tokens
+        .iter()
+        .zip(weights)
+        .map(|(tokens, weight)| {
+            // the epsilon keeps rounding errors in the weights from costing a token
+            let share =
+                (remaining_tokens as f64 * weight / remaining_weight + 1e-9).floor() as usize;
+            let allocation = share.min(*tokens);
+            remaining_tokens -= allocation;
+            remaining_weight -= weight;
+            allocation
+        })
+        .collect()
+}
+
+/// Keeps the first result of each url, and records the urls kept in `seen_urls`. The sources are
+/// stored once per url, so a duplicate would shift the numbers the answer cites them by.
+pub fn retain_unique_urls(
+    retrieved_results: &mut Vec<RetrievedResult>,
+    seen_urls: &mut HashSet<String>,
+) {
+    retrieved_results.retain(|result| seen_urls.insert(result.source.url.clone()));
+}
+
+/// Numbers the retrieved results so that the summarizer can cite them, and truncates each one
+/// to its share of the budget so that the context fits the model even without compression.
+#[tracing::instrument(level = "info", skip(retrieved_results), ret, err)]
+pub fn build_context(
+    budget: &ContextBudget,
+    retrieved_results: &[RetrievedResult],
+) -> Result<Vec<String>, SearchError> {
+    let context_texts: Vec<String> = retrieved_results
+        .iter()
+        .enumerate()
+        .map(|(index, r)| format!("[{}] {}", index + 1, r.text))
+        .collect();
+    let source_tokens = context_texts
+        .iter()
+        .map(|text| tokenizer::count_tokens(&budget.model, text))
+        .collect::<Result<Vec<usize>, SearchError>>()?;
+    let allocations = allocate_source_budgets(budget.max_tokens, &source_tokens);
+
+    context_texts
+        .into_iter()
+        .zip(source_tokens.into_iter().zip(allocations))
+        .map(|(text, (tokens, allocation))| match tokens <= allocation {
+            true => Ok(text),
+            false => tokenizer::truncate_to_tokens(&budget.model, &text, allocation),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::Source;
+    use crate::search::SourceType;
+    use std::collections::HashMap;
+
+    fn result(url: &str, text: &str) -> RetrievedResult {
+        RetrievedResult {
+            text: text.to_string(),
+            source: Source {
+                url: url.to_string(),
+                title: "".to_string(),
+                description: "".to_string(),
+                source_type: SourceType::Url,
+                metadata: HashMap::new(),
+            },
+            similarity: None,
+        }
+    }
+
+    #[test]
+    fn test_retain_unique_urls() {
+        // the same PubMed hit from the parent and the cluster search, and again from Brave
+        let mut agency_results = vec![
+            result("https://pubmed.ncbi.nlm.nih.gov/1", "parent"),
+            result("https://pubmed.ncbi.nlm.nih.gov/2", "second"),
+            result("https://pubmed.ncbi.nlm.nih.gov/1", "cluster"),
+        ];
+        let mut fallback_results = vec![
+            result("https://pubmed.ncbi.nlm.nih.gov/2", "web"),
+            result("https://example.com", "third"),
+        ];
+        let mut seen_urls = HashSet::new();
+        retain_unique_urls(&mut agency_results, &mut seen_urls);
+        retain_unique_urls(&mut fallback_results, &mut seen_urls);
+
+        let texts = |results: &[RetrievedResult]| {
+            results.iter().map(|r| r.text.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(texts(&agency_results), vec!["parent", "second"]);
+        assert_eq!(texts(&fallback_results), vec!["third"]);
+
+        let budget = ContextBudget {
+            model: "gpt-4o".to_string(),
+            target_tokens: 1000,
+            max_tokens: 1000,
+        };
+        let retrieved_results = [agency_results, fallback_results].concat();
+        assert_eq!(
+            build_context(&budget, &retrieved_results).unwrap(),
+            vec!["[1] parent", "[2] second", "[3] third"]
+        );
+    }
+
+    #[test]
+    fn test_context_budget_keeps_a_margin_for_unknown_tokenizers() {
+        let mut settings = Settings::new();
+        settings.search.beta_usage_ratio = 1.0;
+        settings.conversation_memory.enabled = false;
+        settings.summarizer.model = "mistralai/Mistral-7B-Instruct-v0.2".to_string();
+        settings.summarizer.max_context_tokens = 8000;
+
+        settings.summarizer.tokenizer_margin = 0.0;
+        let budget = context_budget(&settings, "Is metformin safe?").unwrap();
+        settings.summarizer.tokenizer_margin = 0.25;
+        let budget_with_margin = context_budget(&settings, "Is metformin safe?").unwrap();
+        assert_eq!(budget.max_tokens - budget_with_margin.max_tokens, 2000);
+
+        settings.summarizer.model = "gpt-4o".to_string();
+        let budget = context_budget(&settings, "Is metformin safe?").unwrap();
+        assert!(budget.max_tokens > budget_with_margin.max_tokens);
+    }
+
+    #[test]
+    fn test_allocate_source_budgets_by_rank() {
+        assert_eq!(
+            allocate_source_budgets(110, &[100, 100, 100]),
+            vec![60, 30, 20]
+        );
+    }
+
+    #[test]
+    fn test_allocate_source_budgets_passes_on_unused_tokens() {
+        assert_eq!(
+            allocate_source_budgets(110, &[10, 100, 100]),
+            vec![10, 60, 40]
+        );
+        assert_eq!(allocate_source_budgets(50, &[10, 10]), vec![10, 10]);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::citations;
+use crate::rag::{utils, RetrievedResult, Source, METADATA_PUBLICATION_TYPES};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Key of the evidence level in `sources.metadata`
+pub const METADATA_EVIDENCE_LEVEL: &str = "evidence_level";
+
+// hosts of the preprint servers, whose papers are not peer reviewed yet
+const PREPRINT_HOSTS: [&str; 6] = [
+    "medrxiv.org",
+    "biorxiv.org",
+    "arxiv.org",
+    "researchsquare.com",
+    "ssrn.com",
+    "preprints.org",
+];
+
+static SYSTEMATIC_REVIEW_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:systematic (?:literature )?review|meta-?analys[ie]s|meta analys[ie]s)\b")
+        .expect("Invalid systematic review regex")
+});
+static RANDOMIZED_TRIAL_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\brandomi[sz]ed(?:,? (?:double|single|triple)-blind(?:ed)?)?,? (?:placebo-controlled |controlled |clinical |multicent(?:er|re) )*trials?\b")
+        .expect("Invalid randomized trial regex")
+});
+static CASE_CONTROL_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\bcase[- ]control\b").expect("Invalid case-control regex"));
+static COHORT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:cohort|(?:prospective|retrospective|longitudinal) (?:observational )?stud(?:y|ies))\b")
+        .expect("Invalid cohort regex")
+});
+static CASE_REPORT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:case reports?|case series|we (?:report|present|describe) (?:a|the|an) (?:\w+ )?(?:case|patient))\b")
+        .expect("Invalid case report regex")
+});
+
+/// Strength of the evidence a source gives, from study design.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvidenceLevel {
+    /// Systematic reviews and meta-analyses
+    SystematicReview,
+    RandomizedControlledTrial,
+    Cohort,
+    CaseControl,
+    CaseReport,
+    Preprint,
+    /// Journal articles of other designs, such as narrative reviews and editorials
+    Other,
+    Web,
+}
+
+impl EvidenceLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvidenceLevel::SystematicReview => "systematic_review",
+            EvidenceLevel::RandomizedControlledTrial => "randomized_controlled_trial",
+            EvidenceLevel::Cohort => "cohort",
+            EvidenceLevel::CaseControl => "case_control",
+            EvidenceLevel::CaseReport => "case_report",
+            EvidenceLevel::Preprint => "preprint",
+            EvidenceLevel::Other => "other",
+            EvidenceLevel::Web => "web",
+        }
+    }
+}
+
+/// Reranking score added to the similarity of the sources of each level. Negative values demote
+/// them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvidenceBoosts {
+    pub systematic_review: f64,
+    pub randomized_controlled_trial: f64,
+    pub cohort: f64,
+    pub case_control: f64,
+    pub case_report: f64,
+    pub preprint: f64,
+    pub other: f64,
+    pub web: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvidenceSettings {
+    /// Applies the boosts when reranking, the levels are graded either way
+    pub boost_enabled: bool,
+    pub boosts: EvidenceBoosts,
+}
+
+impl EvidenceSettings {
+    pub fn boost(&self, level: EvidenceLevel) -> f64 {
+        if !self.boost_enabled {
+            return 0.0;
+        }
+        let boosts = &self.boosts;
+        match level {
+            EvidenceLevel::SystematicReview => boosts.systematic_review,
+            EvidenceLevel::RandomizedControlledTrial => boosts.randomized_controlled_trial,
+            EvidenceLevel::Cohort => boosts.cohort,
+            EvidenceLevel::CaseControl => boosts.case_control,
+            EvidenceLevel::CaseReport => boosts.case_report,
+            EvidenceLevel::Preprint => boosts.preprint,
+            EvidenceLevel::Other => boosts.other,
+            EvidenceLevel::Web => boosts.web,
+        }
+    }
+}
+
+fn is_preprint_url(url: &str) -> bool {
+    let host = utils::url_host(url);
+    PREPRINT_HOSTS
+        .iter()
+        .any(|preprint_host| utils::host_in_domain(&host, preprint_host))
+}
+
+/// The level of a design named in the text, the strongest first.
+fn level_from_text(text: &str) -> Option<EvidenceLevel> {
+    let patterns: [(&Lazy<Regex>, EvidenceLevel); 5] = [
+        (&SYSTEMATIC_REVIEW_REGEX, EvidenceLevel::SystematicReview),
+        (
+            &RANDOMIZED_TRIAL_REGEX,
+            EvidenceLevel::RandomizedControlledTrial,
+        ),
+        (&CASE_CONTROL_REGEX, EvidenceLevel::CaseControl),
+        (&COHORT_REGEX, EvidenceLevel::Cohort),
+        (&CASE_REPORT_REGEX, EvidenceLevel::CaseReport),
+    ];
+    patterns
+        .iter()
+        .find(|(regex, _)| regex.is_match(text))
+        .map(|(_, level)| *level)
+}
+
+/// Grades the source from its PubMed publication types, and else from the study design named in
+/// its title or abstract. Sources that are neither PubMed articles nor preprints are web pages.
+pub fn classify_evidence(source: &Source) -> EvidenceLevel {
+    let publication_types: Vec<String> = source
+        .metadata
+        .get(METADATA_PUBLICATION_TYPES)
+        .map(|types| types.split(';').map(|t| t.trim().to_lowercase()).collect())
+        .unwrap_or_default();
+    let has_type = |names: &[&str]| {
+        publication_types
+            .iter()
+            .any(|t| names.contains(&t.as_str()))
+    };
+
+    if has_type(&["preprint"]) || is_preprint_url(&source.url) {
+        return EvidenceLevel::Preprint;
+    }
+    if !source.metadata.contains_key(citations::METADATA_PMID) {
+        return EvidenceLevel::Web;
+    }
+
+    if has_type(&["systematic review", "meta-analysis"]) {
+        return EvidenceLevel::SystematicReview;
+    }
+    if has_type(&["randomized controlled trial"]) {
+        return EvidenceLevel::RandomizedControlledTrial;
+    }
+    if has_type(&["case reports"]) {
+        return EvidenceLevel::CaseReport;
+    }
+
+    level_from_text(&source.title)
+        .or_else(|| level_from_text(&source.description))
+        .unwrap_or(EvidenceLevel::Other)
+}
+
+/// Records the evidence level of each result in its metadata, and returns the levels.
+pub fn grade_results(retrieved_results: &mut [RetrievedResult]) -> Vec<EvidenceLevel> {
+    retrieved_results
+        .iter_mut()
+        .map(|result| {
+            let level = classify_evidence(&result.source);
+            result.source.metadata.insert(
+                METADATA_EVIDENCE_LEVEL.to_string(),
+                level.as_str().to_string(),
+            );
+            level
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SourceType;
+    use std::collections::HashMap;
+
+    fn source(url: &str, title: &str, metadata: Vec<(&str, &str)>) -> Source {
+        Source {
+            url: url.to_string(),
+            title: title.to_string(),
+            description: "".to_string(),
+            source_type: SourceType::Url,
+            metadata: HashMap::from_iter(
+                metadata
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string())),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_classify_evidence() {
+        let pubmed_url = "https://pubmed.ncbi.nlm.nih.gov/12345678";
+        let cases = [
+            (
+                source(
+                    pubmed_url,
+                    "Metformin in pregnancy",
+                    vec![
+                        ("pmid", "12345678"),
+                        ("publication_types", "Journal Article; Meta-Analysis"),
+                    ],
+                ),
+                EvidenceLevel::SystematicReview,
+            ),
+            (
+                source(
+                    pubmed_url,
+                    "Metformin versus insulin: a randomised, double-blind, placebo-controlled trial",
+                    vec![("pmid", "12345678")],
+                ),
+                EvidenceLevel::RandomizedControlledTrial,
+            ),
+            (
+                source(
+                    pubmed_url,
+                    "Metformin exposure and birth defects: a nested case-control study",
+                    vec![("pmid", "12345678")],
+                ),
+                EvidenceLevel::CaseControl,
+            ),
+            (
+                source(
+                    pubmed_url,
+                    "Offspring outcomes in a Danish birth cohort",
+                    vec![("pmid", "12345678")],
+                ),
+                EvidenceLevel::Cohort,
+            ),
+            (
+                source(
+                    pubmed_url,
+                    "Lactic acidosis after metformin overdose",
+                    vec![("pmid", "12345678"), ("publication_types", "Case Reports")],
+                ),
+                EvidenceLevel::CaseReport,
+            ),
+            (
+                source(
+                    pubmed_url,
+                    "Metformin: an update",
+                    vec![("pmid", "12345678"), ("publication_types", "Review")],
+                ),
+                EvidenceLevel::Other,
+            ),
+            (
+                source(
+                    "https://www.medrxiv.org/content/10.1101/2021.01.01.123456v1",
+                    "Metformin and COVID-19",
+                    vec![],
+                ),
+                EvidenceLevel::Preprint,
+            ),
+            (
+                source(
+                    "https://www.nhs.uk/medicines/metformin/",
+                    "A systematic review of metformin",
+                    vec![],
+                ),
+                EvidenceLevel::Web,
+            ),
+        ];
+
+        for (source, level) in cases {
+            assert_eq!(classify_evidence(&source), level, "{}", source.title);
+        }
+    }
+}
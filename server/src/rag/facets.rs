@@ -0,0 +1,199 @@
+use crate::citations;
+use crate::rag::{
+    filters, utils, RetrievedResult, METADATA_EVIDENCE_LEVEL, METADATA_PUBLICATION_TYPES,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// values kept for each facet, the most frequent first
+const MAX_FACET_VALUES: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/// Counts of the candidate sources of a search by their metadata, before they are cut down to
+/// `max_sources`. Sources without a value are not counted in the facet.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchFacets {
+    /// By publication year, the latest first, to pick `published_after` and `published_before`
+    pub years: Vec<FacetCount>,
+    /// By evidence level, the values of the `publication_types` filter
+    pub evidence_levels: Vec<FacetCount>,
+    /// By PubMed publication type, such as "Randomized Controlled Trial"
+    pub publication_types: Vec<FacetCount>,
+    pub journals: Vec<FacetCount>,
+    /// By ISO 639-1 code, the values of the `languages` filter
+    pub languages: Vec<FacetCount>,
+    /// By host, for the `include_domains` and `exclude_domains` filters
+    pub domains: Vec<FacetCount>,
+}
+
+fn tally(values: Vec<String>) -> Vec<FacetCount> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(value, count)| FacetCount { value, count })
+        .collect()
+}
+
+fn most_frequent(values: Vec<String>) -> Vec<FacetCount> {
+    let mut counts = tally(values);
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    counts.truncate(MAX_FACET_VALUES);
+    counts
+}
+
+/// Counts the facets of the results. Their evidence levels must be graded already.
+pub fn count_facets<'a>(
+    retrieved_results: impl IntoIterator<Item = &'a RetrievedResult>,
+) -> SearchFacets {
+    let mut years = vec![];
+    let mut evidence_levels = vec![];
+    let mut publication_types = vec![];
+    let mut journals = vec![];
+    let mut languages = vec![];
+    let mut domains = vec![];
+
+    for result in retrieved_results {
+        let source = &result.source;
+        let metadata = &source.metadata;
+        if let Some((first_day, _)) = filters::publication_period(source) {
+            years.push(first_day.year().to_string());
+        }
+        evidence_levels.extend(metadata.get(METADATA_EVIDENCE_LEVEL).cloned());
+        if let Some(types) = metadata.get(METADATA_PUBLICATION_TYPES) {
+            publication_types.extend(
+                types
+                    .split(';')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty()),
+            );
+        }
+        journals.extend(
+            metadata
+                .get(citations::METADATA_JOURNAL)
+                .filter(|journal| !journal.is_empty())
+                .cloned(),
+        );
+        languages.extend(filters::source_languages(source));
+
+        let host = utils::url_host(&source.url);
+        if !host.is_empty() {
+            domains.push(host.trim_start_matches("www.").to_string());
+        }
+    }
+
+    let mut years = tally(years);
+    years.sort_by(|a, b| b.value.cmp(&a.value));
+
+    SearchFacets {
+        years,
+        evidence_levels: most_frequent(evidence_levels),
+        publication_types: most_frequent(publication_types),
+        journals: most_frequent(journals),
+        languages: most_frequent(languages),
+        domains: most_frequent(domains),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::Source;
+    use crate::search::SourceType;
+
+    fn result(url: &str, metadata: Vec<(&str, &str)>) -> RetrievedResult {
+        RetrievedResult {
+            text: "".to_string(),
+            source: Source {
+                url: url.to_string(),
+                title: "".to_string(),
+                description: "".to_string(),
+                source_type: SourceType::Url,
+                metadata: HashMap::from_iter(
+                    metadata
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string())),
+                ),
+            },
+            similarity: None,
+        }
+    }
+
+    fn counts(values: Vec<(&str, usize)>) -> Vec<FacetCount> {
+        values
+            .into_iter()
+            .map(|(value, count)| FacetCount {
+                value: value.to_string(),
+                count,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_count_facets() {
+        let results = vec![
+            result(
+                "https://pubmed.ncbi.nlm.nih.gov/1",
+                vec![
+                    ("evidence_level", "randomized_controlled_trial"),
+                    (
+                        "publication_types",
+                        "Journal Article; Randomized Controlled Trial",
+                    ),
+                    ("journal", "Diabetes care"),
+                    ("publication_date", "2021 Mar"),
+                    ("languages", "eng"),
+                ],
+            ),
+            result(
+                "https://pubmed.ncbi.nlm.nih.gov/2",
+                vec![
+                    ("evidence_level", "other"),
+                    ("publication_types", "Journal Article"),
+                    ("journal", "Diabetes care"),
+                    ("year", "2019"),
+                ],
+            ),
+            result(
+                "https://www.nhs.uk/medicines/metformin/",
+                vec![
+                    ("evidence_level", "web"),
+                    ("language", "en"),
+                    ("page_age", "2021-05-10T00:00:00"),
+                ],
+            ),
+        ];
+
+        let facets = count_facets(&results);
+
+        assert_eq!(facets.years, counts(vec![("2021", 2), ("2019", 1)]));
+        assert_eq!(
+            facets.evidence_levels,
+            counts(vec![
+                ("other", 1),
+                ("randomized_controlled_trial", 1),
+                ("web", 1)
+            ])
+        );
+        assert_eq!(
+            facets.publication_types,
+            counts(vec![
+                ("Journal Article", 2),
+                ("Randomized Controlled Trial", 1)
+            ])
+        );
+        assert_eq!(facets.journals, counts(vec![("Diabetes care", 2)]));
+        assert_eq!(facets.languages, counts(vec![("en", 2)]));
+        assert_eq!(
+            facets.domains,
+            counts(vec![("pubmed.ncbi.nlm.nih.gov", 2), ("nhs.uk", 1)])
+        );
+    }
+}
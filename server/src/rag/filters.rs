@@ -0,0 +1,563 @@
+use crate::citations;
+use crate::rag::{
+    utils, EvidenceLevel, RetrievedResult, Source, METADATA_EVIDENCE_LEVEL, METADATA_LANGUAGES,
+    METADATA_PUBLICATION_DATE,
+};
+use serde::de::{value::StrDeserializer, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize};
+use time::macros::date;
+use validator::{Validate, ValidationError};
+
+/// Key of the ISO 639-1 language Brave detected for a web page
+const METADATA_WEB_LANGUAGE: &str = "language";
+/// Key of the date Brave found for a web page, as an ISO 8601 datetime
+const METADATA_PAGE_AGE: &str = "page_age";
+
+// PubMed names languages with ISO 639-2 bibliographic codes, the filters with ISO 639-1 ones
+const PUBMED_LANGUAGES: [(&str, &str); 24] = [
+    ("ara", "ar"),
+    ("chi", "zh"),
+    ("cze", "cs"),
+    ("dan", "da"),
+    ("dut", "nl"),
+    ("eng", "en"),
+    ("fin", "fi"),
+    ("fre", "fr"),
+    ("ger", "de"),
+    ("gre", "el"),
+    ("heb", "he"),
+    ("hun", "hu"),
+    ("ita", "it"),
+    ("jpn", "ja"),
+    ("kor", "ko"),
+    ("nor", "no"),
+    ("per", "fa"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("rus", "ru"),
+    ("spa", "es"),
+    ("swe", "sv"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+];
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Restrictions on the sources of a search. In the query string, dates are written as
+/// YYYY-MM-DD and lists are comma-separated. The filters are strict: a source whose date,
+/// language or evidence level is unknown is left out when that field is filtered on, since it
+/// cannot be shown to meet the filter. Fields that are not filtered on never leave a source out.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Validate)]
+#[validate(schema(function = "validate_date_range"))]
+pub struct SearchFilters {
+    #[serde(default, with = "optional_date")]
+    pub published_after: Option<time::Date>,
+    #[serde(default, with = "optional_date")]
+    pub published_before: Option<time::Date>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub publication_types: Vec<EvidenceLevel>,
+    /// ISO 639-1 codes, such as "en"
+    #[serde(default, deserialize_with = "comma_separated")]
+    #[validate(length(max = 10), custom(function = "validate_languages"))]
+    pub languages: Vec<String>,
+    /// Hosts, with their subdomains
+    #[serde(default, deserialize_with = "comma_separated")]
+    #[validate(length(max = 20), custom(function = "validate_domains"))]
+    pub include_domains: Vec<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    #[validate(length(max = 20), custom(function = "validate_domains"))]
+    pub exclude_domains: Vec<String>,
+}
+
+fn validate_date_range(filters: &SearchFilters) -> Result<(), ValidationError> {
+    match (filters.published_after, filters.published_before) {
+        (Some(after), Some(before)) if after > before => Err(ValidationError::new(
+            "published_after is later than published_before",
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn validate_languages(languages: &[String]) -> Result<(), ValidationError> {
+    if languages
+        .iter()
+        .all(|language| language.len() == 2 && language.chars().all(|c| c.is_ascii_lowercase()))
+    {
+        return Ok(());
+    }
+    Err(ValidationError::new("languages are not ISO 639-1 codes"))
+}
+
+// The domains end up in the Brave query, so anything but a host name is rejected
+fn validate_domains(domains: &[String]) -> Result<(), ValidationError> {
+    if domains.iter().all(|domain| {
+        domain.len() <= 253
+            && domain.contains('.')
+            && domain.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+    }) {
+        return Ok(());
+    }
+    Err(ValidationError::new("domains are not host names"))
+}
+
+fn comma_separated<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = String::deserialize(deserializer)?;
+    value
+        .split(',')
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .map(|item| T::deserialize(StrDeserializer::<D::Error>::new(&item)))
+        .collect()
+}
+
+mod optional_date {
+    use super::parse_date;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        date: &Option<time::Date>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match date {
+            Some(date) => serializer.serialize_str(&date.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<time::Date>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(date) if !date.is_empty() => parse_date(&date).map(Some).ok_or_else(|| {
+                D::Error::custom(format!("invalid date {}, expected YYYY-MM-DD", date))
+            }),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Parses a YYYY-MM-DD date, or the date of a YYYY-MM-DDTHH:MM:SS datetime.
+fn parse_date(date: &str) -> Option<time::Date> {
+    let date = date.get(..10)?;
+    match date.split('-').collect::<Vec<&str>>()[..] {
+        [year, month, day] => time::Date::from_calendar_date(
+            year.parse().ok()?,
+            time::Month::try_from(month.parse::<u8>().ok()?).ok()?,
+            day.parse().ok()?,
+        )
+        .ok(),
+        _ => None,
+    }
+}
+
+fn parse_month(month: &str) -> Option<time::Month> {
+    let month = month.to_lowercase();
+    let number = match MONTHS.iter().position(|name| month.starts_with(name)) {
+        Some(index) => index as u8 + 1,
+        None => month.parse().ok()?,
+    };
+    time::Month::try_from(number).ok()
+}
+
+/// The days the source may have been published on: a single day when its date is complete, its
+/// month or year otherwise. PubMed writes dates as "2021 Mar 15", "2021 Mar" or "2020 Nov-Dec".
+pub(crate) fn publication_period(source: &Source) -> Option<(time::Date, time::Date)> {
+    if let Some(date) = source
+        .metadata
+        .get(METADATA_PAGE_AGE)
+        .and_then(|page_age| parse_date(page_age))
+    {
+        return Some((date, date));
+    }
+
+    let publication_date = source
+        .metadata
+        .get(METADATA_PUBLICATION_DATE)
+        .or_else(|| source.metadata.get(citations::METADATA_YEAR))?;
+    let mut parts = publication_date.split([' ', '-']);
+    let year: i32 = parts.next()?.parse().ok()?;
+    let Some(month) = parts.next().and_then(parse_month) else {
+        return Some((
+            time::Date::from_calendar_date(year, time::Month::January, 1).ok()?,
+            time::Date::from_calendar_date(year, time::Month::December, 31).ok()?,
+        ));
+    };
+    match parts.next().and_then(|day| day.parse::<u8>().ok()) {
+        Some(day) => {
+            let date = time::Date::from_calendar_date(year, month, day).ok()?;
+            Some((date, date))
+        }
+        None => Some((
+            time::Date::from_calendar_date(year, month, 1).ok()?,
+            time::Date::from_calendar_date(
+                year,
+                month,
+                time::util::days_in_year_month(year, month),
+            )
+            .ok()?,
+        )),
+    }
+}
+
+/// The ISO 639-1 languages of the source.
+pub(crate) fn source_languages(source: &Source) -> Vec<String> {
+    if let Some(languages) = source.metadata.get(METADATA_LANGUAGES) {
+        return languages
+            .split(';')
+            .map(|language| language.trim().to_lowercase())
+            .map(|language| {
+                PUBMED_LANGUAGES
+                    .iter()
+                    .find(|(pubmed_code, _)| *pubmed_code == language)
+                    .map(|(_, code)| code.to_string())
+                    .unwrap_or(language)
+            })
+            .collect();
+    }
+    source
+        .metadata
+        .get(METADATA_WEB_LANGUAGE)
+        .filter(|language| !language.is_empty())
+        .map(|language| vec![language.to_lowercase()])
+        .unwrap_or_default()
+}
+
+impl SearchFilters {
+    pub fn is_empty(&self) -> bool {
+        self == &SearchFilters::default()
+    }
+
+    /// Whether the source meets every filter. Its evidence level must be graded already.
+    pub fn matches(&self, source: &Source) -> bool {
+        let host = utils::url_host(&source.url);
+        if !self.include_domains.is_empty()
+            && !self
+                .include_domains
+                .iter()
+                .any(|domain| utils::host_in_domain(&host, domain))
+        {
+            return false;
+        }
+        if self
+            .exclude_domains
+            .iter()
+            .any(|domain| utils::host_in_domain(&host, domain))
+        {
+            return false;
+        }
+
+        if !self.publication_types.is_empty() {
+            let level = source
+                .metadata
+                .get(METADATA_EVIDENCE_LEVEL)
+                .and_then(|level| {
+                    EvidenceLevel::deserialize(StrDeserializer::<serde::de::value::Error>::new(
+                        level,
+                    ))
+                    .ok()
+                });
+            if !level.is_some_and(|level| self.publication_types.contains(&level)) {
+                return false;
+            }
+        }
+
+        if !self.languages.is_empty()
+            && !source_languages(source)
+                .iter()
+                .any(|language| self.languages.contains(language))
+        {
+            return false;
+        }
+
+        if self.published_after.is_some() || self.published_before.is_some() {
+            let Some((first_day, last_day)) = publication_period(source) else {
+                return false;
+            };
+            if self.published_after.is_some_and(|after| last_day < after)
+                || self
+                    .published_before
+                    .is_some_and(|before| first_day > before)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// The `site:` operators Brave restricts the domains with, to append to the query.
+    pub fn brave_query_operators(&self) -> String {
+        let mut operators = vec![];
+        match self.include_domains.as_slice() {
+            [] => {}
+            [domain] => operators.push(format!("site:{}", domain)),
+            domains => operators.push(format!(
+                "({})",
+                domains
+                    .iter()
+                    .map(|domain| format!("site:{}", domain))
+                    .collect::<Vec<String>>()
+                    .join(" OR ")
+            )),
+        }
+        operators.extend(
+            self.exclude_domains
+                .iter()
+                .map(|domain| format!("-site:{}", domain)),
+        );
+        operators.join(" ")
+    }
+
+    /// The date range of the Brave `freshness` parameter, as YYYY-MM-DDtoYYYY-MM-DD.
+    pub fn brave_freshness(&self, today: time::Date) -> Option<String> {
+        if self.published_after.is_none() && self.published_before.is_none() {
+            return None;
+        }
+        let after = self.published_after.unwrap_or(date!(1970 - 01 - 01));
+        let before = self.published_before.unwrap_or(today);
+        Some(format!("{}to{}", after, before))
+    }
+}
+
+/// Keeps the results that meet the filters.
+pub fn apply_filters(
+    filters: &SearchFilters,
+    retrieved_results: Vec<RetrievedResult>,
+) -> Vec<RetrievedResult> {
+    if filters.is_empty() {
+        return retrieved_results;
+    }
+    retrieved_results
+        .into_iter()
+        .filter(|result| filters.matches(&result.source))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{SearchQueryRequest, SourceType};
+    use std::collections::HashMap;
+
+    fn source(url: &str, metadata: Vec<(&str, &str)>) -> Source {
+        Source {
+            url: url.to_string(),
+            title: "".to_string(),
+            description: "".to_string(),
+            source_type: SourceType::Url,
+            metadata: HashMap::from_iter(
+                metadata
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string())),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_parse_search_filters() {
+        let request: SearchQueryRequest = serde_urlencoded::from_str(
+            "query=metformin&published_after=2021-01-01\
+                &publication_types=randomized_controlled_trial,systematic_review\
+                &languages=en&exclude_domains=example.com,Example.org",
+        )
+        .unwrap();
+
+        assert_eq!(request.query, "metformin");
+        assert_eq!(
+            request.filters,
+            SearchFilters {
+                published_after: Some(date!(2021 - 01 - 01)),
+                published_before: None,
+                publication_types: vec![
+                    EvidenceLevel::RandomizedControlledTrial,
+                    EvidenceLevel::SystematicReview
+                ],
+                languages: vec!["en".to_string()],
+                include_domains: vec![],
+                exclude_domains: vec!["example.com".to_string(), "example.org".to_string()],
+            }
+        );
+        assert!(request.validate().is_ok());
+
+        let request: SearchQueryRequest = serde_urlencoded::from_str("query=metformin").unwrap();
+        assert!(request.filters.is_empty());
+
+        let request: SearchQueryRequest = serde_urlencoded::from_str(
+            "query=metformin&published_after=2022-01-01&published_before=2021-01-01",
+        )
+        .unwrap();
+        assert!(request.validate().is_err());
+
+        assert!(serde_urlencoded::from_str::<SearchQueryRequest>(
+            "query=metformin&publication_types=anecdote"
+        )
+        .is_err());
+
+        for domains in [
+            "include_domains=nih.gov%20OR%20site:example.com",
+            "exclude_domains=example.com/metformin",
+            "include_domains=localhost",
+            "exclude_domains=-example.com",
+            "include_domains=example..com",
+        ] {
+            let request: SearchQueryRequest =
+                serde_urlencoded::from_str(&format!("query=metformin&{}", domains)).unwrap();
+            assert!(request.validate().is_err(), "{}", domains);
+        }
+        let request: SearchQueryRequest = serde_urlencoded::from_str(
+            "query=metformin&include_domains=pubmed.ncbi.nlm.nih.gov,who-europe.int",
+        )
+        .unwrap();
+        assert!(request.validate().is_ok());
+    }
+
+    #[test]
+    fn test_matches() {
+        let filters = SearchFilters {
+            published_after: Some(date!(2021 - 03 - 10)),
+            publication_types: vec![EvidenceLevel::RandomizedControlledTrial],
+            languages: vec!["en".to_string()],
+            exclude_domains: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        let trial = vec![
+            ("evidence_level", "randomized_controlled_trial"),
+            ("languages", "eng"),
+        ];
+
+        let cases = [
+            // the month of March 2021 overlaps the range
+            (
+                [trial.clone(), vec![("publication_date", "2021 Mar")]].concat(),
+                true,
+            ),
+            (
+                [trial.clone(), vec![("publication_date", "2021 Mar 9")]].concat(),
+                false,
+            ),
+            ([trial.clone(), vec![("year", "2022")]].concat(), true),
+            // an unknown date is left out
+            (trial.clone(), false),
+            (
+                vec![
+                    ("evidence_level", "randomized_controlled_trial"),
+                    ("languages", "ger"),
+                    ("year", "2022"),
+                ],
+                false,
+            ),
+            (
+                vec![
+                    ("evidence_level", "cohort"),
+                    ("languages", "eng"),
+                    ("year", "2022"),
+                ],
+                false,
+            ),
+        ];
+        for (metadata, expected) in cases {
+            let source = source("https://pubmed.ncbi.nlm.nih.gov/12345678", metadata);
+            assert_eq!(filters.matches(&source), expected, "{:?}", source.metadata);
+        }
+
+        let web_page = source(
+            "https://www.example.com/metformin",
+            vec![
+                ("evidence_level", "web"),
+                ("language", "en"),
+                ("page_age", "2023-05-10T00:00:00"),
+            ],
+        );
+        let filters = SearchFilters {
+            published_after: Some(date!(2021 - 03 - 10)),
+            languages: vec!["en".to_string()],
+            ..Default::default()
+        };
+        assert!(filters.matches(&web_page));
+        let filters = SearchFilters {
+            include_domains: vec!["nih.gov".to_string()],
+            ..Default::default()
+        };
+        assert!(!filters.matches(&web_page));
+    }
+
+    #[test]
+    fn test_matches_leaves_out_unknown_values() {
+        // nothing is known of the source but its url
+        let unknown = source("https://pubmed.ncbi.nlm.nih.gov/12345678", vec![]);
+        assert!(SearchFilters::default().matches(&unknown));
+        assert!(SearchFilters {
+            exclude_domains: vec!["example.com".to_string()],
+            ..Default::default()
+        }
+        .matches(&unknown));
+
+        let filters = [
+            SearchFilters {
+                published_after: Some(date!(2021 - 03 - 10)),
+                ..Default::default()
+            },
+            SearchFilters {
+                published_before: Some(date!(2021 - 03 - 10)),
+                ..Default::default()
+            },
+            SearchFilters {
+                languages: vec!["en".to_string()],
+                ..Default::default()
+            },
+            SearchFilters {
+                publication_types: vec![EvidenceLevel::RandomizedControlledTrial],
+                ..Default::default()
+            },
+        ];
+        for filters in filters {
+            assert!(!filters.matches(&unknown), "{:?}", filters);
+        }
+
+        // an evidence level the grading does not know is as good as none
+        let ungraded = source(
+            "https://pubmed.ncbi.nlm.nih.gov/12345678",
+            vec![("evidence_level", "meta_analysis_of_anecdotes")],
+        );
+        let filters = SearchFilters {
+            publication_types: vec![EvidenceLevel::RandomizedControlledTrial],
+            ..Default::default()
+        };
+        assert!(!filters.matches(&ungraded));
+    }
+
+    #[test]
+    fn test_brave_filters() {
+        let filters = SearchFilters {
+            published_after: Some(date!(2021 - 01 - 01)),
+            include_domains: vec!["nih.gov".to_string(), "who.int".to_string()],
+            exclude_domains: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            filters.brave_query_operators(),
+            "(site:nih.gov OR site:who.int) -site:example.com"
+        );
+        assert_eq!(
+            filters.brave_freshness(date!(2026 - 10 - 19)),
+            Some("2021-01-01to2026-10-19".to_string())
+        );
+        assert_eq!(
+            SearchFilters::default().brave_freshness(date!(2026 - 10 - 19)),
+            None
+        );
+    }
+}
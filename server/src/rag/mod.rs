@@ -1,15 +1,27 @@
 pub use brave_search::*;
+pub use context_builder::*;
+pub use evidence::*;
+pub use facets::*;
+pub use filters::*;
 pub use models::*;
 pub use post_process::*;
 pub use pre_process::*;
+pub use pubmed_metadata::*;
 pub use pubmed_search::*;
+pub use retractions::*;
 pub use search::*;
 pub use utils::*;
 
 pub mod brave_search;
+pub mod context_builder;
+pub mod evidence;
+pub mod facets;
+pub mod filters;
 pub mod models;
 pub mod post_process;
 pub mod pre_process;
+pub mod pubmed_metadata;
 pub mod pubmed_search;
+pub mod retractions;
 pub mod search;
 pub mod utils;
@@ -1,3 +1,4 @@
+use crate::rag::SearchFacets;
 use crate::search::SourceType;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -15,12 +16,17 @@ pub struct Source {
 pub struct RetrievedResult {
     pub text: String,
     pub source: Source,
+    /// Embedding similarity to the query, for sources retrieved by embeddings
+    pub similarity: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SearchResponse {
     pub result: String,
     pub sources: Vec<Source>,
+    /// Responses cached before facets were counted have none
+    #[serde(default)]
+    pub facets: SearchFacets,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
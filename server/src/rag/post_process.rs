@@ -1,18 +1,23 @@
-use crate::llms::summarizer;
+use crate::llms::{self, moderation, summarizer, verification};
 use crate::proto::Embeddings;
-use crate::rag::utils;
-use crate::search::{api_models, SearchError};
+use crate::rag::{self, utils};
+use crate::resilience::Dependencies;
+use crate::search::{api_models, services, Search, SearchError};
 use crate::settings::Settings;
 use rand::Rng;
 use regex::Regex;
+use sqlx::PgPool;
 use std::cmp::Ordering;
 use tokio::sync::mpsc::Sender;
 
+/// Returns the result indices with their similarity to the query, ordered by the similarity plus
+/// the evidence boost of each result. The similarity itself is returned without the boost.
 #[tracing::instrument(level = "info", ret)]
 pub fn rerank_search_results(
     query_embeddings: &Embeddings,
     results_embeddings: &Vec<Embeddings>,
-) -> Vec<usize> {
+    evidence_boosts: &[f64],
+) -> Vec<(usize, f64)> {
     let query_dense_embedding = &query_embeddings.dense_embedding;
 
     let mut cosine_similarities: Vec<(usize, f64)> = results_embeddings
@@ -26,49 +31,196 @@ pub fn rerank_search_results(
         })
         .collect();
 
-    cosine_similarities.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    let score = |(index, similarity): &(usize, f64)| {
+        similarity + evidence_boosts.get(*index).copied().unwrap_or(0.0)
+    };
+    cosine_similarities.sort_by(|a, b| score(b).partial_cmp(&score(a)).unwrap_or(Ordering::Equal));
 
     cosine_similarities
-        .iter()
-        .map(|(index, _)| *index)
-        .collect()
 }
 
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn summarize_search_results(
     settings: Settings,
-    search_query_request: api_models::SearchQueryRequest,
-    search_response: String,
+    dependencies: &Dependencies,
+    summarizer_input: summarizer::SummarizerInput,
     update_processor: api_models::UpdateResultProcessor,
     stream_regex: Regex,
-    tx: Sender<api_models::SearchByIdResponse>,
-) -> Result<(), SearchError> {
+    moderator: &mut moderation::AnswerModerator,
+    tx: Sender<api_models::SearchStreamEvent>,
+) -> Result<String, SearchError> {
     let random_number = rand::thread_rng().gen_range(0.0..1.0);
 
-    if random_number < settings.search.beta_usage_ratio {
+    let answer = if random_number < settings.search.beta_usage_ratio {
         summarizer::generate_text_with_llm(
+            &dependencies.summarizer,
             settings.summarizer,
-            summarizer::SummarizerInput {
-                query: search_query_request.query,
-                retrieved_result: search_response,
-            },
+            summarizer_input,
             update_processor,
+            moderator,
             tx,
         )
-        .await?;
+        .await?
     } else {
         summarizer::generate_text_with_openai(
+            &dependencies.openai,
             settings.openai,
-            summarizer::SummarizerInput {
-                query: search_query_request.query,
-                retrieved_result: search_response,
-            },
+            summarizer_input,
             update_processor,
             stream_regex,
+            moderator,
             tx,
         )
-        .await?;
+        .await?
+    };
+
+    Ok(answer)
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn record_moderation_outcome(
+    pool: &PgPool,
+    search: &Search,
+    outcome: &moderation::ModerationOutcome,
+    tx: &Sender<api_models::SearchStreamEvent>,
+) -> Result<(), SearchError> {
+    services::update_search_moderation(pool, search, outcome).await?;
+
+    tx.send(api_models::SearchStreamEvent::Moderation(
+        api_models::SearchModerationResponse {
+            search_id: search.search_id,
+            outcome: outcome.clone(),
+        },
+    ))
+    .await
+    .map_err(|e| SearchError::Other(format!("Failed to send moderation result: {}", e)))?;
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn record_conversation_memory(
+    pool: &PgPool,
+    dependencies: &Dependencies,
+    settings: &Settings,
+    search: &Search,
+    memory: llms::ConversationMemory,
+    turn: llms::ConversationTurn,
+) -> Result<(), SearchError> {
+    let memory = llms::update_conversation_memory(
+        &dependencies.query_rephraser,
+        &settings.query_rephraser,
+        &settings.conversation_memory,
+        memory,
+        turn,
+    )
+    .await?;
+    services::update_thread_memory(pool, search, &memory).await?;
+
+    Ok(())
+}
+
+/// Suggests follow-up questions in the same thread, as the last event of the stream. The
+/// rephrased query stands on its own and is already redacted, so it is the one the model sees.
+#[tracing::instrument(level = "info", skip(sources), ret, err)]
+pub async fn suggest_follow_up_questions(
+    pool: &PgPool,
+    dependencies: &Dependencies,
+    settings: &Settings,
+    search: &Search,
+    answer: &str,
+    sources: &[rag::Source],
+    tx: &Sender<api_models::SearchStreamEvent>,
+) -> Result<(), SearchError> {
+    let questions = llms::generate_follow_up_questions(
+        &dependencies.query_rephraser,
+        &settings.query_rephraser,
+        &settings.follow_up,
+        &search.rephrased_query,
+        answer,
+        sources,
+    )
+    .await?;
+    if questions.is_empty() {
+        return Ok(());
     }
+    let search = services::update_search_follow_up_questions(pool, search, &questions).await?;
+
+    tx.send(api_models::SearchStreamEvent::FollowUp(
+        api_models::SearchFollowUpResponse {
+            search_id: search.search_id,
+            thread_id: search.thread_id,
+            questions,
+        },
+    ))
+    .await
+    .map_err(|e| SearchError::Other(format!("Failed to send follow-up questions: {}", e)))?;
+
+    Ok(())
+}
+
+/// Titles a new thread after its first answer, and pushes the title to the client.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn record_thread_title(
+    pool: &PgPool,
+    dependencies: &Dependencies,
+    settings: &Settings,
+    search: &Search,
+    query: &str,
+    answer: &str,
+    tx: &Sender<api_models::SearchStreamEvent>,
+) -> Result<(), SearchError> {
+    let Some(title) = llms::generate_thread_title(
+        &dependencies.query_rephraser,
+        &settings.query_rephraser,
+        &settings.thread_title,
+        query,
+        answer,
+    )
+    .await?
+    else {
+        return Ok(());
+    };
+    // the user may have renamed the thread while the answer was streaming
+    let Some(thread) = services::update_generated_thread_title(pool, search, &title).await? else {
+        return Ok(());
+    };
+
+    tx.send(api_models::SearchStreamEvent::ThreadTitle(
+        api_models::ThreadTitleResponse {
+            thread_id: thread.thread_id,
+            title: thread.title,
+        },
+    ))
+    .await
+    .map_err(|e| SearchError::Other(format!("Failed to send thread title: {}", e)))?;
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn verify_search_result(
+    pool: &PgPool,
+    dependencies: &Dependencies,
+    settings: &verification::VerificationSettings,
+    search: &Search,
+    answer: &str,
+    sources: &[rag::Source],
+    tx: &Sender<api_models::SearchStreamEvent>,
+) -> Result<(), SearchError> {
+    let verification_output =
+        verification::verify_answer(&dependencies.verification, settings, answer, sources).await?;
+    services::update_search_verification(pool, search, &verification_output).await?;
+
+    tx.send(api_models::SearchStreamEvent::Verification(
+        api_models::SearchVerificationResponse {
+            search_id: search.search_id,
+            faithfulness_score: verification_output.faithfulness_score,
+            sentences: verification_output.sentences,
+        },
+    ))
+    .await
+    .map_err(|e| SearchError::Other(format!("Failed to send verification result: {}", e)))?;
 
     Ok(())
 }
@@ -1,7 +1,8 @@
-use crate::llms::query_rephraser;
+use crate::llms::{query_rephraser, redaction, summarizer};
 use crate::proto::{
     agency_service_client::AgencyServiceClient, Embeddings, EmbeddingsOutput, SearchInput,
 };
+use crate::resilience::{Dependencies, Dependency};
 use crate::search::{api_models, services as search_services, SearchError};
 use crate::settings::Settings;
 use sqlx::PgPool;
@@ -11,17 +12,21 @@ use tonic::transport::Channel;
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn compute_embeddings(
     agency_service: Arc<AgencyServiceClient<Channel>>,
+    dependency: &Dependency,
     search_query: &str,
 ) -> Result<Embeddings, SearchError> {
-    let request = tonic::Request::new(SearchInput {
-        query: search_query.to_string(),
-    });
-    let mut agency_service = agency_service.as_ref().clone();
-
-    let response: EmbeddingsOutput = agency_service
-        .embeddings_compute(request)
-        .await?
-        .into_inner();
+    let response: EmbeddingsOutput = dependency
+        .call(true, || async {
+            let request = tonic::Request::new(SearchInput {
+                query: search_query.to_string(),
+            });
+            let mut agency_service = agency_service.as_ref().clone();
+            Ok(agency_service
+                .embeddings_compute(request)
+                .await?
+                .into_inner())
+        })
+        .await?;
 
     if response.status != 200 {
         return Err(SearchError::AgencyFailure(
@@ -37,11 +42,43 @@ pub async fn compute_embeddings(
     }
 }
 
+/// Builds the chat history of the thread for the summarizer. Stored queries may contain patient
+/// details depending on the redaction policy, so they are redacted again.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn chat_history(
+    pool: &PgPool,
+    settings: &Settings,
+    search_query_request: &api_models::SearchQueryRequest,
+) -> Result<Vec<summarizer::ChatTurn>, SearchError> {
+    let last_n_searches = match search_query_request.thread_id {
+        Some(thread_id) => search_services::get_last_n_searches(
+            pool,
+            settings.search.max_search_context,
+            &thread_id,
+        )
+        .await
+        .map_err(|e| SearchError::Other(format!("Failed to get last n searches: {}", e)))?,
+        None => vec![],
+    };
+
+    Ok(last_n_searches
+        .into_iter()
+        .rev()
+        .filter(|s| !s.result.is_empty())
+        .map(|s| summarizer::ChatTurn {
+            query: redaction::redact_pii(&settings.redaction, &s.query),
+            answer: s.result,
+        })
+        .collect())
+}
+
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn rephrase_query(
     pool: &PgPool,
+    dependencies: &Dependencies,
     settings: &Settings,
     search_query_request: &api_models::SearchQueryRequest,
+    conversation_memory: &str,
 ) -> Result<String, SearchError> {
     let last_n_searches = match search_query_request.thread_id {
         Some(thread_id) => search_services::get_last_n_searches(
@@ -59,6 +96,7 @@ pub async fn rephrase_query(
     }
 
     let rephraser_response = query_rephraser::rephrase_query(
+        &dependencies.query_rephraser,
         &settings.query_rephraser,
         &query_rephraser::QueryRephraserInput {
             query: search_query_request.query.clone(),
@@ -69,6 +107,7 @@ pub async fn rephrase_query(
                     result: s.result,
                 })
                 .collect(),
+            conversation_memory: conversation_memory.to_string(),
         },
     )
     .await?;
@@ -0,0 +1,543 @@
+use crate::cache::CachePool;
+use crate::citations;
+use crate::rag::RetrievedResult;
+use crate::resilience::Dependency;
+use crate::search::{services, SearchError};
+use crate::secrets::Secret;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use redis::{SetExpiry, SetOptions};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+// Keys of the PubMed fields in `sources.metadata`, next to the bibliographic ones. Lists are
+// separated by semicolons.
+pub const METADATA_PUBLICATION_DATE: &str = "publication_date";
+pub const METADATA_PUBLICATION_TYPES: &str = "publication_types";
+pub const METADATA_MESH_TERMS: &str = "mesh_terms";
+/// As ISO 639-2 codes, such as "eng"
+pub const METADATA_LANGUAGES: &str = "languages";
+pub const METADATA_PMCID: &str = "pmcid";
+/// When the fields were last fetched, in RFC 3339
+pub const METADATA_ENRICHED_AT: &str = "enriched_at";
+
+const CACHE_KEY_PREFIX: &str = "pubmed_metadata";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PubmedMetadataSettings {
+    pub enabled: bool,
+    /// The `efetch` endpoint of E-utilities, or of a compatible mirror
+    pub url: String,
+    /// Raises the rate limit of NCBI, left empty to go without
+    pub api_key: Secret<String>,
+    /// Identifies the application to NCBI
+    pub tool: String,
+    pub email: String,
+    /// PMIDs fetched in one request
+    pub batch_size: usize,
+    pub cache_ttl_secs: u64,
+    /// Age after which the stored fields are fetched again by the refresh job
+    pub refresh_after_days: i64,
+    pub refresh_interval_secs: u64,
+    /// Sources refreshed on each run of the refresh job
+    pub refresh_batch_size: i64,
+}
+
+/// The fields of a PubMed record, as given by `efetch`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PubmedMetadata {
+    pub pmid: String,
+    /// As "Family, Given", or the name of a group
+    pub authors: Vec<String>,
+    pub journal: Option<String>,
+    pub year: Option<String>,
+    /// As PubMed writes it, such as "2021 Mar 15" or "2020 Nov-Dec"
+    pub publication_date: Option<String>,
+    pub volume: Option<String>,
+    pub issue: Option<String>,
+    pub pages: Option<String>,
+    pub doi: Option<String>,
+    pub pmcid: Option<String>,
+    pub publication_types: Vec<String>,
+    pub mesh_terms: Vec<String>,
+    pub languages: Vec<String>,
+}
+
+impl PubmedMetadata {
+    /// The fields as stored in `sources.metadata`, stamped with the time they were fetched.
+    pub fn to_metadata(&self, enriched_at: &OffsetDateTime) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        metadata.insert(citations::METADATA_PMID.to_string(), self.pmid.clone());
+
+        let lists = [
+            (citations::METADATA_AUTHORS, &self.authors),
+            (METADATA_PUBLICATION_TYPES, &self.publication_types),
+            (METADATA_MESH_TERMS, &self.mesh_terms),
+            (METADATA_LANGUAGES, &self.languages),
+        ];
+        for (key, values) in lists {
+            if !values.is_empty() {
+                metadata.insert(key.to_string(), values.join("; "));
+            }
+        }
+
+        let fields = [
+            (citations::METADATA_JOURNAL, &self.journal),
+            (citations::METADATA_YEAR, &self.year),
+            (METADATA_PUBLICATION_DATE, &self.publication_date),
+            (citations::METADATA_VOLUME, &self.volume),
+            (citations::METADATA_ISSUE, &self.issue),
+            (citations::METADATA_PAGES, &self.pages),
+            (citations::METADATA_DOI, &self.doi),
+            (METADATA_PMCID, &self.pmcid),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                metadata.insert(key.to_string(), value.clone());
+            }
+        }
+
+        if let Ok(enriched_at) = enriched_at.format(&Rfc3339) {
+            metadata.insert(METADATA_ENRICHED_AT.to_string(), enriched_at);
+        }
+        metadata
+    }
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.to_string())
+}
+
+fn push_text(field: &mut Option<String>, text: &str) {
+    field.get_or_insert_with(String::new).push_str(text);
+}
+
+/// Parses the `PubmedArticleSet` returned by `efetch`. The identifiers in the reference lists of
+/// the articles are not theirs, so only the ones of the article itself are read.
+fn parse_pubmed_articles(xml: &str) -> Result<Vec<PubmedMetadata>, SearchError> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut articles = vec![];
+    let mut article = PubmedMetadata::default();
+    let mut path: Vec<String> = vec![];
+    // the identifier type of the open ELocationID or ArticleId
+    let mut id_type: Option<String> = None;
+    let mut author: (Option<String>, Option<String>, Option<String>) = (None, None, None);
+    let mut date: (Option<String>, Option<String>, Option<String>) = (None, None, None);
+    let mut medline_date: Option<String> = None;
+    let mut text: Option<String> = None;
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| SearchError::Other(format!("Invalid PubMed response: {}", e)))?;
+        match event {
+            Event::Start(element) => {
+                let name = String::from_utf8_lossy(element.name().as_ref()).to_string();
+                if matches!(name.as_str(), "ELocationID" | "ArticleId") {
+                    id_type = attribute(&element, "EIdType")
+                        .or_else(|| attribute(&element, "IdType"))
+                        .map(|t| t.to_lowercase());
+                }
+                if name == "PubmedArticle" {
+                    article = PubmedMetadata::default();
+                }
+                path.push(name);
+                text = None;
+            }
+            Event::Text(content) => {
+                let content = content
+                    .unescape()
+                    .map_err(|e| SearchError::Other(format!("Invalid PubMed response: {}", e)))?;
+                push_text(&mut text, &content);
+            }
+            Event::CData(content) => {
+                push_text(&mut text, &String::from_utf8_lossy(&content));
+            }
+            Event::End(_) => {
+                let joined = path.join("/");
+                let value = text.take().map(|t| t.trim().to_string());
+                let value = value.filter(|v| !v.is_empty());
+
+                match joined.as_str() {
+                    p if p.ends_with("MedlineCitation/PMID") => {
+                        article.pmid = value.unwrap_or_default();
+                    }
+                    p if p.ends_with("Article/Journal/Title") => article.journal = value,
+                    p if p.ends_with("Journal/JournalIssue/Volume") => article.volume = value,
+                    p if p.ends_with("Journal/JournalIssue/Issue") => article.issue = value,
+                    p if p.ends_with("JournalIssue/PubDate/Year") => date.0 = value,
+                    p if p.ends_with("JournalIssue/PubDate/Month") => date.1 = value,
+                    p if p.ends_with("JournalIssue/PubDate/Day") => date.2 = value,
+                    p if p.ends_with("JournalIssue/PubDate/MedlineDate") => medline_date = value,
+                    p if p.ends_with("Article/Pagination/MedlinePgn") => article.pages = value,
+                    p if p.ends_with("AuthorList/Author/LastName") => author.0 = value,
+                    p if p.ends_with("AuthorList/Author/ForeName") => author.1 = value,
+                    p if p.ends_with("AuthorList/Author/CollectiveName") => author.2 = value,
+                    p if p.ends_with("Article/AuthorList/Author") => {
+                        let name = match std::mem::take(&mut author) {
+                            (Some(family), Some(given), _) => {
+                                Some(format!("{}, {}", family, given))
+                            }
+                            (Some(family), None, _) => Some(family),
+                            (None, _, collective) => collective,
+                        };
+                        article.authors.extend(name);
+                    }
+                    p if p.ends_with("PublicationTypeList/PublicationType") => {
+                        article.publication_types.extend(value);
+                    }
+                    p if p.ends_with("MeshHeading/DescriptorName") => {
+                        article.mesh_terms.extend(value);
+                    }
+                    p if p.ends_with("MedlineCitation/Article/Language") => {
+                        article.languages.extend(value);
+                    }
+                    p if p.ends_with("Article/ELocationID")
+                        || p.ends_with("PubmedData/ArticleIdList/ArticleId") =>
+                    {
+                        match id_type.take().as_deref() {
+                            Some("doi") if article.doi.is_none() => {
+                                article.doi = value.and_then(|doi| citations::normalize_doi(&doi));
+                            }
+                            Some("pmc") => article.pmcid = value,
+                            _ => {}
+                        }
+                    }
+                    p if p.ends_with("PubmedArticle") => {
+                        let date = std::mem::take(&mut date);
+                        let medline_date = medline_date.take();
+                        article.year = date.0.clone().or_else(|| {
+                            medline_date
+                                .as_ref()
+                                .and_then(|d| d.get(..4))
+                                .filter(|year| year.chars().all(|c| c.is_ascii_digit()))
+                                .map(str::to_string)
+                        });
+                        article.publication_date = match date {
+                            (Some(year), month, day) => Some(
+                                [Some(year), month, day]
+                                    .into_iter()
+                                    .flatten()
+                                    .collect::<Vec<String>>()
+                                    .join(" "),
+                            ),
+                            _ => medline_date,
+                        };
+                        if !article.pmid.is_empty() {
+                            articles.push(std::mem::take(&mut article));
+                        }
+                    }
+                    _ => {}
+                }
+                path.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(articles)
+}
+
+/// Fetches the records of the PMIDs from the `efetch` endpoint, in batches of `batch_size`.
+#[tracing::instrument(level = "info", skip(dependency), err)]
+pub async fn fetch_pubmed_metadata(
+    dependency: &Dependency,
+    settings: &PubmedMetadataSettings,
+    pmids: &[String],
+) -> Result<Vec<PubmedMetadata>, SearchError> {
+    let mut articles = vec![];
+    for batch in pmids.chunks(settings.batch_size.max(1)) {
+        let mut query = vec![
+            ("db", "pubmed".to_string()),
+            ("retmode", "xml".to_string()),
+            ("id", batch.join(",")),
+            ("tool", settings.tool.clone()),
+            ("email", settings.email.clone()),
+        ];
+        if !settings.api_key.expose().is_empty() {
+            query.push(("api_key", settings.api_key.expose().clone()));
+        }
+
+        let response_body = dependency
+            .call(true, || async {
+                let response = dependency
+                    .client()
+                    .post(&settings.url)
+                    .form(&query)
+                    .send()
+                    .await?;
+
+                if !response.status().is_success() {
+                    response.error_for_status_ref()?;
+                }
+                Ok(response.text().await?)
+            })
+            .await?;
+
+        articles.extend(parse_pubmed_articles(&response_body)?);
+    }
+
+    Ok(articles)
+}
+
+fn cache_key(pmid: &str) -> String {
+    format!("{}:{}", CACHE_KEY_PREFIX, pmid)
+}
+
+/// Fills the metadata of the PubMed results with their PubMed record, from the cache or else
+/// from E-utilities. The search goes on with the bare results when the records cannot be
+/// fetched.
+#[tracing::instrument(level = "info", skip_all)]
+pub async fn enrich_pubmed_results(
+    dependency: &Dependency,
+    settings: &PubmedMetadataSettings,
+    cache: &CachePool,
+    retrieved_results: &mut [RetrievedResult],
+) {
+    let mut records: HashMap<String, PubmedMetadata> = HashMap::new();
+    let mut missing_pmids = vec![];
+    for result in retrieved_results.iter() {
+        let Some(pmid) = result.source.metadata.get(citations::METADATA_PMID) else {
+            continue;
+        };
+        match cache.get::<PubmedMetadata>(&cache_key(pmid)).await {
+            Some(record) => {
+                records.insert(pmid.clone(), record);
+            }
+            None => missing_pmids.push(pmid.clone()),
+        }
+    }
+
+    if !missing_pmids.is_empty() {
+        match fetch_pubmed_metadata(dependency, settings, &missing_pmids).await {
+            Ok(fetched_records) => {
+                let options = SetOptions::default()
+                    .with_expiration(SetExpiry::EX(settings.cache_ttl_secs as usize));
+                for record in fetched_records {
+                    cache
+                        .set_options(&cache_key(&record.pmid), &record, options)
+                        .await;
+                    records.insert(record.pmid.clone(), record);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to fetch PubMed metadata: {}", e),
+        }
+    }
+
+    let enriched_at = OffsetDateTime::now_utc();
+    for result in retrieved_results.iter_mut() {
+        let record = result
+            .source
+            .metadata
+            .get(citations::METADATA_PMID)
+            .and_then(|pmid| records.get(pmid));
+        if let Some(record) = record {
+            result
+                .source
+                .metadata
+                .extend(record.to_metadata(&enriched_at));
+        }
+    }
+}
+
+/// Fetches again the PubMed records of the stored sources once they are `refresh_after_days`
+/// old, or when they were never fetched.
+#[tracing::instrument(level = "info", skip_all, ret, err)]
+pub async fn refresh_pubmed_metadata(
+    pool: &PgPool,
+    dependency: &Dependency,
+    settings: &PubmedMetadataSettings,
+) -> Result<usize, SearchError> {
+    let enriched_before =
+        OffsetDateTime::now_utc() - time::Duration::days(settings.refresh_after_days);
+    let sources =
+        services::get_stale_pubmed_sources(pool, &enriched_before, settings.refresh_batch_size)
+            .await?;
+    let pmids: Vec<String> = sources
+        .iter()
+        .filter_map(|source| source.metadata.as_ref())
+        .filter_map(|metadata| metadata.get(citations::METADATA_PMID))
+        .filter_map(|pmid| pmid.as_str().map(str::to_string))
+        .collect();
+    if pmids.is_empty() {
+        return Ok(0);
+    }
+
+    let records = fetch_pubmed_metadata(dependency, settings, &pmids).await?;
+    let enriched_at = OffsetDateTime::now_utc();
+    let mut updates = vec![];
+    for source in &sources {
+        let pmid = source
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(citations::METADATA_PMID))
+            .and_then(|pmid| pmid.as_str());
+        // a PMID PubMed no longer knows is only stamped, so that it is not fetched on every run
+        let metadata = match records
+            .iter()
+            .find(|record| Some(record.pmid.as_str()) == pmid)
+        {
+            Some(record) => record.to_metadata(&enriched_at),
+            None => HashMap::from_iter(vec![(
+                METADATA_ENRICHED_AT.to_string(),
+                enriched_at.format(&Rfc3339).unwrap_or_default(),
+            )]),
+        };
+        updates.push((source.source_id, serde_json::to_value(metadata)?));
+    }
+    services::update_sources_metadata(pool, &updates).await?;
+
+    Ok(updates.len())
+}
+
+/// Periodically refreshes the PubMed records of the stored sources.
+pub fn spawn_pubmed_refresh_job(
+    pool: PgPool,
+    dependency: Dependency,
+    settings: PubmedMetadataSettings,
+) {
+    if !settings.enabled {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(settings.refresh_interval_secs));
+        loop {
+            interval.tick().await;
+            // a failed run is retried on the next tick
+            if let Err(e) = refresh_pubmed_metadata(&pool, &dependency, &settings).await {
+                tracing::error!("Failed to refresh PubMed metadata: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    const EFETCH_RESPONSE: &str = r#"<?xml version="1.0" ?>
+<!DOCTYPE PubmedArticleSet PUBLIC "-//NLM//DTD PubMedArticle, 1st January 2024//EN" "https://dtd.nlm.nih.gov/ncbi/pubmed/out/pubmed_240101.dtd">
+<PubmedArticleSet>
+  <PubmedArticle>
+    <MedlineCitation Status="MEDLINE" Owner="NLM">
+      <PMID Version="1">12345678</PMID>
+      <Article PubModel="Print">
+        <Journal>
+          <JournalIssue CitedMedium="Internet">
+            <Volume>44</Volume>
+            <Issue>3</Issue>
+            <PubDate><Year>2021</Year><Month>Mar</Month></PubDate>
+          </JournalIssue>
+          <Title>Diabetes care</Title>
+        </Journal>
+        <ArticleTitle>Metformin in <i>pregnancy</i>.</ArticleTitle>
+        <Pagination><MedlinePgn>123-130</MedlinePgn></Pagination>
+        <ELocationID EIdType="doi" ValidYN="Y">10.2337/dc20-1234</ELocationID>
+        <AuthorList CompleteYN="Y">
+          <Author ValidYN="Y"><LastName>Smith</LastName><ForeName>Jane A</ForeName><Initials>JA</Initials></Author>
+          <Author ValidYN="Y"><CollectiveName>MiTy Collaborative Group</CollectiveName></Author>
+        </AuthorList>
+        <PublicationTypeList>
+          <PublicationType UI="D016428">Journal Article</PublicationType>
+          <PublicationType UI="D016449">Randomized Controlled Trial</PublicationType>
+        </PublicationTypeList>
+        <Language>eng</Language>
+      </Article>
+      <CommentsCorrectionsList>
+        <CommentsCorrections RefType="Cites"><PMID Version="1">87654321</PMID></CommentsCorrections>
+      </CommentsCorrectionsList>
+      <MeshHeadingList>
+        <MeshHeading><DescriptorName UI="D008687" MajorTopicYN="Y">Metformin</DescriptorName></MeshHeading>
+        <MeshHeading><DescriptorName UI="D011247" MajorTopicYN="N">Pregnancy</DescriptorName></MeshHeading>
+      </MeshHeadingList>
+    </MedlineCitation>
+    <PubmedData>
+      <ArticleIdList>
+        <ArticleId IdType="pubmed">12345678</ArticleId>
+        <ArticleId IdType="pmc">PMC7654321</ArticleId>
+      </ArticleIdList>
+    </PubmedData>
+  </PubmedArticle>
+  <PubmedArticle>
+    <MedlineCitation>
+      <PMID Version="1">23456789</PMID>
+      <Article>
+        <Journal>
+          <JournalIssue><PubDate><MedlineDate>2020 Nov-Dec</MedlineDate></PubDate></JournalIssue>
+          <Title>BMJ &amp; Practice</Title>
+        </Journal>
+      </Article>
+    </MedlineCitation>
+  </PubmedArticle>
+</PubmedArticleSet>"#;
+
+    #[test]
+    fn test_parse_pubmed_articles() {
+        let articles = parse_pubmed_articles(EFETCH_RESPONSE).unwrap();
+
+        assert_eq!(articles.len(), 2);
+        assert_eq!(
+            articles[0],
+            PubmedMetadata {
+                pmid: "12345678".to_string(),
+                authors: vec![
+                    "Smith, Jane A".to_string(),
+                    "MiTy Collaborative Group".to_string()
+                ],
+                journal: Some("Diabetes care".to_string()),
+                year: Some("2021".to_string()),
+                publication_date: Some("2021 Mar".to_string()),
+                volume: Some("44".to_string()),
+                issue: Some("3".to_string()),
+                pages: Some("123-130".to_string()),
+                doi: Some("10.2337/dc20-1234".to_string()),
+                pmcid: Some("PMC7654321".to_string()),
+                publication_types: vec![
+                    "Journal Article".to_string(),
+                    "Randomized Controlled Trial".to_string()
+                ],
+                mesh_terms: vec!["Metformin".to_string(), "Pregnancy".to_string()],
+                languages: vec!["eng".to_string()],
+            }
+        );
+        assert_eq!(articles[1].journal.as_deref(), Some("BMJ & Practice"));
+        assert_eq!(articles[1].year.as_deref(), Some("2020"));
+        assert_eq!(
+            articles[1].publication_date.as_deref(),
+            Some("2020 Nov-Dec")
+        );
+    }
+
+    #[test]
+    fn test_to_metadata() {
+        let articles = parse_pubmed_articles(EFETCH_RESPONSE).unwrap();
+        let metadata = articles[0].to_metadata(&datetime!(2026-10-19 09:30 UTC));
+
+        assert_eq!(
+            metadata["authors"],
+            "Smith, Jane A; MiTy Collaborative Group"
+        );
+        assert_eq!(
+            metadata["publication_types"],
+            "Journal Article; Randomized Controlled Trial"
+        );
+        assert_eq!(metadata["enriched_at"], "2026-10-19T09:30:00Z");
+        assert_eq!(metadata["languages"], "eng");
+        assert!(!metadata.contains_key("language"));
+    }
+}
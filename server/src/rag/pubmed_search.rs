@@ -1,7 +1,9 @@
+use crate::citations;
 use crate::proto::{
     agency_service_client::AgencyServiceClient, Embeddings, PubmedResponse, PubmedSource,
 };
 use crate::rag::{RetrievedResult, Source};
+use crate::resilience::Dependency;
 use crate::search::{SearchError, SourceType};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -24,23 +26,31 @@ pub fn convert_to_retrieved_result(
             title: source.title,
             description: source.r#abstract,
             source_type: SourceType::Url,
-            metadata: HashMap::new(),
+            metadata: HashMap::from_iter(vec![(
+                citations::METADATA_PMID.to_string(),
+                source.pubmed_id.to_string(),
+            )]),
         },
+        similarity: None,
     }
 }
 
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn pubmed_parent_search(
     agency_service: Arc<AgencyServiceClient<Channel>>,
+    dependency: &Dependency,
     embeddings: &Embeddings,
 ) -> Result<Vec<PubmedSource>, SearchError> {
-    let request = tonic::Request::new(embeddings.clone());
-    let mut agency_service = agency_service.as_ref().clone();
-
-    let response: PubmedResponse = agency_service
-        .pubmed_parent_search(request)
-        .await?
-        .into_inner();
+    let response: PubmedResponse = dependency
+        .call(true, || async {
+            let request = tonic::Request::new(embeddings.clone());
+            let mut agency_service = agency_service.as_ref().clone();
+            Ok(agency_service
+                .pubmed_parent_search(request)
+                .await?
+                .into_inner())
+        })
+        .await?;
 
     if response.status != 200 {
         return Err(SearchError::AgencyFailure(
@@ -54,15 +64,19 @@ pub async fn pubmed_parent_search(
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn pubmed_cluster_search(
     agency_service: Arc<AgencyServiceClient<Channel>>,
+    dependency: &Dependency,
     embeddings: &Embeddings,
 ) -> Result<Vec<PubmedSource>, SearchError> {
-    let request = tonic::Request::new(embeddings.clone());
-    let mut agency_service = agency_service.as_ref().clone();
-
-    let response: PubmedResponse = agency_service
-        .pubmed_cluster_search(request)
-        .await?
-        .into_inner();
+    let response: PubmedResponse = dependency
+        .call(true, || async {
+            let request = tonic::Request::new(embeddings.clone());
+            let mut agency_service = agency_service.as_ref().clone();
+            Ok(agency_service
+                .pubmed_cluster_search(request)
+                .await?
+                .into_inner())
+        })
+        .await?;
 
     if response.status != 200 {
         return Err(SearchError::AgencyFailure(
@@ -0,0 +1,517 @@
+use crate::citations;
+use crate::rag::RetrievedResult;
+use crate::search::{data_models, services, SearchError};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+// Keys of the retraction flag in `sources.metadata`
+pub const METADATA_RETRACTION_STATUS: &str = "retraction_status";
+pub const METADATA_RETRACTION_NOTICE_DOI: &str = "retraction_notice_doi";
+/// As YYYY-MM-DD
+pub const METADATA_RETRACTION_DATE: &str = "retraction_date";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetractionNature {
+    Retraction,
+    ExpressionOfConcern,
+}
+
+impl RetractionNature {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetractionNature::Retraction => "retraction",
+            RetractionNature::ExpressionOfConcern => "expression_of_concern",
+        }
+    }
+
+    /// The nature as the dataset names it. Corrections and reinstatements are not kept.
+    fn from_dataset(nature: &str) -> Option<Self> {
+        match nature.trim().to_lowercase().as_str() {
+            "retraction" => Some(RetractionNature::Retraction),
+            "expression of concern" => Some(RetractionNature::ExpressionOfConcern),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to the retrieved sources that were retracted. Sources with an expression of
+/// concern are always kept, and flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetractedSourcePolicy {
+    Drop,
+    Flag,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetractionSettings {
+    pub enabled: bool,
+    /// The Retraction Watch CSV, as distributed by Crossref, imported again whenever it changes
+    pub dataset_path: String,
+    pub import_interval_secs: u64,
+    pub retracted_sources: RetractedSourcePolicy,
+}
+
+/// A notice of the dataset, for the paper it retracts or raises concerns about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetractionRecord {
+    pub record_id: String,
+    pub doi: Option<String>,
+    pub pmid: Option<String>,
+    pub nature: RetractionNature,
+    pub notice_doi: Option<String>,
+    pub reason: Option<String>,
+    pub retracted_on: Option<time::Date>,
+}
+
+const DATASET_COLUMNS: [&str; 7] = [
+    "Record ID",
+    "RetractionDate",
+    "RetractionDOI",
+    "OriginalPaperDOI",
+    "OriginalPaperPubMedID",
+    "RetractionNature",
+    "Reason",
+];
+
+#[derive(Debug, Deserialize)]
+struct RetractionWatchRow {
+    #[serde(rename = "Record ID")]
+    record_id: String,
+    #[serde(rename = "RetractionDate")]
+    retraction_date: String,
+    #[serde(rename = "RetractionDOI")]
+    retraction_doi: String,
+    #[serde(rename = "OriginalPaperDOI")]
+    original_paper_doi: String,
+    #[serde(rename = "OriginalPaperPubMedID")]
+    original_paper_pmid: String,
+    #[serde(rename = "RetractionNature")]
+    retraction_nature: String,
+    #[serde(rename = "Reason")]
+    reason: String,
+}
+
+// the dataset writes missing DOIs as "unavailable" and missing PMIDs as 0
+fn dataset_doi(doi: &str) -> Option<String> {
+    match doi.trim().to_lowercase().as_str() {
+        "" | "unavailable" | "na" => None,
+        _ => citations::normalize_doi(doi),
+    }
+}
+
+fn dataset_pmid(pmid: &str) -> Option<String> {
+    let pmid = pmid.trim();
+    if pmid.is_empty() || pmid.trim_start_matches('0').is_empty() {
+        return None;
+    }
+    pmid.chars()
+        .all(|c| c.is_ascii_digit())
+        .then(|| pmid.to_string())
+}
+
+/// Parses the dates of the dataset, written as "4/22/2024 0:00", or as YYYY-MM-DD.
+fn parse_retraction_date(date: &str) -> Option<time::Date> {
+    let date = date.split_whitespace().next()?;
+    let (year, month, day) = match date.split('/').collect::<Vec<&str>>()[..] {
+        [month, day, year] => (year, month, day),
+        _ => match date.split('-').collect::<Vec<&str>>()[..] {
+            [year, month, day] => (year, month, day),
+            _ => return None,
+        },
+    };
+    let month = time::Month::try_from(month.parse::<u8>().ok()?).ok()?;
+    time::Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()
+}
+
+/// Parses the retractions and expressions of concern of a Retraction Watch CSV. Notices of papers
+/// with neither a DOI nor a PMID cannot be matched to a source, and are left out.
+pub fn parse_retraction_watch<R: std::io::Read>(
+    reader: R,
+) -> Result<Vec<RetractionRecord>, SearchError> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader
+        .headers()
+        .map_err(|e| SearchError::InvalidData(format!("Invalid retraction dataset: {}", e)))?;
+    if let Some(column) = DATASET_COLUMNS
+        .iter()
+        .find(|column| !headers.iter().any(|header| header == **column))
+    {
+        return Err(SearchError::InvalidData(format!(
+            "Invalid retraction dataset: missing the {} column",
+            column
+        )));
+    }
+
+    let mut records = vec![];
+    let mut malformed_rows = 0;
+    for row in reader.deserialize::<RetractionWatchRow>() {
+        let Ok(row) = row else {
+            malformed_rows += 1;
+            continue;
+        };
+        let Some(nature) = RetractionNature::from_dataset(&row.retraction_nature) else {
+            continue;
+        };
+        let doi = dataset_doi(&row.original_paper_doi);
+        let pmid = dataset_pmid(&row.original_paper_pmid);
+        if doi.is_none() && pmid.is_none() {
+            continue;
+        }
+
+        let reason = row.reason.trim().trim_matches(';').trim();
+        records.push(RetractionRecord {
+            record_id: row.record_id.trim().to_string(),
+            doi,
+            pmid,
+            nature,
+            notice_doi: dataset_doi(&row.retraction_doi),
+            reason: (!reason.is_empty()).then(|| reason.to_string()),
+            retracted_on: parse_retraction_date(&row.retraction_date),
+        });
+    }
+    if malformed_rows > 0 {
+        tracing::warn!(
+            "Skipped {} malformed rows of the retraction dataset",
+            malformed_rows
+        );
+    }
+
+    Ok(records)
+}
+
+/// Replaces the retraction index with the notices of the dataset, and flags the stored sources
+/// they concern.
+#[tracing::instrument(level = "info", skip_all, ret, err)]
+pub async fn import_retractions(
+    pool: &PgPool,
+    settings: &RetractionSettings,
+) -> Result<usize, SearchError> {
+    let dataset_path = settings.dataset_path.clone();
+    let records = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(dataset_path).map_err(|e| {
+            SearchError::Other(format!("Failed to open the retraction dataset: {}", e))
+        })?;
+        parse_retraction_watch(std::io::BufReader::new(file))
+    })
+    .await
+    .map_err(|e| SearchError::Other(format!("Failed to parse the retraction dataset: {}", e)))??;
+    // an empty or truncated file would otherwise wipe the index
+    if records.is_empty() {
+        return Err(SearchError::InvalidData(
+            "Invalid retraction dataset: no notices found".to_string(),
+        ));
+    }
+
+    let imported = services::replace_retractions(pool, &records).await?;
+    services::flag_retracted_sources(pool).await?;
+
+    Ok(imported)
+}
+
+/// Periodically imports the retraction dataset, when the file was modified since the last import.
+/// Fails when screening is enabled and the dataset is missing, rather than screening nothing.
+pub fn spawn_retraction_import_job(
+    pool: PgPool,
+    settings: RetractionSettings,
+) -> Result<(), SearchError> {
+    if !settings.enabled {
+        return Ok(());
+    }
+    if !std::path::Path::new(&settings.dataset_path).is_file() {
+        return Err(SearchError::Other(format!(
+            "Retraction dataset not found at {}",
+            settings.dataset_path
+        )));
+    }
+
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(settings.import_interval_secs));
+        let mut imported_version: Option<SystemTime> = None;
+        loop {
+            interval.tick().await;
+            let version = std::fs::metadata(&settings.dataset_path).and_then(|m| m.modified());
+            let version = match version {
+                Ok(version) => version,
+                Err(e) => {
+                    tracing::error!("Failed to read the retraction dataset: {}", e);
+                    continue;
+                }
+            };
+            if imported_version == Some(version) {
+                continue;
+            }
+            // a failed run is retried on the next tick
+            match import_retractions(&pool, &settings).await {
+                Ok(_) => imported_version = Some(version),
+                Err(e) => tracing::error!("Failed to import retractions: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// The notice a source is flagged with, a retraction over an expression of concern.
+fn strongest_notice<'a>(
+    result: &RetrievedResult,
+    retractions: &'a [data_models::Retraction],
+) -> Option<&'a data_models::Retraction> {
+    let metadata = &result.source.metadata;
+    let doi = metadata
+        .get(citations::METADATA_DOI)
+        .and_then(|doi| citations::normalize_doi(doi))
+        .map(|doi| doi.to_lowercase());
+    let pmid = metadata.get(citations::METADATA_PMID);
+
+    retractions
+        .iter()
+        .filter(|retraction| {
+            let same_doi =
+                doi.is_some() && retraction.doi.as_ref().map(|doi| doi.to_lowercase()) == doi;
+            let same_pmid = pmid.is_some() && retraction.pmid.as_ref() == pmid;
+            same_doi || same_pmid
+        })
+        .max_by_key(|retraction| {
+            (
+                retraction.nature == RetractionNature::Retraction.as_str(),
+                retraction.retracted_on,
+            )
+        })
+}
+
+/// Flags the results that were retracted or given an expression of concern, and drops the
+/// retracted ones when the policy says so.
+fn apply_retractions(
+    settings: &RetractionSettings,
+    retrieved_results: Vec<RetrievedResult>,
+    retractions: &[data_models::Retraction],
+) -> Vec<RetrievedResult> {
+    retrieved_results
+        .into_iter()
+        .filter_map(|mut result| {
+            let Some(notice) = strongest_notice(&result, retractions) else {
+                return Some(result);
+            };
+            if notice.nature == RetractionNature::Retraction.as_str()
+                && settings.retracted_sources == RetractedSourcePolicy::Drop
+            {
+                tracing::info!("Dropped retracted source {}", result.source.url);
+                return None;
+            }
+
+            let metadata = &mut result.source.metadata;
+            metadata.insert(
+                METADATA_RETRACTION_STATUS.to_string(),
+                notice.nature.clone(),
+            );
+            if let Some(notice_doi) = &notice.notice_doi {
+                metadata.insert(
+                    METADATA_RETRACTION_NOTICE_DOI.to_string(),
+                    notice_doi.clone(),
+                );
+            }
+            if let Some(retracted_on) = notice.retracted_on {
+                metadata.insert(
+                    METADATA_RETRACTION_DATE.to_string(),
+                    retracted_on.to_string(),
+                );
+            }
+            Some(result)
+        })
+        .collect()
+}
+
+/// Checks the results against the retraction index by DOI and PMID.
+#[tracing::instrument(level = "info", skip_all, err)]
+pub async fn screen_retractions(
+    pool: &PgPool,
+    settings: &RetractionSettings,
+    retrieved_results: Vec<RetrievedResult>,
+) -> Result<Vec<RetrievedResult>, SearchError> {
+    if !settings.enabled {
+        return Ok(retrieved_results);
+    }
+
+    let mut dois = HashSet::new();
+    let mut pmids = HashSet::new();
+    for result in &retrieved_results {
+        let metadata = &result.source.metadata;
+        if let Some(doi) = metadata
+            .get(citations::METADATA_DOI)
+            .and_then(|doi| citations::normalize_doi(doi))
+        {
+            dois.insert(doi.to_lowercase());
+        }
+        if let Some(pmid) = metadata.get(citations::METADATA_PMID) {
+            pmids.insert(pmid.clone());
+        }
+    }
+    if dois.is_empty() && pmids.is_empty() {
+        return Ok(retrieved_results);
+    }
+
+    let retractions = services::get_retractions(
+        pool,
+        &dois.into_iter().collect::<Vec<String>>(),
+        &pmids.into_iter().collect::<Vec<String>>(),
+    )
+    .await?;
+
+    Ok(apply_retractions(settings, retrieved_results, &retractions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::Source;
+    use crate::search::SourceType;
+    use std::collections::HashMap;
+    use time::macros::date;
+
+    const DATASET: &str = "\
+Record ID,Title,Subject,Institution,Journal,Publisher,Country,Author,URLS,ArticleType,RetractionDate,RetractionDOI,RetractionPubMedID,OriginalPaperDate,OriginalPaperDOI,OriginalPaperPubMedID,RetractionNature,Reason,Paywalled,Notes
+12345,Hydroxychloroquine in COVID-19,(HSC) Medicine - Infectious Disease;,Some Hospital,The Lancet,Elsevier,United States,Jane Doe;John Roe,,Research Article;,6/4/2020 0:00,10.1016/S0140-6736(20)31324-6,32511943,5/22/2020 0:00,10.1016/S0140-6736(20)31180-6,32450107,Retraction,+Concerns/Issues About Data;+Lack of Approval from Author;,No,
+12346,Ivermectin for COVID-19,(HSC) Medicine - Infectious Disease;,Some Institute,Research Square,Research Square,Egypt,A. Author,,Preprint;,7/14/2021 0:00,unavailable,0,11/16/2020 0:00,10.21203/rs.3.rs-100956/v1,0,Expression of concern,+Concerns/Issues About Data;,No,
+12347,A corrected paper,(BLS) Biology;,Some University,Nature,Springer,Germany,B. Author,,Research Article;,1/2/2021 0:00,10.1038/correction,0,1/1/2020 0:00,10.1038/original,0,Correction,+Error in Data;,No,
+12348,A paper without identifiers,(BLS) Biology;,Some University,Some Journal,Some Publisher,France,C. Author,,Research Article;,1/2/2021 0:00,unavailable,0,1/1/2020 0:00,unavailable,0,Retraction,+Plagiarism;,No,
+";
+
+    fn result(url: &str, metadata: Vec<(&str, &str)>) -> RetrievedResult {
+        RetrievedResult {
+            text: "".to_string(),
+            source: Source {
+                url: url.to_string(),
+                title: url.to_string(),
+                description: "".to_string(),
+                source_type: SourceType::Url,
+                metadata: HashMap::from_iter(
+                    metadata
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string())),
+                ),
+            },
+            similarity: None,
+        }
+    }
+
+    fn retraction(
+        doi: Option<&str>,
+        pmid: Option<&str>,
+        nature: RetractionNature,
+    ) -> data_models::Retraction {
+        let now = crate::custom_types::DateTime(time::OffsetDateTime::now_utc());
+        data_models::Retraction {
+            retraction_id: uuid::Uuid::new_v4(),
+            record_id: "1".to_string(),
+            doi: doi.map(str::to_string),
+            pmid: pmid.map(str::to_string),
+            nature: nature.as_str().to_string(),
+            notice_doi: Some("10.1000/notice".to_string()),
+            reason: None,
+            retracted_on: Some(date!(2021 - 07 - 14)),
+            created_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn test_parse_retraction_watch() {
+        let records = parse_retraction_watch(DATASET.as_bytes()).unwrap();
+
+        assert_eq!(
+            records,
+            vec![
+                RetractionRecord {
+                    record_id: "12345".to_string(),
+                    doi: Some("10.1016/S0140-6736(20)31180-6".to_string()),
+                    pmid: Some("32450107".to_string()),
+                    nature: RetractionNature::Retraction,
+                    notice_doi: Some("10.1016/S0140-6736(20)31324-6".to_string()),
+                    reason: Some(
+                        "+Concerns/Issues About Data;+Lack of Approval from Author".to_string()
+                    ),
+                    retracted_on: Some(date!(2020 - 06 - 04)),
+                },
+                RetractionRecord {
+                    record_id: "12346".to_string(),
+                    doi: Some("10.21203/rs.3.rs-100956/v1".to_string()),
+                    pmid: None,
+                    nature: RetractionNature::ExpressionOfConcern,
+                    notice_doi: None,
+                    reason: Some("+Concerns/Issues About Data".to_string()),
+                    retracted_on: Some(date!(2021 - 07 - 14)),
+                },
+            ]
+        );
+        assert!(parse_retraction_watch("Title,Journal\nA,B\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_apply_retractions() {
+        let results = vec![
+            result(
+                "https://pubmed.ncbi.nlm.nih.gov/32450107",
+                vec![("pmid", "32450107")],
+            ),
+            result(
+                "https://www.researchsquare.com/article/rs-100956/v1",
+                vec![("doi", "https://doi.org/10.21203/RS.3.RS-100956/v1")],
+            ),
+            result("https://pubmed.ncbi.nlm.nih.gov/1", vec![("pmid", "1")]),
+        ];
+        let retractions = vec![
+            retraction(
+                Some("10.1000/other"),
+                Some("32450107"),
+                RetractionNature::ExpressionOfConcern,
+            ),
+            retraction(None, Some("32450107"), RetractionNature::Retraction),
+            retraction(
+                Some("10.21203/rs.3.rs-100956/v1"),
+                None,
+                RetractionNature::ExpressionOfConcern,
+            ),
+        ];
+
+        let mut settings = RetractionSettings {
+            enabled: true,
+            dataset_path: "".to_string(),
+            import_interval_secs: 3600,
+            retracted_sources: RetractedSourcePolicy::Flag,
+        };
+        let flagged = apply_retractions(&settings, results.clone(), &retractions);
+        let statuses: Vec<Option<&str>> = flagged
+            .iter()
+            .map(|r| {
+                r.source
+                    .metadata
+                    .get(METADATA_RETRACTION_STATUS)
+                    .map(String::as_str)
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![Some("retraction"), Some("expression_of_concern"), None]
+        );
+        assert_eq!(
+            flagged[0].source.metadata.get(METADATA_RETRACTION_DATE),
+            Some(&"2021-07-14".to_string())
+        );
+
+        settings.retracted_sources = RetractedSourcePolicy::Drop;
+        let screened = apply_retractions(&settings, results, &retractions);
+        let urls: Vec<&str> = screened.iter().map(|r| r.source.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://www.researchsquare.com/article/rs-100956/v1",
+                "https://pubmed.ncbi.nlm.nih.gov/1"
+            ]
+        );
+    }
+}
@@ -1,80 +1,202 @@
 use crate::cache::CachePool;
-use crate::llms::prompt_compression;
+use crate::llms::{extractive_compression, prompt_compression, tokenizer};
 use crate::proto::agency_service_client::AgencyServiceClient;
-use crate::rag::{self, brave_search, post_process, pre_process, pubmed_search};
-use crate::search::SearchError;
+use crate::rag::{
+    self, brave_search, context_builder, evidence, facets, filters, post_process, pre_process,
+    pubmed_metadata, pubmed_search, retractions,
+};
+use crate::resilience::Dependencies;
+use crate::search::{api_models, SearchError};
 use crate::settings::Settings;
+use sqlx::PgPool;
+use std::collections::HashSet;
 use std::sync::Arc;
 use tonic::transport::Channel;
 
+/// Searches the sources for the query of the request, restricted to its filters.
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn search(
     settings: &Settings,
+    pool: &PgPool,
+    dependencies: &Dependencies,
     brave_api_config: &brave_search::BraveAPIConfig,
     cache: &CachePool,
     agency_service: &AgencyServiceClient<Channel>,
-    search_query: &str,
+    search_query_request: &api_models::SearchQueryRequest,
 ) -> Result<rag::SearchResponse, SearchError> {
-    if let Some(response) = cache.get(search_query).await {
+    let search_query = search_query_request.query.as_str();
+    let search_filters = &search_query_request.filters;
+    let cache_key = match search_filters.is_empty() {
+        true => search_query.to_string(),
+        false => format!(
+            "{}\n{}",
+            search_query,
+            serde_json::to_string(search_filters)?
+        ),
+    };
+    if let Some(response) = cache.get(&cache_key).await {
         return Ok(response);
     }
 
     let (agency_results, fallback_results) = tokio::join!(
-        retrieve_result_from_agency(settings, agency_service, search_query),
-        brave_search::web_search(&settings.brave, brave_api_config, search_query),
+        retrieve_result_from_agency(
+            settings,
+            dependencies,
+            cache,
+            agency_service,
+            search_query,
+            search_filters
+        ),
+        brave_search::web_search(
+            &dependencies.brave,
+            &settings.brave,
+            brave_api_config,
+            search_query,
+            search_filters
+        ),
     );
 
-    let mut retrieved_results = Vec::new();
+    let mut agency_candidates = Vec::new();
     if let Ok(agency_results) = agency_results {
-        retrieved_results.extend(agency_results);
+        // before the top sources are picked, so that the next ones take the place of dropped ones
+        agency_candidates =
+            retractions::screen_retractions(pool, &settings.retractions, agency_results).await?;
     }
+    let mut fallback_candidates = Vec::new();
+    if let Ok(mut fallback_results) = fallback_results {
+        // Brave only takes the filters it has operators for
+        evidence::grade_results(&mut fallback_results);
+        fallback_candidates = filters::apply_filters(search_filters, fallback_results);
+    }
+    // the same source is often found by both PubMed searches, and by Brave
+    let mut seen_urls = HashSet::new();
+    context_builder::retain_unique_urls(&mut agency_candidates, &mut seen_urls);
+    context_builder::retain_unique_urls(&mut fallback_candidates, &mut seen_urls);
+    let facets = facets::count_facets(agency_candidates.iter().chain(&fallback_candidates));
 
     let max_sources = settings.search.max_sources as usize;
-    if retrieved_results.len() < max_sources {
-        if let Ok(fallback_results) = fallback_results {
-            let required_results_count = fallback_results
-                .len()
-                .min(max_sources - retrieved_results.len());
-            retrieved_results.extend(fallback_results.into_iter().take(required_results_count));
-        }
-    }
+    let mut retrieved_results: Vec<rag::RetrievedResult> =
+        agency_candidates.into_iter().take(max_sources).collect();
+    let required_results_count = max_sources - retrieved_results.len();
+    retrieved_results.extend(fallback_candidates.into_iter().take(required_results_count));
 
     if retrieved_results.is_empty() {
         return Err(SearchError::NoSources("No sources found".to_string()));
     }
 
-    let compressed_results = prompt_compression::compress(
-        &settings.llm,
-        prompt_compression::PromptCompressionInput {
-            query: search_query.to_string(),
-            target_token: 300,
-            context_texts_list: retrieved_results.iter().map(|r| r.text.clone()).collect(),
-        },
+    let context_budget = context_builder::context_budget(settings, search_query)?;
+    let compression_input = prompt_compression::PromptCompressionInput {
+        query: search_query.to_string(),
+        target_token: u16::try_from(context_budget.target_tokens).unwrap_or(u16::MAX),
+        context_texts_list: context_builder::build_context(&context_budget, &retrieved_results)?,
+    };
+    let (compressed_prompt, compression_path) = compress_search_context(
+        settings,
+        dependencies,
+        &context_budget,
+        compression_input,
+        &retrieved_results,
     )
     .await?;
 
     let response = rag::SearchResponse {
-        result: compressed_results.compressed_prompt,
+        result: compressed_prompt,
         sources: retrieved_results.into_iter().map(|r| r.source).collect(),
+        facets,
     };
-    cache.set(search_query, &response).await;
+    // a degraded context should not outlive the outage of the compression service
+    if compression_path == CompressionPath::Remote {
+        cache.set(&cache_key, &response).await;
+    }
 
     return Ok(response);
 }
 
+#[derive(Debug, PartialEq)]
+enum CompressionPath {
+    Remote,
+    ExtractiveFallback,
+}
+
+/// Compresses the context with the prompt compression service, and falls back to in-process
+/// extractive compression when the service fails, so that retrieved sources are not wasted.
+#[tracing::instrument(level = "info", skip_all, ret, err)]
+async fn compress_search_context(
+    settings: &Settings,
+    dependencies: &Dependencies,
+    context_budget: &context_builder::ContextBudget,
+    compression_input: prompt_compression::PromptCompressionInput,
+    retrieved_results: &[rag::RetrievedResult],
+) -> Result<(String, CompressionPath), SearchError> {
+    let (compressed_prompt, compression_path) = match prompt_compression::compress(
+        &dependencies.prompt_compression,
+        &settings.llm,
+        compression_input.clone(),
+    )
+    .await
+    {
+        Ok(output) => {
+            tracing::info!(compression_path = "remote", "Compressed search context");
+            (output.compressed_prompt, CompressionPath::Remote)
+        }
+        Err(e) => {
+            tracing::warn!(
+                compression_path = "extractive_fallback",
+                "Prompt compression failed, compressing in-process: {}",
+                e
+            );
+            let similarities: Vec<Option<f64>> =
+                retrieved_results.iter().map(|r| r.similarity).collect();
+            let output = extractive_compression::compress_extractively(
+                &context_budget.model,
+                &compression_input,
+                &similarities,
+            )?;
+            (
+                output.compressed_prompt,
+                CompressionPath::ExtractiveFallback,
+            )
+        }
+    };
+
+    // the compression service only aims for the target, so the context window is enforced here
+    let compressed_prompt = tokenizer::truncate_to_tokens(
+        &context_budget.model,
+        &compressed_prompt,
+        context_budget.max_tokens,
+    )?;
+
+    Ok((compressed_prompt, compression_path))
+}
+
 #[tracing::instrument(level = "info", ret, err)]
 async fn retrieve_result_from_agency(
     settings: &Settings,
+    dependencies: &Dependencies,
+    cache: &CachePool,
     agency_service: &AgencyServiceClient<Channel>,
     search_query: &str,
+    search_filters: &filters::SearchFilters,
 ) -> Result<Vec<rag::RetrievedResult>, SearchError> {
     let agency_service = Arc::new(agency_service.clone());
-    let query_embeddings =
-        pre_process::compute_embeddings(Arc::clone(&agency_service), search_query).await?;
+    let query_embeddings = pre_process::compute_embeddings(
+        Arc::clone(&agency_service),
+        &dependencies.agency,
+        search_query,
+    )
+    .await?;
 
     let (pubmed_parent_response, pubmed_cluster_response) = tokio::join!(
-        pubmed_search::pubmed_parent_search(Arc::clone(&agency_service), &query_embeddings),
-        pubmed_search::pubmed_cluster_search(Arc::clone(&agency_service), &query_embeddings),
+        pubmed_search::pubmed_parent_search(
+            Arc::clone(&agency_service),
+            &dependencies.agency,
+            &query_embeddings
+        ),
+        pubmed_search::pubmed_cluster_search(
+            Arc::clone(&agency_service),
+            &dependencies.agency,
+            &query_embeddings
+        ),
     );
 
     let mut retrieved_results: Vec<rag::RetrievedResult> = Vec::new();
@@ -106,16 +228,41 @@ async fn retrieve_result_from_agency(
         });
     }
 
-    let reranked_indices =
-        post_process::rerank_search_results(&query_embeddings, &source_embeddings);
+    // the publication types are needed to grade the evidence
+    if settings.pubmed_metadata.enabled {
+        pubmed_metadata::enrich_pubmed_results(
+            &dependencies.pubmed,
+            &settings.pubmed_metadata,
+            cache,
+            &mut retrieved_results,
+        )
+        .await;
+    }
+    let evidence_levels = evidence::grade_results(&mut retrieved_results);
+    // the agency has no filters, so the sources outside them are dropped before the top ones
+    // are picked
+    let (retrieved_results, (source_embeddings, evidence_boosts)): (Vec<_>, (Vec<_>, Vec<f64>)) =
+        retrieved_results
+            .into_iter()
+            .zip(source_embeddings.into_iter().zip(evidence_levels))
+            .filter(|(result, _)| search_filters.matches(&result.source))
+            .map(|(result, (embeddings, level))| {
+                (result, (embeddings, settings.evidence.boost(level)))
+            })
+            .unzip();
+
+    let reranked_indices = post_process::rerank_search_results(
+        &query_embeddings,
+        &source_embeddings,
+        &evidence_boosts,
+    );
 
-    let top_k = reranked_indices
-        .len()
-        .min(settings.search.max_sources as usize);
     let reranked_retrieved_results = reranked_indices
         .into_iter()
-        .take(top_k)
-        .map(|index| retrieved_results[index].clone())
+        .map(|(index, similarity)| rag::RetrievedResult {
+            similarity: Some(similarity),
+            ..retrieved_results[index].clone()
+        })
         .collect();
 
     Ok(reranked_retrieved_results)
@@ -13,3 +13,22 @@ pub fn cosine_similarity(v1: &[f64], v2: &[f64]) -> f64 {
 
     dot_product / magnitude_product
 }
+
+/// The lowercase host of the url, without its port.
+pub fn url_host(url: &str) -> String {
+    url.split("://")
+        .nth(1)
+        .unwrap_or(url)
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// Whether the host is the domain, or one of its subdomains.
+pub fn host_in_domain(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
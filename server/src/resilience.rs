@@ -0,0 +1,392 @@
+use crate::search::SearchError;
+use rand::Rng;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::Code;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DependencySettings {
+    pub connect_timeout_ms: u64,
+    pub read_timeout_ms: u64,
+    pub timeout_ms: u64,
+    pub max_retries: u32,
+    pub retry_base_delay_ms: u64,
+    pub failure_threshold: u32,
+    pub reset_timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResilienceSettings {
+    pub agency: DependencySettings,
+    pub brave: DependencySettings,
+    pub crisis_classifier: DependencySettings,
+    pub openai: DependencySettings,
+    pub prompt_compression: DependencySettings,
+    pub pubmed: DependencySettings,
+    pub query_rephraser: DependencySettings,
+    pub summarizer: DependencySettings,
+    pub toxicity: DependencySettings,
+    pub verification: DependencySettings,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct CircuitBreakerState {
+    state: CircuitState,
+    consecutive_failures: u32,
+    changed_at: Instant,
+}
+
+/// Stops calling a dependency after `failure_threshold` consecutive failed calls. Once
+/// `reset_timeout` has passed, a single trial call is let through to decide whether to close again.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    reset_timeout: Duration,
+    state: Arc<Mutex<CircuitBreakerState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold,
+            reset_timeout,
+            state: Arc::new(Mutex::new(CircuitBreakerState {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                changed_at: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).state
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.state {
+            CircuitState::Closed => true,
+            // a half-open trial that never reported back must not keep the circuit stuck
+            CircuitState::Open | CircuitState::HalfOpen => {
+                if state.changed_at.elapsed() < self.reset_timeout {
+                    return false;
+                }
+                state.state = CircuitState::HalfOpen;
+                state.changed_at = Instant::now();
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.state != CircuitState::Closed {
+            state.state = CircuitState::Closed;
+            state.changed_at = Instant::now();
+        }
+        state.consecutive_failures = 0;
+    }
+
+    /// Records an error response. The dependency answered, so a half-open circuit closes, but
+    /// the failures counted by a closed circuit are kept.
+    pub fn record_answer(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.state == CircuitState::HalfOpen {
+            state.state = CircuitState::Closed;
+            state.changed_at = Instant::now();
+            state.consecutive_failures = 0;
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.consecutive_failures += 1;
+        if state.state == CircuitState::HalfOpen
+            || state.consecutive_failures >= self.failure_threshold
+        {
+            state.state = CircuitState::Open;
+            state.changed_at = Instant::now();
+        }
+    }
+}
+
+/// Errors worth retrying and counting against the circuit breaker, as opposed to errors where
+/// the dependency answered but the request or its response was invalid.
+fn is_transient(error: &SearchError) -> bool {
+    match error {
+        SearchError::Reqwest(e) => match e.status() {
+            Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+            None => !e.is_decode() && !e.is_builder(),
+        },
+        SearchError::Tonic(status) => matches!(
+            status.code(),
+            Code::Unavailable
+                | Code::DeadlineExceeded
+                | Code::ResourceExhausted
+                | Code::Aborted
+                | Code::Unknown
+        ),
+        SearchError::DependencyUnavailable(_) => true,
+        _ => false,
+    }
+}
+
+/// A downstream service with its pooled HTTP client, timeouts, retry policy and circuit breaker.
+#[derive(Debug, Clone)]
+pub struct Dependency {
+    name: &'static str,
+    settings: DependencySettings,
+    client: Client,
+    breaker: CircuitBreaker,
+}
+
+impl Dependency {
+    pub fn new(name: &'static str, settings: &DependencySettings) -> Result<Self, SearchError> {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_millis(settings.connect_timeout_ms))
+            .read_timeout(Duration::from_millis(settings.read_timeout_ms))
+            .build()?;
+
+        Ok(Dependency {
+            name,
+            settings: settings.clone(),
+            client,
+            breaker: CircuitBreaker::new(
+                settings.failure_threshold,
+                Duration::from_millis(settings.reset_timeout_ms),
+            ),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn circuit_state(&self) -> CircuitState {
+        self.breaker.state()
+    }
+
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        // full jitter: a random delay up to the exponential backoff for this attempt
+        let backoff = self
+            .settings
+            .retry_base_delay_ms
+            .saturating_mul(1 << attempt.min(16));
+        Duration::from_millis(rand::thread_rng().gen_range(0..=backoff))
+    }
+
+    /// Runs `call` while the circuit is closed. Idempotent calls are retried on transient errors,
+    /// up to `max_retries` times. `timeout_ms` bounds each attempt, so a call can take up to
+    /// `(max_retries + 1) * timeout_ms` plus the backoff between attempts.
+    pub async fn call<T, F, Fut>(&self, idempotent: bool, call: F) -> Result<T, SearchError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, SearchError>>,
+    {
+        let max_attempts = match idempotent {
+            true => self.settings.max_retries + 1,
+            false => 1,
+        };
+        if !self.breaker.try_acquire() {
+            return Err(SearchError::DependencyUnavailable(format!(
+                "Circuit breaker for {} is open",
+                self.name
+            )));
+        }
+
+        // the breaker counts the call once, however many attempts it took
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let timeout = Duration::from_millis(self.settings.timeout_ms);
+            let result = match tokio::time::timeout(timeout, call()).await {
+                Ok(result) => result,
+                Err(_) => Err(SearchError::DependencyUnavailable(format!(
+                    "{} did not respond within {:?}",
+                    self.name, timeout
+                ))),
+            };
+
+            match result {
+                Ok(value) => {
+                    self.breaker.record_success();
+                    return Ok(value);
+                }
+                Err(e) if is_transient(&e) => {
+                    if attempt >= max_attempts {
+                        self.breaker.record_failure();
+                        return Err(e);
+                    }
+                    tracing::debug!("Retrying {} after attempt {}: {}", self.name, attempt, e);
+                    tokio::time::sleep(self.retry_delay(attempt)).await;
+                }
+                Err(e) => {
+                    self.breaker.record_answer();
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Dependencies {
+    pub agency: Dependency,
+    pub brave: Dependency,
+    pub crisis_classifier: Dependency,
+    pub openai: Dependency,
+    pub prompt_compression: Dependency,
+    pub pubmed: Dependency,
+    pub query_rephraser: Dependency,
+    pub summarizer: Dependency,
+    pub toxicity: Dependency,
+    pub verification: Dependency,
+}
+
+impl Dependencies {
+    pub fn new(settings: &ResilienceSettings) -> Result<Self, SearchError> {
+        Ok(Dependencies {
+            agency: Dependency::new("agency", &settings.agency)?,
+            brave: Dependency::new("brave", &settings.brave)?,
+            crisis_classifier: Dependency::new("crisis_classifier", &settings.crisis_classifier)?,
+            openai: Dependency::new("openai", &settings.openai)?,
+            prompt_compression: Dependency::new(
+                "prompt_compression",
+                &settings.prompt_compression,
+            )?,
+            pubmed: Dependency::new("pubmed", &settings.pubmed)?,
+            query_rephraser: Dependency::new("query_rephraser", &settings.query_rephraser)?,
+            summarizer: Dependency::new("summarizer", &settings.summarizer)?,
+            toxicity: Dependency::new("toxicity", &settings.toxicity)?,
+            verification: Dependency::new("verification", &settings.verification)?,
+        })
+    }
+
+    pub fn circuit_states(&self) -> BTreeMap<&'static str, CircuitState> {
+        [
+            &self.agency,
+            &self.brave,
+            &self.crisis_classifier,
+            &self.openai,
+            &self.prompt_compression,
+            &self.pubmed,
+            &self.query_rephraser,
+            &self.summarizer,
+            &self.toxicity,
+            &self.verification,
+        ]
+        .into_iter()
+        .map(|dependency| (dependency.name(), dependency.circuit_state()))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn settings() -> DependencySettings {
+        DependencySettings {
+            connect_timeout_ms: 100,
+            read_timeout_ms: 100,
+            timeout_ms: 100,
+            max_retries: 2,
+            retry_base_delay_ms: 0,
+            failure_threshold: 2,
+            reset_timeout_ms: 60_000,
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker_transitions() {
+        let breaker = CircuitBreaker::new(2, Duration::ZERO);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        assert!(breaker.try_acquire());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        assert!(breaker.try_acquire());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        // an error response closes a half-open circuit, but not the count of a closed one
+        breaker.record_failure();
+        breaker.record_answer();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.try_acquire());
+        breaker.record_answer();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_call_retries_idempotent_calls_only() {
+        let dependency = Dependency::new("test", &settings()).unwrap();
+        let attempts = AtomicU32::new(0);
+        let failing_call = || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(SearchError::DependencyUnavailable("down".to_string()))
+        };
+
+        assert!(dependency.call(false, failing_call).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(dependency.circuit_state(), CircuitState::Closed);
+
+        // the retries count as a single failure, the second call opens the breaker
+        attempts.store(0, Ordering::SeqCst);
+        assert!(dependency.call(true, failing_call).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(dependency.circuit_state(), CircuitState::Open);
+
+        attempts.store(0, Ordering::SeqCst);
+        assert!(dependency.call(true, failing_call).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_does_not_retry_invalid_responses() {
+        let dependency = Dependency::new("test", &settings()).unwrap();
+        let attempts = AtomicU32::new(0);
+        let result = dependency
+            .call(true, || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(SearchError::InvalidData("bad input".to_string()))
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(dependency.circuit_state(), CircuitState::Closed);
+
+        // nor do they reset the failures counted by the breaker
+        let failing_call =
+            || async { Err::<(), _>(SearchError::DependencyUnavailable("down".to_string())) };
+        assert!(dependency.call(false, failing_call).await.is_err());
+        let invalid_call = || async { Err::<(), _>(SearchError::InvalidData("bad".to_string())) };
+        assert!(dependency.call(false, invalid_call).await.is_err());
+        assert!(dependency.call(false, failing_call).await.is_err());
+        assert_eq!(dependency.circuit_state(), CircuitState::Open);
+    }
+}
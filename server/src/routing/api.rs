@@ -37,7 +37,9 @@ pub fn router(state: AppState) -> crate::Result<Router> {
             PostgresBackend,
             login_url = "/auth/session"
         ))
-        .nest("/auth", auth::routes());
+        .nest("/auth", auth::routes())
+        // Shared threads are read by people without an account
+        .nest("/shared", search::shared_routes());
 
     Ok(Router::new()
         .merge(api_routes)
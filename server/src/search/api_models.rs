@@ -1,3 +1,8 @@
+use crate::citations;
+use crate::custom_types::DateTime;
+use crate::export;
+use crate::llms;
+use crate::rag;
 use crate::search::{Search, Source, Thread};
 use reqwest::header::{InvalidHeaderName, InvalidHeaderValue};
 use serde::{Deserialize, Serialize};
@@ -37,30 +42,158 @@ pub enum RouteCategory {
     NotSpecified,
 }
 
-#[derive(Serialize, Deserialize, Debug, Validate)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Validate)]
 pub struct SearchQueryRequest {
     #[validate(length(min = 1, max = 300))]
     pub query: String,
     pub thread_id: Option<uuid::Uuid>,
+    /// Sources whose date, language or evidence level is unknown are left out when that field
+    /// is filtered on
+    #[serde(flatten)]
+    #[validate(nested)]
+    pub filters: rag::SearchFilters,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SearchByIdResponse {
     pub search: Search,
     pub sources: Vec<Source>,
+    /// References of the sources in the requested citation style, in the same order
+    pub citations: Option<Vec<String>>,
+    /// Counts of all the candidate sources of a new search, to refine it with filters
+    pub facets: Option<rag::SearchFacets>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchVerificationResponse {
+    pub search_id: uuid::Uuid,
+    pub faithfulness_score: f64,
+    pub sentences: Vec<llms::SentenceVerification>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchFollowUpResponse {
+    pub search_id: uuid::Uuid,
+    pub thread_id: uuid::Uuid,
+    pub questions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ThreadTitleResponse {
+    pub thread_id: uuid::Uuid,
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchSafetyResponse {
+    pub search_id: uuid::Uuid,
+    pub action: llms::SafetyAction,
+    pub violations: Vec<llms::SafetyViolation>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchModerationResponse {
+    pub search_id: uuid::Uuid,
+    pub outcome: llms::ModerationOutcome,
+}
+
+/// Messages streamed to the client over the search SSE connection. `Search` updates are sent as
+/// unnamed events so existing clients keep working; every other variant is a named event.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SearchStreamEvent {
+    Search(Box<SearchByIdResponse>),
+    Safety(SearchSafetyResponse),
+    Moderation(SearchModerationResponse),
+    Verification(SearchVerificationResponse),
+    ThreadTitle(ThreadTitleResponse),
+    FollowUp(SearchFollowUpResponse),
+}
+
+impl From<SearchByIdResponse> for SearchStreamEvent {
+    fn from(response: SearchByIdResponse) -> Self {
+        SearchStreamEvent::Search(Box::new(response))
+    }
+}
+
+impl SearchStreamEvent {
+    pub fn event_name(&self) -> Option<&'static str> {
+        match self {
+            SearchStreamEvent::Search(_) => None,
+            SearchStreamEvent::Safety(_) => Some("safety"),
+            SearchStreamEvent::Moderation(_) => Some("moderation"),
+            SearchStreamEvent::Verification(_) => Some("verification"),
+            SearchStreamEvent::ThreadTitle(_) => Some("thread_title"),
+            SearchStreamEvent::FollowUp(_) => Some("follow_up"),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        match self {
+            SearchStreamEvent::Search(response) => serde_json::to_string(response),
+            SearchStreamEvent::Safety(response) => serde_json::to_string(response),
+            SearchStreamEvent::Moderation(response) => serde_json::to_string(response),
+            SearchStreamEvent::Verification(response) => serde_json::to_string(response),
+            SearchStreamEvent::ThreadTitle(response) => serde_json::to_string(response),
+            SearchStreamEvent::FollowUp(response) => serde_json::to_string(response),
+        }
+        .unwrap_or("".to_string())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Validate)]
 pub struct ThreadHistoryRequest {
     #[validate(range(min = 1, max = 20))]
     pub limit: Option<u8>,
-    #[validate(range(min = 0))]
-    pub offset: Option<u8>,
+    /// `next_cursor` of the previous page, the first page when absent
+    pub cursor: Option<String>,
+    /// Archived threads are left out unless asked for
+    pub include_archived: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ThreadHistoryResponse {
     pub threads: Vec<Thread>,
+    /// Cursor of the next page, absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct HistorySearchRequest {
+    #[validate(length(min = 1, max = 400))]
+    pub query: String,
+    /// Only searches created at or after this time
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<time::OffsetDateTime>,
+    /// Only searches created before this time
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<time::OffsetDateTime>,
+    pub reaction: Option<bool>,
+    #[validate(range(min = 1, max = 20))]
+    pub limit: Option<u8>,
+    /// Number of results to skip, the results are ranked so there is no cursor to page with
+    #[validate(range(max = 10000))]
+    pub offset: Option<u32>,
+}
+
+/// A search matching the history query. The highlights are HTML, escaped text with the matched
+/// terms wrapped in `<mark>` tags.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistorySearchResult {
+    pub search_id: uuid::Uuid,
+    pub thread_id: uuid::Uuid,
+    pub thread_title: String,
+    pub query: String,
+    pub reaction: Option<bool>,
+    pub rank: f32,
+    pub title_highlight: String,
+    pub query_highlight: String,
+    pub result_highlight: String,
+    pub created_at: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistorySearchResponse {
+    pub results: Vec<HistorySearchResult>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Validate)]
@@ -68,19 +201,24 @@ pub struct GetThreadRequest {
     pub thread_id: uuid::Uuid,
     #[validate(range(min = 1, max = 20))]
     pub limit: Option<u8>,
-    #[validate(range(min = 0))]
-    pub offset: Option<u8>,
+    /// `next_cursor` of the previous page, the first page when absent
+    pub cursor: Option<String>,
+    /// Archived searches are left out unless asked for
+    pub include_archived: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SearchThreadResponse {
     pub thread: Thread,
     pub searches: Vec<SearchByIdResponse>,
+    /// Cursor of the next page of searches, absent on the last page
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SearchByIdRequest {
     pub search_id: uuid::Uuid,
+    pub citation_style: Option<citations::CitationStyle>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -96,6 +234,138 @@ pub struct UpdateThreadRequest {
     pub title: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct BulkThreadRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub thread_ids: Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct ArchiveThreadsRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub thread_ids: Vec<uuid::Uuid>,
+    /// Unarchives the threads when false
+    pub archived: bool,
+}
+
+/// Threads the operation applied to, leaving out the ones that are not found
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BulkThreadResponse {
+    pub thread_ids: Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct BulkSearchRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub search_ids: Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct ArchiveSearchesRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub search_ids: Vec<uuid::Uuid>,
+    /// Unarchives the searches when false
+    pub archived: bool,
+}
+
+/// Searches the operation applied to, leaving out the ones that are not found
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BulkSearchResponse {
+    pub search_ids: Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ShareThreadRequest {
+    pub thread_id: uuid::Uuid,
+    pub include_sources: bool,
+    /// The link never expires when absent
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<time::OffsetDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevokeSharedThreadRequest {
+    pub token: String,
+}
+
+/// Snapshot of a shared thread, as of when the link was created. It is public, so it carries no
+/// identifier of the user or of the rows.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SharedThreadResponse {
+    pub title: String,
+    pub searches: Vec<SharedSearch>,
+    pub created_at: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SharedSearch {
+    pub query: String,
+    pub result: String,
+    /// Empty when the link was created without sources
+    pub sources: Vec<SharedSource>,
+    pub created_at: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SharedSource {
+    pub title: String,
+    pub url: String,
+    pub description: Option<String>,
+}
+
+/// Exports either a whole thread or a single search.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportRequest {
+    pub thread_id: Option<uuid::Uuid>,
+    pub search_id: Option<uuid::Uuid>,
+    pub format: export::ExportFormat,
+    /// Lists the sources as references in this style rather than by title
+    pub citation_style: Option<citations::CitationStyle>,
+}
+
+/// Exports the citations of the sources of a search, a thread or a collection.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CitationExportRequest {
+    pub search_id: Option<uuid::Uuid>,
+    pub thread_id: Option<uuid::Uuid>,
+    pub collection_id: Option<uuid::Uuid>,
+    pub format: citations::CitationFormat,
+}
+
+/// Imports the references of an uploaded BibTeX or RIS file into a collection.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CollectionImportRequest {
+    pub collection_id: uuid::Uuid,
+    pub format: citations::CitationFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    /// Linked to a source that was already known
+    Matched,
+    Created,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportedEntry {
+    pub label: String,
+    pub title: Option<String>,
+    pub status: ImportStatus,
+    pub source_id: Option<uuid::Uuid>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CollectionImportResponse {
+    pub collection_id: uuid::Uuid,
+    pub matched: usize,
+    pub created: usize,
+    pub failed: usize,
+    pub entries: Vec<ImportedEntry>,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum SearchError {
     #[error(transparent)]
@@ -126,6 +396,8 @@ pub enum SearchError {
     NoResults(String),
     #[error("No sources: {0}")]
     NoSources(String),
+    #[error("Dependency unavailable: {0}")]
+    DependencyUnavailable(String),
     #[error("Other error: {0}")]
     Other(String),
 }
@@ -0,0 +1,85 @@
+use crate::search::SearchError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Keyset cursor pointing after the row with this `created_at` and id, in `created_at desc, id
+/// desc` order. Rows inserted while paging do not shift the pages that follow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub created_at: OffsetDateTime,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    /// Encodes the cursor as an opaque url-safe string.
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!(
+            "{}:{}",
+            self.created_at.unix_timestamp_nanos(),
+            self.id
+        ))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, SearchError> {
+        let invalid_cursor = || SearchError::InvalidData("Invalid cursor".to_string());
+        let decoded = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map_err(|_| invalid_cursor())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid_cursor())?;
+        let (created_at, id) = decoded.split_once(':').ok_or_else(invalid_cursor)?;
+
+        let created_at = created_at.parse::<i128>().map_err(|_| invalid_cursor())?;
+        Ok(Cursor {
+            created_at: OffsetDateTime::from_unix_timestamp_nanos(created_at)
+                .map_err(|_| invalid_cursor())?,
+            id: id.parse().map_err(|_| invalid_cursor())?,
+        })
+    }
+}
+
+/// Decodes the optional cursor of a request into the values of its keyset condition.
+pub fn decode_cursor(
+    cursor: &Option<String>,
+) -> Result<(Option<OffsetDateTime>, Option<Uuid>), SearchError> {
+    match cursor {
+        Some(cursor) => {
+            let cursor = Cursor::decode(cursor)?;
+            Ok((Some(cursor.created_at), Some(cursor.id)))
+        }
+        None => Ok((None, None)),
+    }
+}
+
+/// Cuts the rows fetched with one extra row down to `limit`, and returns the cursor of the next
+/// page when there is one.
+pub fn next_cursor<T>(
+    rows: &mut Vec<T>,
+    limit: usize,
+    cursor: impl Fn(&T) -> Cursor,
+) -> Option<String> {
+    if rows.len() <= limit {
+        return None;
+    }
+    rows.truncate(limit);
+    rows.last().map(|row| cursor(row).encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = Cursor {
+            created_at: OffsetDateTime::from_unix_timestamp_nanos(1_729_327_800_123_456_000)
+                .unwrap(),
+            id: Uuid::new_v4(),
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+        assert!(Cursor::decode("not-a-cursor").is_err());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode("1:not-a-uuid")).is_err());
+    }
+}
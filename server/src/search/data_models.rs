@@ -25,6 +25,11 @@ pub struct Thread {
     pub user_id: uuid::Uuid,
     pub title: String,
     pub context: Option<serde_json::Value>,
+    pub title_set_by_user: bool,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub archived_at: Option<time::OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub deleted_at: Option<time::OffsetDateTime>,
 
     pub created_at: DateTime,
     pub updated_at: DateTime,
@@ -39,6 +44,14 @@ pub struct Search {
     pub result: String,
     pub media_urls: Option<Vec<String>>,
     pub reaction: Option<bool>,
+    pub faithfulness_score: Option<f64>,
+    pub verification: Option<serde_json::Value>,
+    pub moderation: Option<serde_json::Value>,
+    pub follow_up_questions: Option<Vec<String>>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub archived_at: Option<time::OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub deleted_at: Option<time::OffsetDateTime>,
 
     pub created_at: DateTime,
     pub updated_at: DateTime,
@@ -62,6 +75,51 @@ pub struct SearchSource {
     pub search_source_id: uuid::Uuid,
     pub search_id: uuid::Uuid,
     pub source_id: uuid::Uuid,
+    pub position: i32,
+
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct SafetyAuditLog {
+    pub safety_audit_log_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub query: String,
+    pub action: String,
+    pub scores: serde_json::Value,
+    pub violations: serde_json::Value,
+
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct SharedThread {
+    pub shared_thread_id: uuid::Uuid,
+    pub thread_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub token: String,
+    pub include_sources: bool,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<time::OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub revoked_at: Option<time::OffsetDateTime>,
+
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct Retraction {
+    pub retraction_id: uuid::Uuid,
+    pub record_id: String,
+    pub doi: Option<String>,
+    pub pmid: Option<String>,
+    pub nature: String,
+    pub notice_doi: Option<String>,
+    pub reason: Option<String>,
+    pub retracted_on: Option<time::Date>,
 
     pub created_at: DateTime,
     pub updated_at: DateTime,
@@ -1,9 +1,13 @@
 pub use api_models::*;
+pub use cursor::*;
 pub use data_models::*;
+pub use purge::*;
 pub use routes::*;
 pub use services::*;
 
 pub mod api_models;
+pub mod cursor;
 pub mod data_models;
+pub mod purge;
 pub mod routes;
 pub mod services;
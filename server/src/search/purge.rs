@@ -0,0 +1,30 @@
+use crate::search::services;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgeSettings {
+    pub enabled: bool,
+    /// Days a deleted thread or search can still be restored before it is removed for good
+    pub retention_days: i32,
+    pub interval_secs: u64,
+}
+
+/// Periodically hard deletes the threads and searches whose retention window has passed.
+pub fn spawn_purge_job(pool: PgPool, settings: PurgeSettings) {
+    if !settings.enabled {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(settings.interval_secs));
+        loop {
+            interval.tick().await;
+            // a failed run is retried on the next tick
+            if let Err(e) = services::purge_deleted(&pool, settings.retention_days).await {
+                tracing::error!("Failed to purge deleted threads and searches: {}", e);
+            }
+        }
+    });
+}
@@ -1,11 +1,16 @@
-use crate::llms;
+use crate::citations;
+use crate::export;
+use crate::llms::{self, summarizer};
 use crate::rag::{self, post_process, pre_process};
-use crate::search::{api_models, services, SearchError};
+use crate::search::{api_models, data_models, services, SearchError};
+use crate::settings::Settings;
 use crate::startup::AppState;
 use crate::users::User;
-use axum::extract::{Query, State};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap};
 use axum::response::sse::{Event, KeepAlive, Sse};
-use axum::routing::{get, patch};
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, patch, post};
 use axum::{Json, Router};
 use futures::{stream::StreamExt, Stream};
 use sqlx::PgPool;
@@ -14,6 +19,53 @@ use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use validator::Validate;
 
+fn search_event_stream(
+    rx: mpsc::Receiver<api_models::SearchStreamEvent>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = ReceiverStream::new(rx).map(move |msg: api_models::SearchStreamEvent| {
+        let event = match msg.event_name() {
+            Some(event_name) => Event::default().event(event_name),
+            None => Event::default(),
+        };
+        Ok(event.data(msg.to_json()))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(std::time::Duration::from_secs(30)))
+}
+
+/// Stores a search answered with a fixed response instead of running RAG, and streams it back.
+#[tracing::instrument(level = "info", skip(pool), err)]
+async fn answer_with_safe_response(
+    pool: &PgPool,
+    user_id: &uuid::Uuid,
+    search_query_request: &api_models::SearchQueryRequest,
+    response: &str,
+) -> Result<mpsc::Receiver<api_models::SearchStreamEvent>, SearchError> {
+    let search_item = services::insert_new_search(
+        pool,
+        user_id,
+        search_query_request,
+        &search_query_request.query,
+    )
+    .await?;
+    let search_item = services::append_search_result(pool, &search_item, response).await?;
+
+    let (tx, rx) = mpsc::channel(1);
+    tx.send(
+        api_models::SearchByIdResponse {
+            search: search_item,
+            sources: vec![],
+            citations: None,
+            facets: None,
+        }
+        .into(),
+    )
+    .await
+    .map_err(|e| SearchError::Other(format!("Failed to send search result: {}", e)))?;
+
+    Ok(rx)
+}
+
 #[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
 async fn get_search_query_handler(
     State(AppState {
@@ -22,10 +74,12 @@ async fn get_search_query_handler(
         settings,
         brave_config,
         openai_stream_regex,
+        dependencies,
         ..
     }): State<AppState>,
     State(pool): State<PgPool>,
     user: User,
+    headers: HeaderMap,
     Query(search_query_request): Query<api_models::SearchQueryRequest>,
 ) -> crate::Result<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
     search_query_request
@@ -33,32 +87,123 @@ async fn get_search_query_handler(
         .map_err(|e| SearchError::InvalidData(format!("Invalid search query: {}", e)))?;
     let user_id = user.user_id;
 
-    let (query_toxicity, rephrased_query) = tokio::join!(
-        llms::toxicity::predict_toxicity(
+    // Patient details must not reach third-party services, so every outbound call uses the
+    // redacted query. The deployment decides which one is stored.
+    let redacted_query_request = api_models::SearchQueryRequest {
+        query: llms::redact_pii(&settings.redaction, &search_query_request.query),
+        thread_id: search_query_request.thread_id,
+        filters: search_query_request.filters.clone(),
+    };
+    let stored_query_request = match settings.redaction.stored_query {
+        llms::StoredQuery::Original => &search_query_request,
+        llms::StoredQuery::Redacted => &redacted_query_request,
+    };
+
+    let conversation_memory = match (
+        settings.conversation_memory.enabled,
+        search_query_request.thread_id,
+    ) {
+        (true, Some(thread_id)) => services::get_thread_memory(&pool, &user_id, &thread_id)
+            .await?
+            .unwrap_or_default(),
+        _ => llms::ConversationMemory::default(),
+    };
+    let conversation_memory_prompt = llms::format_conversation_memory(
+        &settings.query_rephraser.model,
+        &settings.conversation_memory,
+        &conversation_memory,
+    )?;
+
+    let (query_crisis, query_safety, rephrased_query, chat_history) = tokio::join!(
+        llms::detect_crisis(
+            &dependencies.crisis_classifier,
+            &settings.crisis,
+            &redacted_query_request.query
+        ),
+        llms::assess_query_safety(
+            &dependencies.toxicity,
             &settings.llm,
-            llms::ToxicityInput {
-                inputs: search_query_request.query.to_string(),
-            }
+            &settings.safety_policy,
+            &redacted_query_request.query
         ),
-        pre_process::rephrase_query(&pool, &settings, &search_query_request)
+        pre_process::rephrase_query(
+            &pool,
+            &dependencies,
+            &settings,
+            &redacted_query_request,
+            &conversation_memory_prompt
+        ),
+        pre_process::chat_history(&pool, &settings, &redacted_query_request)
     );
 
-    if let Ok(true) = query_toxicity {
-        return Err(SearchError::ToxicQuery("Query is too toxic to proceed".to_string()).into());
+    // Crisis queries get emergency resources instead of a refusal or a regular answer
+    if let Ok(Some(_)) = query_crisis {
+        let accept_language = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let rx = answer_with_safe_response(
+            &pool,
+            &user_id,
+            stored_query_request,
+            llms::crisis_response(&settings.crisis, accept_language),
+        )
+        .await?;
+        return Ok(search_event_stream(rx));
     }
+
+    // An unavailable classifier does not block the search, same as before the safety policy
+    let query_safety = query_safety.unwrap_or_default();
+    let safety_action = query_safety.action();
+    if let Some(action) = &safety_action {
+        services::insert_safety_audit_log(
+            &pool,
+            &user_id,
+            &stored_query_request.query,
+            action,
+            &query_safety,
+        )
+        .await?;
+    }
+
+    match safety_action {
+        Some(llms::SafetyAction::Block) => {
+            return Err(
+                SearchError::ToxicQuery("Query is too toxic to proceed".to_string()).into(),
+            );
+        }
+        Some(llms::SafetyAction::SafeCompletion) => {
+            let rx = answer_with_safe_response(
+                &pool,
+                &user_id,
+                stored_query_request,
+                &settings.safety_policy.safe_completion_template,
+            )
+            .await?;
+            return Ok(search_event_stream(rx));
+        }
+        _ => {}
+    }
+
     let rephrased_query = match rephrased_query {
         Ok(rephrased_query) => rephrased_query,
-        _ => search_query_request.query.clone(),
+        _ => redacted_query_request.query.clone(),
+    };
+    let rephrased_query_request = api_models::SearchQueryRequest {
+        query: rephrased_query.clone(),
+        ..redacted_query_request.clone()
     };
 
     let (search_item, search_response) = tokio::join!(
-        services::insert_new_search(&pool, &user_id, &search_query_request, &rephrased_query),
+        services::insert_new_search(&pool, &user_id, stored_query_request, &rephrased_query),
         rag::search(
             &settings,
+            &pool,
+            &dependencies,
             &brave_config,
             &cache,
             &agency_service,
-            &rephrased_query
+            &rephrased_query_request
         )
     );
     let search_item = search_item?;
@@ -68,16 +213,23 @@ async fn get_search_query_handler(
         services::add_search_sources(&pool, &search_item, &search_response.sources).await?;
 
     let (tx, rx) = mpsc::channel(1);
-    tx.send(api_models::SearchByIdResponse {
-        search: search_item.clone(),
-        sources,
-    })
+    tx.send(
+        api_models::SearchByIdResponse {
+            search: search_item.clone(),
+            sources,
+            citations: None,
+            facets: Some(search_response.facets),
+        }
+        .into(),
+    )
     .await
     .map_err(|e| SearchError::Other(format!("Failed to send search result: {}", e)))?;
 
+    let update_pool = pool.clone();
+    let update_search_item = search_item.clone();
     let update_processor = api_models::UpdateResultProcessor::new(Arc::new(move |result_suffix| {
-        let pool_clone = pool.clone();
-        let search_item_clone = search_item.clone();
+        let pool_clone = update_pool.clone();
+        let search_item_clone = update_search_item.clone();
         Box::pin(async move {
             let search =
                 services::append_search_result(&pool_clone, &search_item_clone, &result_suffix)
@@ -86,21 +238,127 @@ async fn get_search_query_handler(
         })
     }));
 
-    tokio::spawn(post_process::summarize_search_results(
-        settings.clone(),
-        search_query_request,
-        search_response.result,
-        update_processor,
-        openai_stream_regex,
-        tx,
-    ));
+    tokio::spawn(async move {
+        if let Some(action) = safety_action {
+            tx.send(api_models::SearchStreamEvent::Safety(
+                api_models::SearchSafetyResponse {
+                    search_id: search_item.search_id,
+                    action,
+                    violations: query_safety.violations,
+                },
+            ))
+            .await
+            .map_err(|e| SearchError::Other(format!("Failed to send safety warning: {}", e)))?;
+        }
+
+        let mut moderator = llms::AnswerModerator::new(
+            dependencies.toxicity.clone(),
+            settings.llm.clone(),
+            settings.moderation.clone(),
+        );
+        let answer = post_process::summarize_search_results(
+            settings.clone(),
+            &dependencies,
+            summarizer::SummarizerInput {
+                query: redacted_query_request.query.clone(),
+                retrieved_result: search_response.result,
+                conversation_memory: conversation_memory_prompt,
+                // the answer is still useful without the earlier turns
+                chat_history: chat_history.unwrap_or_default(),
+            },
+            update_processor,
+            openai_stream_regex,
+            &mut moderator,
+            tx.clone(),
+        )
+        .await?;
 
-    let stream = ReceiverStream::new(rx).map(move |msg: api_models::SearchByIdResponse| {
-        let json_data = serde_json::to_string(&msg).unwrap_or("".to_string());
-        Ok(Event::default().data(json_data))
+        // the answer is out already, so a failed step is logged and does not stop the next ones
+        if moderator.is_enabled() {
+            if let Err(e) = post_process::record_moderation_outcome(
+                &pool,
+                &search_item,
+                moderator.outcome(),
+                &tx,
+            )
+            .await
+            {
+                tracing::error!("Failed to record moderation outcome: {}", e);
+            }
+        }
+
+        // only new threads are titled, a title is never regenerated
+        if settings.thread_title.enabled && search_query_request.thread_id.is_none() {
+            if let Err(e) = post_process::record_thread_title(
+                &pool,
+                &dependencies,
+                &settings,
+                &search_item,
+                &redacted_query_request.query,
+                &answer,
+                &tx,
+            )
+            .await
+            {
+                tracing::error!("Failed to record thread title: {}", e);
+            }
+        }
+
+        if settings.verification.enabled {
+            if let Err(e) = post_process::verify_search_result(
+                &pool,
+                &dependencies,
+                &settings.verification,
+                &search_item,
+                &answer,
+                &search_response.sources,
+                &tx,
+            )
+            .await
+            {
+                tracing::error!("Failed to verify search result: {}", e);
+            }
+        }
+
+        if settings.follow_up.enabled {
+            if let Err(e) = post_process::suggest_follow_up_questions(
+                &pool,
+                &dependencies,
+                &settings,
+                &search_item,
+                &answer,
+                &search_response.sources,
+                &tx,
+            )
+            .await
+            {
+                tracing::error!("Failed to suggest follow-up questions: {}", e);
+            }
+        }
+
+        if settings.conversation_memory.enabled {
+            let turn = llms::ConversationTurn {
+                query: redacted_query_request.query,
+                cited_sources: llms::cited_sources(&answer, &search_response.sources),
+                answer,
+            };
+            if let Err(e) = post_process::record_conversation_memory(
+                &pool,
+                &dependencies,
+                &settings,
+                &search_item,
+                conversation_memory,
+                turn,
+            )
+            .await
+            {
+                tracing::error!("Failed to record conversation memory: {}", e);
+            }
+        }
+        Ok::<(), SearchError>(())
     });
 
-    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(std::time::Duration::from_secs(30))))
+    Ok(search_event_stream(rx))
 }
 
 #[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
@@ -129,6 +387,21 @@ async fn get_threads_handler(
     Ok(Json(search_history))
 }
 
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn search_history_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Query(history_search_request): Query<api_models::HistorySearchRequest>,
+) -> crate::Result<Json<api_models::HistorySearchResponse>> {
+    history_search_request
+        .validate()
+        .map_err(|e| SearchError::InvalidData(format!("Invalid history search request: {}", e)))?;
+
+    let search_results =
+        services::search_history(&pool, &user.user_id, &history_search_request).await?;
+    Ok(Json(search_results))
+}
+
 #[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
 async fn get_one_thread_handler(
     State(pool): State<PgPool>,
@@ -157,6 +430,243 @@ async fn update_thread_handler(
     Ok(())
 }
 
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn archive_threads_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Json(archive_threads_request): Json<api_models::ArchiveThreadsRequest>,
+) -> crate::Result<Json<api_models::BulkThreadResponse>> {
+    archive_threads_request
+        .validate()
+        .map_err(|e| SearchError::InvalidData(format!("Invalid archive threads request: {}", e)))?;
+
+    let threads = services::archive_threads(&pool, &user.user_id, &archive_threads_request).await?;
+    Ok(Json(threads))
+}
+
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn delete_threads_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Json(threads_request): Json<api_models::BulkThreadRequest>,
+) -> crate::Result<Json<api_models::BulkThreadResponse>> {
+    threads_request
+        .validate()
+        .map_err(|e| SearchError::InvalidData(format!("Invalid delete threads request: {}", e)))?;
+
+    let threads = services::delete_threads(&pool, &user.user_id, &threads_request).await?;
+    Ok(Json(threads))
+}
+
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn restore_threads_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Json(threads_request): Json<api_models::BulkThreadRequest>,
+) -> crate::Result<Json<api_models::BulkThreadResponse>> {
+    threads_request
+        .validate()
+        .map_err(|e| SearchError::InvalidData(format!("Invalid restore threads request: {}", e)))?;
+
+    let threads = services::restore_threads(&pool, &user.user_id, &threads_request).await?;
+    Ok(Json(threads))
+}
+
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn delete_searches_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Json(searches_request): Json<api_models::BulkSearchRequest>,
+) -> crate::Result<Json<api_models::BulkSearchResponse>> {
+    searches_request
+        .validate()
+        .map_err(|e| SearchError::InvalidData(format!("Invalid delete searches request: {}", e)))?;
+
+    let searches = services::delete_searches(&pool, &user.user_id, &searches_request).await?;
+    Ok(Json(searches))
+}
+
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn archive_searches_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Json(archive_searches_request): Json<api_models::ArchiveSearchesRequest>,
+) -> crate::Result<Json<api_models::BulkSearchResponse>> {
+    archive_searches_request.validate().map_err(|e| {
+        SearchError::InvalidData(format!("Invalid archive searches request: {}", e))
+    })?;
+
+    let searches =
+        services::archive_searches(&pool, &user.user_id, &archive_searches_request).await?;
+    Ok(Json(searches))
+}
+
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn restore_searches_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Json(searches_request): Json<api_models::BulkSearchRequest>,
+) -> crate::Result<Json<api_models::BulkSearchResponse>> {
+    searches_request.validate().map_err(|e| {
+        SearchError::InvalidData(format!("Invalid restore searches request: {}", e))
+    })?;
+
+    let searches = services::restore_searches(&pool, &user.user_id, &searches_request).await?;
+    Ok(Json(searches))
+}
+
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn share_thread_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Json(share_thread_request): Json<api_models::ShareThreadRequest>,
+) -> crate::Result<Json<data_models::SharedThread>> {
+    if let Some(expires_at) = share_thread_request.expires_at {
+        if expires_at <= time::OffsetDateTime::now_utc() {
+            return Err(SearchError::InvalidData(
+                "Invalid share thread request: expires_at is in the past".to_string(),
+            )
+            .into());
+        }
+    }
+
+    let shared_thread = services::share_thread(&pool, &user.user_id, &share_thread_request).await?;
+    Ok(Json(shared_thread))
+}
+
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn revoke_shared_thread_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Json(revoke_request): Json<api_models::RevokeSharedThreadRequest>,
+) -> crate::Result<()> {
+    services::revoke_shared_thread(&pool, &user.user_id, &revoke_request).await?;
+    Ok(())
+}
+
+// Public, the token is the only credential
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn get_shared_thread_handler(
+    State(pool): State<PgPool>,
+    Path(token): Path<String>,
+) -> crate::Result<Json<api_models::SharedThreadResponse>> {
+    let shared_thread = services::get_shared_thread(&pool, &token).await?;
+    Ok(Json(shared_thread))
+}
+
+#[tracing::instrument(level = "info", skip_all, err(Debug))]
+async fn export_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Query(export_request): Query<api_models::ExportRequest>,
+) -> crate::Result<impl IntoResponse> {
+    if export_request.thread_id.is_some() == export_request.search_id.is_some() {
+        return Err(SearchError::InvalidData(
+            "Invalid export request: exactly one of thread_id and search_id is required"
+                .to_string(),
+        )
+        .into());
+    }
+
+    let document = services::get_export_document(&pool, &user.user_id, &export_request).await?;
+    let file_name = export::export_file_name(&document.title, export_request.format);
+    let body = export::render_export(export_request.format, &document);
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                export_request.format.content_type().to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file_name),
+            ),
+        ],
+        body,
+    ))
+}
+
+#[tracing::instrument(level = "info", skip_all, err(Debug))]
+async fn citations_handler(
+    State(pool): State<PgPool>,
+    user: User,
+    Query(citation_request): Query<api_models::CitationExportRequest>,
+) -> crate::Result<impl IntoResponse> {
+    let ids = [
+        citation_request.search_id,
+        citation_request.thread_id,
+        citation_request.collection_id,
+    ];
+    if ids.iter().filter(|id| id.is_some()).count() != 1 {
+        return Err(SearchError::InvalidData(
+            "Invalid citation request: exactly one of search_id, thread_id and collection_id is required"
+                .to_string(),
+        )
+        .into());
+    }
+
+    let sources = services::get_citation_sources(&pool, &user.user_id, &citation_request).await?;
+    let records: Vec<citations::CitationRecord> = sources
+        .iter()
+        .map(citations::CitationRecord::from_source)
+        .collect();
+    let body = citations::render_citations(citation_request.format, &records);
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                citation_request.format.content_type().to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"citations.{}\"",
+                    citation_request.format.file_extension()
+                ),
+            ),
+        ],
+        body,
+    ))
+}
+
+#[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
+async fn import_collection_handler(
+    State(settings): State<Settings>,
+    State(pool): State<PgPool>,
+    user: User,
+    Query(import_request): Query<api_models::CollectionImportRequest>,
+    body: String,
+) -> crate::Result<Json<api_models::CollectionImportResponse>> {
+    let entries = match import_request.format {
+        citations::CitationFormat::Bibtex => citations::parse_bibtex(&body),
+        citations::CitationFormat::Ris => citations::parse_ris(&body),
+        citations::CitationFormat::CslJson => {
+            return Err(SearchError::InvalidData(
+                "Invalid import request: only BibTeX and RIS files can be imported".to_string(),
+            )
+            .into());
+        }
+    };
+    if entries.is_empty() {
+        return Err(SearchError::InvalidData(
+            "Invalid import request: the file has no entries".to_string(),
+        )
+        .into());
+    }
+
+    let report = services::import_collection_sources(
+        &pool,
+        &user.user_id,
+        &import_request,
+        entries,
+        &settings.pubmed.url_prefix,
+    )
+    .await?;
+    Ok(Json(report))
+}
+
 #[tracing::instrument(level = "info", skip_all, ret, err(Debug))]
 async fn update_search_reaction_handler(
     State(pool): State<PgPool>,
@@ -170,9 +680,26 @@ async fn update_search_reaction_handler(
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(get_search_query_handler))
+        .route("/", delete(delete_searches_handler))
+        .route("/archive", patch(archive_searches_handler))
+        .route("/restore", patch(restore_searches_handler))
         .route("/one", get(get_one_search_result_handler))
         .route("/threads", get(get_one_thread_handler))
         .route("/threads", patch(update_thread_handler))
+        .route("/threads", delete(delete_threads_handler))
+        .route("/threads/archive", patch(archive_threads_handler))
+        .route("/threads/restore", patch(restore_threads_handler))
+        .route("/threads/share", post(share_thread_handler))
+        .route("/threads/share", delete(revoke_shared_thread_handler))
         .route("/history", get(get_threads_handler))
+        .route("/history/search", get(search_history_handler))
         .route("/reaction", patch(update_search_reaction_handler))
+        .route("/export", get(export_handler))
+        .route("/citations", get(citations_handler))
+        .route("/collections/import", post(import_collection_handler))
+}
+
+/// Routes served without a session, so they must stay outside of `login_required!`.
+pub fn shared_routes() -> Router<AppState> {
+    Router::new().route("/:token", get(get_shared_thread_handler))
 }
@@ -1,7 +1,13 @@
-use crate::rag::Source;
-use crate::search::{api_models, data_models, SearchError};
+use crate::citations;
+use crate::export;
+use crate::llms;
+use crate::rag::{self, Source};
+use crate::search::{api_models, cursor, data_models, SearchError, SourceType};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::Rng;
 use sqlx::PgPool;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 type Result<T> = std::result::Result<T, SearchError>;
@@ -17,7 +23,8 @@ pub async fn insert_new_search(
         Some(thread_id) => {
             sqlx::query_as!(
                 data_models::Thread,
-                "select * from threads where thread_id = $1 and user_id = $2",
+                "select * from threads where thread_id = $1 and user_id = $2 \
+                    and deleted_at is null",
                 thread_id,
                 user_id,
             )
@@ -50,6 +57,30 @@ pub async fn insert_new_search(
     return Ok(search);
 }
 
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn insert_safety_audit_log(
+    pool: &PgPool,
+    user_id: &Uuid,
+    query: &str,
+    action: &llms::SafetyAction,
+    assessment: &llms::SafetyAssessment,
+) -> Result<data_models::SafetyAuditLog> {
+    let safety_audit_log = sqlx::query_as!(
+        data_models::SafetyAuditLog,
+        "insert into safety_audit_logs (user_id, query, action, scores, violations) \
+            values ($1, $2, $3, $4, $5) returning *",
+        user_id,
+        query,
+        action.as_str(),
+        serde_json::to_value(&assessment.scores)?,
+        serde_json::to_value(&assessment.violations)?,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(safety_audit_log)
+}
+
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn append_search_result(
     pool: &PgPool,
@@ -69,6 +100,65 @@ pub async fn append_search_result(
     Ok(search)
 }
 
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn update_search_moderation(
+    pool: &PgPool,
+    search: &data_models::Search,
+    outcome: &llms::ModerationOutcome,
+) -> Result<data_models::Search> {
+    // Only used by internal services, so no need to check if user_id is the owner of the search
+    let search = sqlx::query_as!(
+        data_models::Search,
+        "update searches set moderation = $1 where search_id = $2 returning *",
+        serde_json::to_value(outcome)?,
+        search.search_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(search)
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn update_search_verification(
+    pool: &PgPool,
+    search: &data_models::Search,
+    verification: &llms::VerificationOutput,
+) -> Result<data_models::Search> {
+    // Only used by internal services, so no need to check if user_id is the owner of the search
+    let search = sqlx::query_as!(
+        data_models::Search,
+        "update searches set faithfulness_score = $1, verification = $2 \
+            where search_id = $3 returning *",
+        verification.faithfulness_score,
+        serde_json::to_value(&verification.sentences)?,
+        search.search_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(search)
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn update_search_follow_up_questions(
+    pool: &PgPool,
+    search: &data_models::Search,
+    questions: &[String],
+) -> Result<data_models::Search> {
+    // Only used by internal services, so no need to check if user_id is the owner of the search
+    let search = sqlx::query_as!(
+        data_models::Search,
+        "update searches set follow_up_questions = $1 where search_id = $2 returning *",
+        questions,
+        search.search_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(search)
+}
+
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn add_search_sources(
     pool: &PgPool,
@@ -92,16 +182,22 @@ pub async fn add_search_sources(
         })
         .collect::<Vec<_>>();
 
+    let urls = sources
+        .iter()
+        .map(|s| s.url.clone())
+        .collect::<Vec<String>>();
+
     // Only used by internal services, so no need to check if user_id is the owner of the search
     let sources = sqlx::query_as!(
         data_models::Source,
         "insert into sources (title, description, url, source_type, metadata) \
             select * from unnest($1::text[], $2::text[], $3::text[], $4::int[], $5::jsonb[]) \
             on conflict (url) do update set title = excluded.title, description = excluded.description, \
-            source_type = excluded.source_type, metadata = excluded.metadata returning *",
+            source_type = excluded.source_type, \
+            metadata = coalesce(sources.metadata, '{}'::jsonb) || excluded.metadata returning *",
         &sources.iter().map(|s| s.title.clone()).collect::<Vec<String>>(),
         &sources.iter().map(|s| s.description.clone()).collect::<Vec<String>>(),
-        &sources.iter().map(|s| s.url.clone()).collect::<Vec<String>>(),
+        &urls,
         &sources.iter().map(|s| s.source_type.clone() as i32).collect::<Vec<i32>>(),
         &sources.iter().map(|s| serde_json::to_value(
             s.metadata.clone()
@@ -110,11 +206,21 @@ pub async fn add_search_sources(
     .fetch_all(pool)
     .await?;
 
+    // the upsert does not return the sources in the order of the input, which is their rank
+    let positions: HashMap<&String, i32> = urls
+        .iter()
+        .enumerate()
+        .map(|(position, url)| (url, position as i32))
+        .collect();
+    let mut sources = sources;
+    sources.sort_by_key(|source| positions.get(&source.url).copied().unwrap_or(i32::MAX));
+
     sqlx::query!(
-        "insert into search_sources (search_id, source_id) \
-            select * from unnest($1::uuid[], $2::uuid[])",
+        "insert into search_sources (search_id, source_id, position) \
+            select * from unnest($1::uuid[], $2::uuid[], $3::int[])",
         &vec![search.search_id; sources.len()],
         &sources.iter().map(|s| s.source_id).collect::<Vec<Uuid>>(),
+        &(0..sources.len() as i32).collect::<Vec<i32>>(),
     )
     .fetch_all(pool)
     .await?;
@@ -132,7 +238,8 @@ pub async fn get_one_search(
         data_models::Search,
         "select s.* from searches s \
             inner join threads t on s.thread_id = t.thread_id \
-            where s.search_id = $1 and t.user_id = $2",
+            where s.search_id = $1 and t.user_id = $2 \
+            and s.deleted_at is null and t.deleted_at is null",
         search_by_id_request.search_id,
         user_id,
     )
@@ -143,13 +250,27 @@ pub async fn get_one_search(
         data_models::Source,
         "select s.* from sources s \
             inner join search_sources ss on s.source_id = ss.source_id \
-            where ss.search_id = $1",
+            where ss.search_id = $1 order by ss.position",
         search.search_id,
     )
     .fetch_all(pool)
     .await?;
 
-    return Ok(api_models::SearchByIdResponse { search, sources });
+    let citations = search_by_id_request.citation_style.map(|style| {
+        sources
+            .iter()
+            .map(|source| {
+                citations::format_citation(style, &citations::CitationRecord::from_source(source))
+            })
+            .collect()
+    });
+
+    return Ok(api_models::SearchByIdResponse {
+        search,
+        sources,
+        citations,
+        facets: None,
+    });
 }
 
 #[tracing::instrument(level = "info", ret, err)]
@@ -162,7 +283,7 @@ pub async fn get_last_n_searches(
     let searches = sqlx::query_as!(
         data_models::Search,
         "select s.* from searches s \
-            where s.thread_id = $1 \
+            where s.thread_id = $1 and s.deleted_at is null \
             order by s.created_at desc limit $2",
         thread_id,
         last_n as i64,
@@ -173,23 +294,169 @@ pub async fn get_last_n_searches(
     return Ok(searches);
 }
 
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn get_thread_memory(
+    pool: &PgPool,
+    user_id: &Uuid,
+    thread_id: &Uuid,
+) -> Result<Option<llms::ConversationMemory>> {
+    let thread = sqlx::query_as!(
+        data_models::Thread,
+        "select * from threads where thread_id = $1 and user_id = $2 and deleted_at is null",
+        thread_id,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match thread.and_then(|t| t.context) {
+        Some(context) => Ok(Some(serde_json::from_value(context)?)),
+        None => Ok(None),
+    }
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn update_thread_memory(
+    pool: &PgPool,
+    search: &data_models::Search,
+    memory: &llms::ConversationMemory,
+) -> Result<data_models::Thread> {
+    // Only used by internal services, so no need to check if user_id is the owner of the thread
+    let thread = sqlx::query_as!(
+        data_models::Thread,
+        "update threads set context = $1 where thread_id = $2 returning *",
+        serde_json::to_value(memory)?,
+        search.thread_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(thread)
+}
+
+/// Replaces the title of the thread with a generated one, unless the user has set it. Returns
+/// `None` when the title was kept.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn update_generated_thread_title(
+    pool: &PgPool,
+    search: &data_models::Search,
+    title: &str,
+) -> Result<Option<data_models::Thread>> {
+    // Only used by internal services, so no need to check if user_id is the owner of the thread
+    let thread = sqlx::query_as!(
+        data_models::Thread,
+        "update threads set title = $1 where thread_id = $2 and not title_set_by_user returning *",
+        title,
+        search.thread_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(thread)
+}
+
 #[tracing::instrument(level = "info", ret, err)]
 pub async fn get_threads(
     pool: &PgPool,
     user_id: &Uuid,
     thread_history_request: &api_models::ThreadHistoryRequest,
 ) -> Result<api_models::ThreadHistoryResponse> {
-    let threads = sqlx::query_as!(
+    let limit = thread_history_request.limit.unwrap_or(10) as usize;
+    let (cursor_created_at, cursor_thread_id) =
+        cursor::decode_cursor(&thread_history_request.cursor)?;
+
+    // one more thread than asked for tells whether there is a next page
+    let mut threads = sqlx::query_as!(
         data_models::Thread,
-        "select * from threads where user_id = $1 order by created_at desc limit $2 offset $3",
+        "select * from threads where user_id = $1 and deleted_at is null \
+            and ($2 or archived_at is null) \
+            and ($3::timestamptz is null or (created_at, thread_id) < ($3, $4::uuid)) \
+            order by created_at desc, thread_id desc limit $5",
+        user_id,
+        thread_history_request.include_archived.unwrap_or(false),
+        cursor_created_at,
+        cursor_thread_id,
+        limit as i64 + 1,
+    )
+    .fetch_all(pool)
+    .await?;
+    let next_cursor = cursor::next_cursor(&mut threads, limit, |thread| cursor::Cursor {
+        created_at: thread.created_at.0,
+        id: thread.thread_id,
+    });
+
+    return Ok(api_models::ThreadHistoryResponse {
+        threads,
+        next_cursor,
+    });
+}
+
+/// Searches the user's history by relevance to the query, matching the questions, their answers
+/// and the thread titles.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn search_history(
+    pool: &PgPool,
+    user_id: &Uuid,
+    history_search_request: &api_models::HistorySearchRequest,
+) -> Result<api_models::HistorySearchResponse> {
+    // the tsvector expressions match the GIN indexes on searches and threads
+    let results = sqlx::query_as!(
+        api_models::HistorySearchResult,
+        r#"select s.search_id, s.thread_id, t.title as thread_title, s.query, s.reaction,
+            ts_rank(
+                setweight(to_tsvector('english', s.query), 'A') ||
+                setweight(to_tsvector('english', s.rephrased_query), 'B') ||
+                setweight(to_tsvector('english', s.result), 'C'),
+                q
+            ) + ts_rank(setweight(to_tsvector('english', t.title), 'A'), q) as "rank!",
+            ts_headline('english', t.title, q, 'HighlightAll=true, StartSel=' || chr(2) || ', StopSel=' || chr(3)) as "title_highlight!",
+            ts_headline('english', s.query, q, 'HighlightAll=true, StartSel=' || chr(2) || ', StopSel=' || chr(3)) as "query_highlight!",
+            ts_headline('english', s.result, q, 'MaxFragments=2, StartSel=' || chr(2) || ', StopSel=' || chr(3)) as "result_highlight!",
+            s.created_at
+        from searches s
+            inner join threads t on s.thread_id = t.thread_id,
+            websearch_to_tsquery('english', $2) q
+        where t.user_id = $1 and s.deleted_at is null and t.deleted_at is null
+            and (
+                (setweight(to_tsvector('english', s.query), 'A') ||
+                setweight(to_tsvector('english', s.rephrased_query), 'B') ||
+                setweight(to_tsvector('english', s.result), 'C')) @@ q
+                or setweight(to_tsvector('english', t.title), 'A') @@ q
+            )
+            and ($3::timestamptz is null or s.created_at >= $3)
+            and ($4::timestamptz is null or s.created_at < $4)
+            and ($5::boolean is null or s.reaction = $5)
+        order by "rank!" desc, s.created_at desc limit $6 offset $7"#,
         user_id,
-        thread_history_request.limit.unwrap_or(10) as i64,
-        thread_history_request.offset.unwrap_or(0) as i64
+        history_search_request.query,
+        history_search_request.from,
+        history_search_request.to,
+        history_search_request.reaction,
+        history_search_request.limit.unwrap_or(10) as i64,
+        history_search_request.offset.unwrap_or(0) as i64
     )
     .fetch_all(pool)
     .await?;
 
-    return Ok(api_models::ThreadHistoryResponse { threads });
+    let results = results
+        .into_iter()
+        .map(|result| api_models::HistorySearchResult {
+            title_highlight: headline_to_html(&result.title_highlight),
+            query_highlight: headline_to_html(&result.query_highlight),
+            result_highlight: headline_to_html(&result.result_highlight),
+            ..result
+        })
+        .collect();
+
+    return Ok(api_models::HistorySearchResponse { results });
+}
+
+// ts_headline marks the matched terms with control characters, which are swapped for the `<mark>`
+// tags once the text is escaped
+fn headline_to_html(headline: &str) -> String {
+    export::escape_html(headline)
+        .replace('\u{2}', "<mark>")
+        .replace('\u{3}', "</mark>")
 }
 
 #[tracing::instrument(level = "info", ret, err)]
@@ -200,29 +467,42 @@ pub async fn get_one_thread(
 ) -> Result<api_models::SearchThreadResponse> {
     let thread = sqlx::query_as!(
         data_models::Thread,
-        "select * from threads where thread_id = $1 and user_id = $2",
+        "select * from threads where thread_id = $1 and user_id = $2 and deleted_at is null",
         thread_by_id_request.thread_id,
         user_id,
     )
     .fetch_one(pool)
     .await?;
 
-    let searches = sqlx::query_as!(
+    let limit = thread_by_id_request.limit.unwrap_or(10) as usize;
+    let (cursor_created_at, cursor_search_id) =
+        cursor::decode_cursor(&thread_by_id_request.cursor)?;
+
+    // one more search than asked for tells whether there is a next page
+    let mut searches = sqlx::query_as!(
         data_models::Search,
-        "select * from searches where thread_id = $1 \
-            order by created_at desc limit $2 offset $3",
+        "select * from searches where thread_id = $1 and deleted_at is null \
+            and ($2 or archived_at is null) \
+            and ($3::timestamptz is null or (created_at, search_id) < ($3, $4::uuid)) \
+            order by created_at desc, search_id desc limit $5",
         thread.thread_id,
-        thread_by_id_request.limit.unwrap_or(10) as i64,
-        thread_by_id_request.offset.unwrap_or(0) as i64
+        thread_by_id_request.include_archived.unwrap_or(false),
+        cursor_created_at,
+        cursor_search_id,
+        limit as i64 + 1,
     )
     .fetch_all(pool)
     .await?;
+    let next_cursor = cursor::next_cursor(&mut searches, limit, |search| cursor::Cursor {
+        created_at: search.created_at.0,
+        id: search.search_id,
+    });
 
     let sources = sqlx::query_as!(
         data_models::Source,
         "select s.* from sources s \
             inner join search_sources ss on s.source_id = ss.source_id \
-            where ss.search_id = any($1::uuid[])",
+            where ss.search_id = any($1::uuid[]) order by ss.position",
         &searches.iter().map(|s| s.search_id).collect::<Vec<Uuid>>(),
     )
     .fetch_all(pool)
@@ -240,11 +520,20 @@ pub async fn get_one_thread(
                 })
                 .cloned()
                 .collect::<Vec<data_models::Source>>();
-            api_models::SearchByIdResponse { search, sources }
+            api_models::SearchByIdResponse {
+                search,
+                sources,
+                citations: None,
+                facets: None,
+            }
         })
         .collect::<Vec<api_models::SearchByIdResponse>>();
 
-    return Ok(api_models::SearchThreadResponse { thread, searches });
+    return Ok(api_models::SearchThreadResponse {
+        thread,
+        searches,
+        next_cursor,
+    });
 }
 
 #[tracing::instrument(level = "info", ret, err)]
@@ -255,7 +544,8 @@ pub async fn update_thread(
 ) -> Result<data_models::Thread> {
     let thread = sqlx::query_as!(
         data_models::Thread,
-        "update threads set title = $1 where thread_id = $2 and user_id = $3 returning *",
+        "update threads set title = $1, title_set_by_user = true \
+            where thread_id = $2 and user_id = $3 and deleted_at is null returning *",
         update_thread_request.title,
         update_thread_request.thread_id,
         user_id,
@@ -276,6 +566,7 @@ pub async fn update_search_reaction(
         data_models::Search,
         "update searches s set reaction = $1 from threads t \
             where s.search_id = $2 and s.thread_id = t.thread_id and t.user_id = $3 \
+            and s.deleted_at is null and t.deleted_at is null \
             returning s.*",
         search_reaction_request.reaction,
         search_reaction_request.search_id,
@@ -286,3 +577,729 @@ pub async fn update_search_reaction(
 
     return Ok(search);
 }
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn archive_threads(
+    pool: &PgPool,
+    user_id: &Uuid,
+    archive_threads_request: &api_models::ArchiveThreadsRequest,
+) -> Result<api_models::BulkThreadResponse> {
+    let threads = sqlx::query_as!(
+        data_models::Thread,
+        "update threads set archived_at = case when $1 then now() end \
+            where thread_id = any($2) and user_id = $3 and deleted_at is null returning *",
+        archive_threads_request.archived,
+        &archive_threads_request.thread_ids,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    return Ok(api_models::BulkThreadResponse {
+        thread_ids: threads.into_iter().map(|t| t.thread_id).collect(),
+    });
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn delete_threads(
+    pool: &PgPool,
+    user_id: &Uuid,
+    threads_request: &api_models::BulkThreadRequest,
+) -> Result<api_models::BulkThreadResponse> {
+    let threads = sqlx::query_as!(
+        data_models::Thread,
+        "update threads set deleted_at = now() \
+            where thread_id = any($1) and user_id = $2 and deleted_at is null returning *",
+        &threads_request.thread_ids,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    return Ok(api_models::BulkThreadResponse {
+        thread_ids: threads.into_iter().map(|t| t.thread_id).collect(),
+    });
+}
+
+/// Undoes the deletion of threads that the purge job has not removed yet.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn restore_threads(
+    pool: &PgPool,
+    user_id: &Uuid,
+    threads_request: &api_models::BulkThreadRequest,
+) -> Result<api_models::BulkThreadResponse> {
+    let threads = sqlx::query_as!(
+        data_models::Thread,
+        "update threads set deleted_at = null \
+            where thread_id = any($1) and user_id = $2 and deleted_at is not null returning *",
+        &threads_request.thread_ids,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    return Ok(api_models::BulkThreadResponse {
+        thread_ids: threads.into_iter().map(|t| t.thread_id).collect(),
+    });
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn delete_searches(
+    pool: &PgPool,
+    user_id: &Uuid,
+    searches_request: &api_models::BulkSearchRequest,
+) -> Result<api_models::BulkSearchResponse> {
+    let searches = sqlx::query_as!(
+        data_models::Search,
+        "update searches s set deleted_at = now() from threads t \
+            where s.search_id = any($1) and s.thread_id = t.thread_id and t.user_id = $2 \
+            and s.deleted_at is null and t.deleted_at is null \
+            returning s.*",
+        &searches_request.search_ids,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    return Ok(api_models::BulkSearchResponse {
+        search_ids: searches.into_iter().map(|s| s.search_id).collect(),
+    });
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn archive_searches(
+    pool: &PgPool,
+    user_id: &Uuid,
+    archive_searches_request: &api_models::ArchiveSearchesRequest,
+) -> Result<api_models::BulkSearchResponse> {
+    let searches = sqlx::query_as!(
+        data_models::Search,
+        "update searches s set archived_at = case when $1 then now() end from threads t \
+            where s.search_id = any($2) and s.thread_id = t.thread_id and t.user_id = $3 \
+            and s.deleted_at is null and t.deleted_at is null \
+            returning s.*",
+        archive_searches_request.archived,
+        &archive_searches_request.search_ids,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    return Ok(api_models::BulkSearchResponse {
+        search_ids: searches.into_iter().map(|s| s.search_id).collect(),
+    });
+}
+
+/// Undoes the deletion of searches that the purge job has not removed yet. The searches of a
+/// deleted thread come back with the thread instead.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn restore_searches(
+    pool: &PgPool,
+    user_id: &Uuid,
+    searches_request: &api_models::BulkSearchRequest,
+) -> Result<api_models::BulkSearchResponse> {
+    let searches = sqlx::query_as!(
+        data_models::Search,
+        "update searches s set deleted_at = null from threads t \
+            where s.search_id = any($1) and s.thread_id = t.thread_id and t.user_id = $2 \
+            and s.deleted_at is not null and t.deleted_at is null \
+            returning s.*",
+        &searches_request.search_ids,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    return Ok(api_models::BulkSearchResponse {
+        search_ids: searches.into_iter().map(|s| s.search_id).collect(),
+    });
+}
+
+/// Hard deletes the threads and searches deleted more than `retention_days` ago, along with
+/// their links to sources and collections. Returns the number of threads and searches removed.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn purge_deleted(pool: &PgPool, retention_days: i32) -> Result<(u64, u64)> {
+    // Only used by internal services, so no need to check if user_id is the owner of the rows
+    let purged_threads = sqlx::query!(
+        "delete from threads where deleted_at < now() - make_interval(days => $1)",
+        retention_days,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    let purged_searches = sqlx::query!(
+        "delete from searches where deleted_at < now() - make_interval(days => $1)",
+        retention_days,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok((purged_threads, purged_searches))
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn share_thread(
+    pool: &PgPool,
+    user_id: &Uuid,
+    share_thread_request: &api_models::ShareThreadRequest,
+) -> Result<data_models::SharedThread> {
+    // 256 random bits, so that links cannot be guessed
+    let token = URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>());
+
+    let shared_thread = sqlx::query_as!(
+        data_models::SharedThread,
+        "insert into shared_threads (thread_id, user_id, token, include_sources, expires_at) \
+            select thread_id, user_id, $1, $2, $3 from threads \
+            where thread_id = $4 and user_id = $5 and deleted_at is null \
+            returning *",
+        token,
+        share_thread_request.include_sources,
+        share_thread_request.expires_at,
+        share_thread_request.thread_id,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    return Ok(shared_thread);
+}
+
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn revoke_shared_thread(
+    pool: &PgPool,
+    user_id: &Uuid,
+    revoke_request: &api_models::RevokeSharedThreadRequest,
+) -> Result<data_models::SharedThread> {
+    let shared_thread = sqlx::query_as!(
+        data_models::SharedThread,
+        "update shared_threads set revoked_at = now() \
+            where token = $1 and user_id = $2 and revoked_at is null returning *",
+        revoke_request.token,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    return Ok(shared_thread);
+}
+
+/// Returns the thread behind a share link, with the searches made before the link was created.
+/// Revoked and expired links, and deleted threads, are not found.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn get_shared_thread(
+    pool: &PgPool,
+    token: &str,
+) -> Result<api_models::SharedThreadResponse> {
+    let shared_thread = sqlx::query_as!(
+        data_models::SharedThread,
+        "select st.* from shared_threads st \
+            inner join threads t on st.thread_id = t.thread_id \
+            where st.token = $1 and st.revoked_at is null \
+            and (st.expires_at is null or st.expires_at > now()) and t.deleted_at is null",
+        token,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let thread = sqlx::query_as!(
+        data_models::Thread,
+        "select * from threads where thread_id = $1",
+        shared_thread.thread_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let searches = sqlx::query_as!(
+        data_models::Search,
+        "select * from searches where thread_id = $1 and deleted_at is null \
+            and created_at <= $2 order by created_at",
+        shared_thread.thread_id,
+        shared_thread.created_at.0,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let search_sources = match shared_thread.include_sources {
+        true => {
+            sqlx::query!(
+                "select ss.search_id, s.title, s.url, s.description from sources s \
+                    inner join search_sources ss on s.source_id = ss.source_id \
+                    where ss.search_id = any($1::uuid[]) order by ss.position",
+                &searches.iter().map(|s| s.search_id).collect::<Vec<Uuid>>(),
+            )
+            .fetch_all(pool)
+            .await?
+        }
+        false => vec![],
+    };
+
+    let searches = searches
+        .into_iter()
+        .map(|search| api_models::SharedSearch {
+            sources: search_sources
+                .iter()
+                .filter(|source| source.search_id == search.search_id)
+                .map(|source| api_models::SharedSource {
+                    title: source.title.clone(),
+                    url: source.url.clone(),
+                    description: source.description.clone(),
+                })
+                .collect(),
+            query: search.query,
+            result: search.result,
+            created_at: search.created_at,
+        })
+        .collect();
+
+    return Ok(api_models::SharedThreadResponse {
+        title: thread.title,
+        searches,
+        created_at: thread.created_at,
+    });
+}
+
+/// Gathers the searches to export, oldest first, with their sources. Exactly one of `thread_id`
+/// and `search_id` is expected to be set.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn get_export_document(
+    pool: &PgPool,
+    user_id: &Uuid,
+    export_request: &api_models::ExportRequest,
+) -> Result<export::ExportDocument> {
+    let searches = sqlx::query_as!(
+        data_models::Search,
+        "select s.* from searches s \
+            inner join threads t on s.thread_id = t.thread_id \
+            where t.user_id = $1 and s.deleted_at is null and t.deleted_at is null \
+            and ($2::uuid is null or s.thread_id = $2) \
+            and ($3::uuid is null or s.search_id = $3) \
+            order by s.created_at",
+        user_id,
+        export_request.thread_id,
+        export_request.search_id,
+    )
+    .fetch_all(pool)
+    .await?;
+    let Some(first_search) = searches.first() else {
+        return Err(SearchError::NoResults("No searches to export".to_string()));
+    };
+
+    let thread = sqlx::query_as!(
+        data_models::Thread,
+        "select * from threads where thread_id = $1",
+        first_search.thread_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let search_ids = searches.iter().map(|s| s.search_id).collect::<Vec<Uuid>>();
+    let search_sources = sqlx::query!(
+        "select search_id, source_id from search_sources where search_id = any($1::uuid[]) \
+            order by position",
+        &search_ids,
+    )
+    .fetch_all(pool)
+    .await?;
+    let sources = sqlx::query_as!(
+        data_models::Source,
+        "select s.* from sources s \
+            where s.source_id in (select source_id from search_sources where search_id = any($1::uuid[]))",
+        &search_ids,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let searches = searches
+        .into_iter()
+        .map(|search| export::ExportedSearch {
+            sources: search_sources
+                .iter()
+                .filter(|search_source| search_source.search_id == search.search_id)
+                .filter_map(|search_source| {
+                    sources
+                        .iter()
+                        .find(|source| source.source_id == search_source.source_id)
+                })
+                .map(|source| export::ExportedSource {
+                    title: source.title.clone(),
+                    url: source.url.clone(),
+                    citation: export_request.citation_style.map(|style| {
+                        citations::format_citation(
+                            style,
+                            &citations::CitationRecord::from_source(source),
+                        )
+                    }),
+                })
+                .collect(),
+            query: search.query,
+            answer: search.result,
+            created_at: search.created_at.0,
+        })
+        .collect();
+
+    return Ok(export::ExportDocument {
+        title: thread.title,
+        searches,
+        exported_at: time::OffsetDateTime::now_utc(),
+    });
+}
+
+/// Sources of a search, a thread or a collection, without duplicates, in the order they were
+/// first cited. The sources of a collection are its own, with the metadata they were imported
+/// with, and those of its searches.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn get_citation_sources(
+    pool: &PgPool,
+    user_id: &Uuid,
+    citation_request: &api_models::CitationExportRequest,
+) -> Result<Vec<data_models::Source>> {
+    let sources = match citation_request.collection_id {
+        Some(collection_id) => {
+            sqlx::query_as!(
+                data_models::Source,
+                "select s.source_id, s.url, s.title, s.description, s.source_type, \
+                    coalesce(cited.metadata, '{}'::jsonb) || coalesce(s.metadata, '{}'::jsonb) as metadata, \
+                    s.created_at, s.updated_at from sources s \
+                    inner join ( \
+                        select cs.source_id, cs.created_at, 0 as position, cs.metadata from collection_sources cs \
+                            inner join collections c on cs.collection_id = c.collection_id \
+                            where c.collection_id = $1 and c.user_id = $2 \
+                        union all \
+                        select ss.source_id, se.created_at, ss.position, null::jsonb from collection_searches cse \
+                            inner join collections c on cse.collection_id = c.collection_id \
+                            inner join searches se on cse.search_id = se.search_id \
+                            inner join search_sources ss on se.search_id = ss.search_id \
+                            where c.collection_id = $1 and c.user_id = $2 and se.deleted_at is null \
+                    ) cited on s.source_id = cited.source_id \
+                    order by cited.created_at, cited.position, s.created_at",
+                collection_id,
+                user_id,
+            )
+            .fetch_all(pool)
+            .await?
+        }
+        None => {
+            sqlx::query_as!(
+                data_models::Source,
+                "select s.* from sources s \
+                    inner join search_sources ss on s.source_id = ss.source_id \
+                    inner join searches se on ss.search_id = se.search_id \
+                    inner join threads t on se.thread_id = t.thread_id \
+                    where t.user_id = $1 and se.deleted_at is null and t.deleted_at is null \
+                    and ($2::uuid is null or se.search_id = $2) \
+                    and ($3::uuid is null or se.thread_id = $3) \
+                    order by se.created_at, ss.position",
+                user_id,
+                citation_request.search_id,
+                citation_request.thread_id,
+            )
+            .fetch_all(pool)
+            .await?
+        }
+    };
+
+    let mut seen = HashSet::new();
+    let sources: Vec<data_models::Source> = sources
+        .into_iter()
+        .filter(|source| seen.insert(source.source_id))
+        .collect();
+    if sources.is_empty() {
+        return Err(SearchError::NoResults("No sources to cite".to_string()));
+    }
+
+    return Ok(sources);
+}
+
+// Longest url `sources.url` can hold
+const MAX_SOURCE_URL_LENGTH: usize = 255;
+
+/// Resolves the parsed references to sources and links them into the collection. A reference is
+/// matched to a known source by DOI, then PMID, then url, and creates a source otherwise. The
+/// metadata of a matched source keeps its own values and gains the ones it was missing.
+#[tracing::instrument(level = "info", skip(entries), ret, err)]
+pub async fn import_collection_sources(
+    pool: &PgPool,
+    user_id: &Uuid,
+    import_request: &api_models::CollectionImportRequest,
+    entries: Vec<citations::ParsedEntry>,
+    pubmed_url_prefix: &str,
+) -> Result<api_models::CollectionImportResponse> {
+    sqlx::query!(
+        "select collection_id from collections where collection_id = $1 and user_id = $2",
+        import_request.collection_id,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let mut tx = pool.begin().await?;
+    let mut imported_entries = vec![];
+    for entry in entries {
+        let record = match entry.record {
+            Ok(record) => record,
+            Err(error) => {
+                imported_entries.push(api_models::ImportedEntry {
+                    label: entry.label,
+                    title: None,
+                    status: api_models::ImportStatus::Failed,
+                    source_id: None,
+                    error: Some(error),
+                });
+                continue;
+            }
+        };
+        let url = match record.source_url(pubmed_url_prefix) {
+            Some(url) if url.chars().count() <= MAX_SOURCE_URL_LENGTH => Ok(url),
+            Some(_) => Err(format!(
+                "The url is longer than {} characters",
+                MAX_SOURCE_URL_LENGTH
+            )),
+            None => Err("The entry has no url, DOI or PMID".to_string()),
+        };
+        let url = match url {
+            Ok(url) => url,
+            Err(error) => {
+                imported_entries.push(api_models::ImportedEntry {
+                    label: entry.label,
+                    title: Some(record.title),
+                    status: api_models::ImportStatus::Failed,
+                    source_id: None,
+                    error: Some(error),
+                });
+                continue;
+            }
+        };
+        let title: String = record.title.chars().take(255).collect();
+        let metadata = serde_json::to_value(record.to_metadata())?;
+
+        // Sources are shared, so they are matched regardless of who cited them first
+        let matched_source = sqlx::query!(
+            "select source_id from sources \
+                where ($1::text is not null and lower(metadata ->> 'doi') = lower($1)) \
+                or ($2::text is not null and metadata ->> 'pmid' = $2) \
+                or url = $3 \
+                order by (lower(metadata ->> 'doi') = lower($1)) is true desc, \
+                (metadata ->> 'pmid' = $2) is true desc \
+                limit 1",
+            record.doi,
+            record.pmid,
+            url,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let (source_id, status) = match matched_source {
+            Some(source) => (source.source_id, api_models::ImportStatus::Matched),
+            None => {
+                // only the identifiers go to the shared source, to match it on the next imports
+                let identifiers = serde_json::json!({
+                    citations::METADATA_DOI: record.doi,
+                    citations::METADATA_PMID: record.pmid,
+                });
+                let source = sqlx::query!(
+                    "insert into sources (title, url, source_type, metadata) \
+                        values ($1, $2, $3, jsonb_strip_nulls($4)) returning source_id",
+                    title,
+                    url,
+                    SourceType::Url as i32,
+                    identifiers,
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                (source.source_id, api_models::ImportStatus::Created)
+            }
+        };
+
+        sqlx::query!(
+            "insert into collection_sources (collection_id, source_id, metadata) \
+                values ($1, $2, $3) \
+                on conflict (collection_id, source_id) do update set metadata = excluded.metadata",
+            import_request.collection_id,
+            source_id,
+            metadata,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        imported_entries.push(api_models::ImportedEntry {
+            label: entry.label,
+            title: Some(title),
+            status,
+            source_id: Some(source_id),
+            error: None,
+        });
+    }
+    tx.commit().await?;
+
+    let count = |status: api_models::ImportStatus| {
+        imported_entries
+            .iter()
+            .filter(|entry| entry.status == status)
+            .count()
+    };
+
+    return Ok(api_models::CollectionImportResponse {
+        collection_id: import_request.collection_id,
+        matched: count(api_models::ImportStatus::Matched),
+        created: count(api_models::ImportStatus::Created),
+        failed: count(api_models::ImportStatus::Failed),
+        entries: imported_entries,
+    });
+}
+
+/// Sources with a PMID whose PubMed record was fetched before `enriched_before`, or never, the
+/// oldest first.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn get_stale_pubmed_sources(
+    pool: &PgPool,
+    enriched_before: &time::OffsetDateTime,
+    limit: i64,
+) -> Result<Vec<data_models::Source>> {
+    let sources = sqlx::query_as!(
+        data_models::Source,
+        "select * from sources \
+            where metadata ->> 'pmid' is not null \
+            and (metadata ->> 'enriched_at' is null or (metadata ->> 'enriched_at')::timestamptz < $1) \
+            order by metadata ->> 'enriched_at' nulls first \
+            limit $2",
+        enriched_before,
+        limit,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    return Ok(sources);
+}
+
+/// Merges the metadata into the one of each source, overwriting the keys they share.
+#[tracing::instrument(level = "info", skip(updates), ret, err)]
+pub async fn update_sources_metadata(
+    pool: &PgPool,
+    updates: &[(Uuid, serde_json::Value)],
+) -> Result<()> {
+    // Only used by internal services, so no need to check if user_id is the owner of the source
+    sqlx::query!(
+        "update sources s set metadata = coalesce(s.metadata, '{}'::jsonb) || u.metadata \
+            from unnest($1::uuid[], $2::jsonb[]) as u(source_id, metadata) \
+            where s.source_id = u.source_id",
+        &updates
+            .iter()
+            .map(|(source_id, _)| *source_id)
+            .collect::<Vec<Uuid>>(),
+        &updates
+            .iter()
+            .map(|(_, metadata)| metadata.clone())
+            .collect::<Vec<serde_json::Value>>(),
+    )
+    .execute(pool)
+    .await?;
+
+    return Ok(());
+}
+
+// Notices inserted in one statement. The arrays are bound as 7 parameters whatever their length,
+// batching only keeps each statement and its memory use small.
+const RETRACTION_INSERT_BATCH_SIZE: usize = 5000;
+
+/// Replaces all the notices of the retraction index at once, so that searches never see a
+/// partly imported dataset.
+#[tracing::instrument(level = "info", skip(retractions), ret, err)]
+pub async fn replace_retractions(
+    pool: &PgPool,
+    retractions: &[rag::RetractionRecord],
+) -> Result<usize> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("delete from retractions")
+        .execute(&mut *tx)
+        .await?;
+
+    let mut imported = 0;
+    for batch in retractions.chunks(RETRACTION_INSERT_BATCH_SIZE) {
+        // the dataset repeats some notices, the first one is kept
+        let result = sqlx::query!(
+            "insert into retractions (record_id, doi, pmid, nature, notice_doi, reason, retracted_on) \
+                select * from unnest($1::text[], $2::text[], $3::text[], $4::text[], $5::text[], $6::text[], $7::date[]) \
+                on conflict (record_id) do nothing",
+            &batch
+                .iter()
+                .map(|r| r.record_id.clone())
+                .collect::<Vec<String>>(),
+            &batch
+                .iter()
+                .map(|r| r.doi.clone())
+                .collect::<Vec<Option<String>>>() as _,
+            &batch
+                .iter()
+                .map(|r| r.pmid.clone())
+                .collect::<Vec<Option<String>>>() as _,
+            &batch
+                .iter()
+                .map(|r| r.nature.as_str().to_string())
+                .collect::<Vec<String>>(),
+            &batch
+                .iter()
+                .map(|r| r.notice_doi.clone())
+                .collect::<Vec<Option<String>>>() as _,
+            &batch
+                .iter()
+                .map(|r| r.reason.clone())
+                .collect::<Vec<Option<String>>>() as _,
+            &batch
+                .iter()
+                .map(|r| r.retracted_on)
+                .collect::<Vec<Option<time::Date>>>() as _,
+        )
+        .execute(&mut *tx)
+        .await?;
+        imported += result.rows_affected() as usize;
+    }
+    tx.commit().await?;
+
+    return Ok(imported);
+}
+
+/// The notices of the papers with any of the DOIs or PMIDs. The DOIs are expected in lowercase.
+#[tracing::instrument(level = "info", ret, err)]
+pub async fn get_retractions(
+    pool: &PgPool,
+    dois: &[String],
+    pmids: &[String],
+) -> Result<Vec<data_models::Retraction>> {
+    let retractions = sqlx::query_as!(
+        data_models::Retraction,
+        "select * from retractions where lower(doi) = any($1) or pmid = any($2)",
+        dois,
+        pmids,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    return Ok(retractions);
+}
+
+/// Flags the stored sources of the papers in the retraction index, with their strongest notice.
+#[tracing::instrument(level = "info", skip(pool), ret, err)]
+pub async fn flag_retracted_sources(pool: &PgPool) -> Result<u64> {
+    // Only used by internal services, so no need to check if user_id is the owner of the source
+    let result = sqlx::query!(
+        "update sources s set metadata = coalesce(s.metadata, '{}'::jsonb) || f.flag \
+            from ( \
+                select distinct on (s.source_id) s.source_id, jsonb_strip_nulls(jsonb_build_object( \
+                    'retraction_status', r.nature, \
+                    'retraction_notice_doi', r.notice_doi, \
+                    'retraction_date', r.retracted_on::text \
+                )) as flag \
+                from sources s join retractions r \
+                    on lower(s.metadata ->> 'doi') = lower(r.doi) or s.metadata ->> 'pmid' = r.pmid \
+                order by s.source_id, r.nature = 'retraction' desc, r.retracted_on desc nulls last \
+            ) f \
+            where s.source_id = f.source_id \
+            and s.metadata ->> 'retraction_status' is distinct from f.flag ->> 'retraction_status'",
+    )
+    .execute(pool)
+    .await?;
+
+    return Ok(result.rows_affected());
+}
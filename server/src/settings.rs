@@ -1,6 +1,6 @@
 use crate::auth::oauth2::OAuth2Client;
 use crate::secrets::Secret;
-use crate::{cache::CacheSettings, llms, rag};
+use crate::{cache::CacheSettings, llms, rag, resilience, search};
 use config::{Config, Environment, File};
 use dotenvy::dotenv;
 use once_cell::sync::Lazy;
@@ -83,12 +83,25 @@ pub struct Settings {
     pub agency_api: Secret<String>,
     pub oauth2_clients: Vec<OAuth2Client>,
     pub pubmed: rag::PubmedSettings,
+    pub pubmed_metadata: rag::PubmedMetadataSettings,
+    pub evidence: rag::EvidenceSettings,
+    pub retractions: rag::RetractionSettings,
     pub brave: rag::BraveSettings,
     pub llm: llms::LLMSettings,
     pub summarizer: llms::SummarizerSettings,
     pub search: rag::SearchSettings,
     pub query_rephraser: llms::QueryRephraserSettings,
     pub openai: llms::OpenAISettings,
+    pub verification: llms::VerificationSettings,
+    pub moderation: llms::ModerationSettings,
+    pub safety_policy: llms::SafetyPolicySettings,
+    pub crisis: llms::CrisisSettings,
+    pub redaction: llms::RedactionSettings,
+    pub conversation_memory: llms::ConversationMemorySettings,
+    pub thread_title: llms::ThreadTitleSettings,
+    pub follow_up: llms::FollowUpSettings,
+    pub purge: search::PurgeSettings,
+    pub resilience: resilience::ResilienceSettings,
 }
 
 impl Settings {
@@ -1,14 +1,17 @@
 use crate::auth::oauth2::OAuth2Client;
 use crate::proto::agency_service_client::AgencyServiceClient;
-use crate::rag::brave_search;
+use crate::rag::{self, brave_search};
+use crate::resilience::{Dependencies, DependencySettings};
+use crate::search;
 use crate::{cache::CachePool, routing::router, settings::Settings};
 use axum::{extract::FromRef, routing::IntoMakeService, serve::Serve, Router};
 use color_eyre::eyre::eyre;
 use log::info;
 use regex::Regex;
 use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tonic::transport::Channel;
+use tonic::transport::{Channel, Endpoint};
 
 pub struct Application {
     port: u16,
@@ -53,6 +56,7 @@ pub struct AppState {
     pub settings: Settings,
     pub brave_config: brave_search::BraveAPIConfig,
     pub openai_stream_regex: regex::Regex,
+    pub dependencies: Dependencies,
 }
 
 impl AppState {
@@ -70,6 +74,7 @@ impl AppState {
             cache,
             agency_service,
             oauth2_clients,
+            dependencies: Dependencies::new(&settings.resilience)?,
             settings,
             brave_config,
             openai_stream_regex,
@@ -80,9 +85,14 @@ impl AppState {
         Ok(Self {
             db: db_connect(settings.db.expose()).await?,
             cache: CachePool::new(&settings.cache).await?,
-            agency_service: agency_service_connect(settings.agency_api.expose()).await?,
+            agency_service: agency_service_connect(
+                settings.agency_api.expose(),
+                &settings.resilience.agency,
+            )
+            .await?,
             oauth2_clients: settings.oauth2_clients.clone(),
             brave_config: settings.brave.clone().into(),
+            dependencies: Dependencies::new(&settings.resilience)?,
             settings,
             openai_stream_regex: Regex::new(r#"\"content\":\"(.*?)\"}"#)
                 .map_err(|e| eyre!("Failed to compile OpenAI stream regex: {}", e))?,
@@ -100,12 +110,19 @@ pub async fn db_connect(database_url: &str) -> crate::Result<PgPool> {
 
 pub async fn agency_service_connect(
     agency_service_url: &str,
+    dependency_settings: &DependencySettings,
 ) -> crate::Result<AgencyServiceClient<Channel>> {
-    let agency_service = AgencyServiceClient::connect(agency_service_url.to_owned())
+    let channel = Endpoint::from_shared(agency_service_url.to_owned())
+        .map_err(|e| eyre!("Invalid agency service url: {}", e))?
+        .connect_timeout(Duration::from_millis(
+            dependency_settings.connect_timeout_ms,
+        ))
+        .timeout(Duration::from_millis(dependency_settings.timeout_ms))
+        .connect()
         .await
         .map_err(|e| eyre!("Failed to connect to agency service: {}", e))?;
 
-    Ok(agency_service)
+    Ok(AgencyServiceClient::new(channel))
 }
 
 async fn run(
@@ -114,6 +131,13 @@ async fn run(
 ) -> crate::Result<Serve<IntoMakeService<Router>, Router>> {
     let state = AppState::initialize(settings).await?;
     sqlx::migrate!().run(&state.db).await?;
+    search::spawn_purge_job(state.db.clone(), state.settings.purge.clone());
+    rag::spawn_pubmed_refresh_job(
+        state.db.clone(),
+        state.dependencies.pubmed.clone(),
+        state.settings.pubmed_metadata.clone(),
+    );
+    rag::spawn_retraction_import_job(state.db.clone(), state.settings.retractions.clone())?;
 
     let app = router(state)?;
 
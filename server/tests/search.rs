@@ -2,26 +2,50 @@ use httpmock::prelude::POST;
 use httpmock::MockServer;
 use server::auth::{register, RegisterUserRequest};
 use server::cache::CachePool;
-use server::llms::{PromptCompressionAPIResponse, PromptCompressionOutput};
-use server::rag::search;
+use server::citations::{
+    parse_bibtex, render_citations, CitationFormat, CitationRecord, CitationStyle,
+};
+use server::export::{render_export, ExportFormat};
+use server::llms::{
+    evaluate_safety_policy, ConversationMemory, PromptCompressionAPIResponse,
+    PromptCompressionOutput, SafetyAction,
+};
+use server::rag::{
+    import_retractions, refresh_pubmed_metadata, screen_retractions, search, RetractedSourcePolicy,
+    RetrievedResult,
+};
+use server::resilience::Dependencies;
 use server::search::{
-    append_search_result, get_one_search, insert_new_search, update_search_reaction,
+    add_search_sources, append_search_result, archive_searches, archive_threads, delete_searches,
+    delete_threads, get_citation_sources, get_export_document, get_one_search, get_one_thread,
+    get_shared_thread, get_thread_memory, get_threads, import_collection_sources,
+    insert_new_search, insert_safety_audit_log, purge_deleted, restore_searches, restore_threads,
+    revoke_shared_thread, search_history, share_thread, update_generated_thread_title,
+    update_search_follow_up_questions, update_search_reaction, update_thread, update_thread_memory,
     SearchByIdRequest,
 };
-use server::search::{SearchQueryRequest, SearchReactionRequest};
+use server::search::{
+    ArchiveSearchesRequest, ArchiveThreadsRequest, BulkSearchRequest, BulkThreadRequest,
+    CitationExportRequest, CollectionImportRequest, ExportRequest, GetThreadRequest,
+    HistorySearchRequest, ImportStatus, RevokeSharedThreadRequest, SearchQueryRequest,
+    SearchReactionRequest, ShareThreadRequest, ThreadHistoryRequest, UpdateThreadRequest,
+};
 use server::settings::Settings;
 use server::Result;
 use sqlx::PgPool;
+use std::collections::HashMap;
+use validator::Validate;
 
 mod utils;
 
-#[tokio::test]
-async fn search_test() -> Result<()> {
+#[sqlx::test]
+async fn search_test(pool: PgPool) -> Result<()> {
     let mut settings = Settings::new();
 
     let (server_future, mut agency_service) = utils::agency_server_and_client_stub().await;
     let cache = CachePool::new(&settings.cache).await?;
     let brave_api_config = settings.brave.clone().into();
+    let dependencies = Dependencies::new(&settings.resilience)?;
 
     // Mock compression server
     let server = MockServer::start();
@@ -42,10 +66,15 @@ async fn search_test() -> Result<()> {
     let request_future = async {
         let search_result = search(
             &settings,
+            &pool,
+            &dependencies,
             &brave_api_config,
             &cache,
             &mut agency_service,
-            "test",
+            &SearchQueryRequest {
+                query: "test".to_string(),
+                ..Default::default()
+            },
         )
         .await;
         // Validate server response with assertions
@@ -61,6 +90,59 @@ async fn search_test() -> Result<()> {
     Ok(())
 }
 
+#[sqlx::test]
+async fn search_falls_back_to_extractive_compression_test(pool: PgPool) -> Result<()> {
+    let mut settings = Settings::new();
+    settings.resilience.prompt_compression.max_retries = 0;
+    settings.search.max_sources = 1;
+
+    let (server_future, agency_service) = utils::agency_server_and_client_stub().await;
+    let cache = CachePool::new(&settings.cache).await?;
+    let brave_api_config = settings.brave.clone().into();
+    let dependencies = Dependencies::new(&settings.resilience)?;
+
+    // Mock an unavailable compression server
+    let server = MockServer::start();
+    settings.llm.prompt_compression_url = server.url("/compress");
+    let _ = server.mock(|when, then| {
+        when.method(POST).path("/compress");
+
+        then.status(503);
+    });
+
+    let request_future = async {
+        let search_result = search(
+            &settings,
+            &pool,
+            &dependencies,
+            &brave_api_config,
+            &cache,
+            &agency_service,
+            &SearchQueryRequest {
+                query: "test-fallback".to_string(),
+                ..Default::default()
+            },
+        )
+        .await;
+        let search_result = search_result.unwrap();
+        assert!(search_result.result.starts_with("[1] test-abstract"));
+        // the facets count the candidates beyond `max_sources`
+        assert_eq!(search_result.sources.len(), 1);
+        assert_eq!(
+            search_result.facets.domains[0].value,
+            "pubmed.ncbi.nlm.nih.gov"
+        );
+        assert_eq!(search_result.facets.domains[0].count, 2);
+    };
+
+    tokio::select! {
+        _ = server_future => panic!("server returned first"),
+        _ = request_future => (),
+    }
+
+    Ok(())
+}
+
 #[sqlx::test]
 async fn insert_search_and_get_search_history_test(pool: PgPool) -> Result<()> {
     let new_user = register(
@@ -77,12 +159,16 @@ async fn insert_search_and_get_search_history_test(pool: PgPool) -> Result<()> {
     let search_query = SearchQueryRequest {
         thread_id: None,
         query: "test-query".to_string(),
+        ..Default::default()
     };
     let rephrased_query = "test-rephrased-query";
 
     let search_result = insert_new_search(&pool, &user_id, &search_query, rephrased_query).await?;
     let search_id = search_result.search_id;
-    let one_search_history_request = SearchByIdRequest { search_id };
+    let one_search_history_request = SearchByIdRequest {
+        search_id,
+        citation_style: None,
+    };
 
     let actual_response = get_one_search(&pool, &user_id, &one_search_history_request).await?;
     assert_eq!(actual_response.search.query, search_query.query);
@@ -95,6 +181,16 @@ async fn insert_search_and_get_search_history_test(pool: PgPool) -> Result<()> {
     assert_eq!(updated_response.search.query, search_query.query);
     assert_eq!(updated_response.search.result, "updated-result");
 
+    // store the suggested follow-up questions
+    let follow_up_questions = vec!["test-follow-up-question?".to_string()];
+    update_search_follow_up_questions(&pool, &search_result, &follow_up_questions).await?;
+
+    let updated_response = get_one_search(&pool, &user_id, &one_search_history_request).await?;
+    assert_eq!(
+        updated_response.search.follow_up_questions,
+        Some(follow_up_questions)
+    );
+
     Ok(())
 }
 
@@ -114,6 +210,7 @@ async fn update_search_reaction_test(pool: PgPool) -> Result<()> {
     let search_query = SearchQueryRequest {
         thread_id: None,
         query: "test-query".to_string(),
+        ..Default::default()
     };
     let rephrased_query = "test-rephrased-query";
     let search_result = insert_new_search(&pool, &user_id, &search_query, rephrased_query).await?;
@@ -132,3 +229,946 @@ async fn update_search_reaction_test(pool: PgPool) -> Result<()> {
 
     Ok(())
 }
+
+#[sqlx::test]
+async fn insert_safety_audit_log_test(pool: PgPool) -> Result<()> {
+    let settings = Settings::new();
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let scores = HashMap::from([("toxic".to_string(), 0.99), ("insult".to_string(), 0.1)]);
+    let assessment = evaluate_safety_policy(&settings.safety_policy, scores);
+    let action = assessment.action().unwrap();
+    assert_eq!(action, SafetyAction::Block);
+
+    let safety_audit_log =
+        insert_safety_audit_log(&pool, &new_user.user_id, "test-query", &action, &assessment)
+            .await?;
+
+    assert_eq!(safety_audit_log.user_id, new_user.user_id);
+    assert_eq!(safety_audit_log.action, "block");
+    assert_eq!(safety_audit_log.violations[0]["label"], "toxic");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn update_and_get_thread_memory_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let search = insert_new_search(&pool, &user_id, &search_query, "test-rephrased-query").await?;
+    assert!(get_thread_memory(&pool, &user_id, &search.thread_id)
+        .await?
+        .is_none());
+
+    let memory = ConversationMemory {
+        summary: "The user asked about metformin.".to_string(),
+        entities: vec!["metformin".to_string()],
+        cited_sources: vec![],
+        turns: 1,
+    };
+    update_thread_memory(&pool, &search, &memory).await?;
+
+    let stored_memory = get_thread_memory(&pool, &user_id, &search.thread_id)
+        .await?
+        .unwrap();
+    assert_eq!(stored_memory.summary, memory.summary);
+    assert_eq!(stored_memory.entities, memory.entities);
+    assert_eq!(stored_memory.turns, 1);
+
+    // threads of other users are not shared
+    assert!(
+        get_thread_memory(&pool, &uuid::Uuid::new_v4(), &search.thread_id)
+            .await?
+            .is_none()
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn generated_thread_title_keeps_user_title_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let search = insert_new_search(&pool, &user_id, &search_query, "test-rephrased-query").await?;
+
+    let thread = update_generated_thread_title(&pool, &search, "generated-title")
+        .await?
+        .unwrap();
+    assert_eq!(thread.title, "generated-title");
+
+    let update_thread_request = UpdateThreadRequest {
+        thread_id: search.thread_id,
+        title: "user-title".to_string(),
+    };
+    let thread = update_thread(&pool, &user_id, &update_thread_request).await?;
+    assert!(thread.title_set_by_user);
+
+    assert!(
+        update_generated_thread_title(&pool, &search, "another-generated-title")
+            .await?
+            .is_none()
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn archive_delete_and_purge_threads_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let archived_search =
+        insert_new_search(&pool, &user_id, &search_query, "test-rephrased-query").await?;
+    let deleted_search =
+        insert_new_search(&pool, &user_id, &search_query, "test-rephrased-query").await?;
+
+    // archived threads are only listed when asked for
+    let archived = archive_threads(
+        &pool,
+        &user_id,
+        &ArchiveThreadsRequest {
+            thread_ids: vec![archived_search.thread_id],
+            archived: true,
+        },
+    )
+    .await?;
+    assert_eq!(archived.thread_ids, vec![archived_search.thread_id]);
+
+    let mut thread_history_request = ThreadHistoryRequest {
+        limit: None,
+        cursor: None,
+        include_archived: None,
+    };
+    let threads = get_threads(&pool, &user_id, &thread_history_request).await?;
+    assert_eq!(threads.threads.len(), 1);
+    thread_history_request.include_archived = Some(true);
+    let threads = get_threads(&pool, &user_id, &thread_history_request).await?;
+    assert_eq!(threads.threads.len(), 2);
+
+    // deleted threads are hidden, and can be restored until they are purged
+    let threads_request = BulkThreadRequest {
+        thread_ids: vec![deleted_search.thread_id],
+    };
+    delete_threads(&pool, &user_id, &threads_request).await?;
+    let threads = get_threads(&pool, &user_id, &thread_history_request).await?;
+    assert_eq!(threads.threads.len(), 1);
+    let one_search_request = SearchByIdRequest {
+        search_id: deleted_search.search_id,
+        citation_style: None,
+    };
+    assert!(get_one_search(&pool, &user_id, &one_search_request)
+        .await
+        .is_err());
+
+    let restored = restore_threads(&pool, &user_id, &threads_request).await?;
+    assert_eq!(restored.thread_ids, vec![deleted_search.thread_id]);
+    get_one_search(&pool, &user_id, &one_search_request).await?;
+
+    // searches of other users are left untouched
+    let searches_request = BulkSearchRequest {
+        search_ids: vec![deleted_search.search_id],
+    };
+    let deleted = delete_searches(&pool, &uuid::Uuid::new_v4(), &searches_request).await?;
+    assert!(deleted.search_ids.is_empty());
+    let deleted = delete_searches(&pool, &user_id, &searches_request).await?;
+    assert_eq!(deleted.search_ids, vec![deleted_search.search_id]);
+    assert!(get_one_search(&pool, &user_id, &one_search_request)
+        .await
+        .is_err());
+    let restored = restore_searches(&pool, &user_id, &searches_request).await?;
+    assert_eq!(restored.search_ids, vec![deleted_search.search_id]);
+    get_one_search(&pool, &user_id, &one_search_request).await?;
+
+    // archived searches are only listed in their thread when asked for
+    let archive_searches_request = ArchiveSearchesRequest {
+        search_ids: vec![deleted_search.search_id],
+        archived: true,
+    };
+    let archived = archive_searches(&pool, &user_id, &archive_searches_request).await?;
+    assert_eq!(archived.search_ids, vec![deleted_search.search_id]);
+    let mut get_thread_request = GetThreadRequest {
+        thread_id: deleted_search.thread_id,
+        limit: None,
+        cursor: None,
+        include_archived: None,
+    };
+    let thread = get_one_thread(&pool, &user_id, &get_thread_request).await?;
+    assert!(thread.searches.is_empty());
+    get_thread_request.include_archived = Some(true);
+    let thread = get_one_thread(&pool, &user_id, &get_thread_request).await?;
+    assert_eq!(thread.searches.len(), 1);
+
+    delete_threads(&pool, &user_id, &threads_request).await?;
+    assert_eq!(purge_deleted(&pool, 1).await?, (0, 0));
+    assert_eq!(purge_deleted(&pool, 0).await?, (1, 0));
+    assert!(restore_threads(&pool, &user_id, &threads_request)
+        .await?
+        .thread_ids
+        .is_empty());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn search_history_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "Which GLP-1 agonists help with weight loss?".to_string(),
+        ..Default::default()
+    };
+    let glp1_search =
+        insert_new_search(&pool, &user_id, &search_query, "GLP-1 weight loss").await?;
+    append_search_result(
+        &pool,
+        &glp1_search,
+        "Semaglutide & tirzepatide lower body weight [1].",
+    )
+    .await?;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "Is metformin safe in pregnancy?".to_string(),
+        ..Default::default()
+    };
+    insert_new_search(&pool, &user_id, &search_query, "metformin pregnancy safety").await?;
+
+    let mut history_search_request = HistorySearchRequest {
+        query: "semaglutide".to_string(),
+        from: None,
+        to: None,
+        reaction: None,
+        limit: None,
+        offset: None,
+    };
+    let response = search_history(&pool, &user_id, &history_search_request).await?;
+    assert_eq!(response.results.len(), 1);
+    assert_eq!(response.results[0].search_id, glp1_search.search_id);
+    assert!(response.results[0]
+        .result_highlight
+        .contains("<mark>Semaglutide</mark>"));
+    // the answer is escaped, only the highlights are markup
+    assert!(response.results[0]
+        .result_highlight
+        .contains("<mark>Semaglutide</mark> &amp; tirzepatide"));
+
+    // the filters leave out searches outside the date range or without the reaction
+    history_search_request.reaction = Some(true);
+    let response = search_history(&pool, &user_id, &history_search_request).await?;
+    assert!(response.results.is_empty());
+    history_search_request.reaction = None;
+    history_search_request.to = Some(time::OffsetDateTime::now_utc() - time::Duration::days(1));
+    let response = search_history(&pool, &user_id, &history_search_request).await?;
+    assert!(response.results.is_empty());
+
+    // other users do not see the history
+    history_search_request.to = None;
+    let response = search_history(&pool, &user_id, &history_search_request).await?;
+    assert_eq!(response.results.len(), 1);
+    let response = search_history(&pool, &uuid::Uuid::new_v4(), &history_search_request).await?;
+    assert!(response.results.is_empty());
+
+    // the offset pages past the results
+    history_search_request.offset = Some(1);
+    let response = search_history(&pool, &user_id, &history_search_request).await?;
+    assert!(response.results.is_empty());
+    history_search_request.offset = Some(10001);
+    assert!(history_search_request.validate().is_err());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn paginate_threads_and_searches_with_cursors_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let mut search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let first_search = insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    search_query.thread_id = Some(first_search.thread_id);
+    for _ in 0..2 {
+        insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    }
+    search_query.thread_id = None;
+    for _ in 0..2 {
+        insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    }
+
+    let mut thread_history_request = ThreadHistoryRequest {
+        limit: Some(2),
+        cursor: None,
+        include_archived: None,
+    };
+    let first_page = get_threads(&pool, &user_id, &thread_history_request).await?;
+    assert_eq!(first_page.threads.len(), 2);
+    assert!(first_page.next_cursor.is_some());
+
+    // a thread created while paging does not shift the next page
+    insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    thread_history_request.cursor = first_page.next_cursor;
+    let last_page = get_threads(&pool, &user_id, &thread_history_request).await?;
+    assert_eq!(last_page.threads.len(), 1);
+    assert_eq!(last_page.threads[0].thread_id, first_search.thread_id);
+    assert!(last_page.next_cursor.is_none());
+
+    let mut get_thread_request = GetThreadRequest {
+        thread_id: first_search.thread_id,
+        limit: Some(2),
+        cursor: None,
+        include_archived: None,
+    };
+    let first_page = get_one_thread(&pool, &user_id, &get_thread_request).await?;
+    assert_eq!(first_page.searches.len(), 2);
+    get_thread_request.cursor = first_page.next_cursor;
+    let last_page = get_one_thread(&pool, &user_id, &get_thread_request).await?;
+    assert_eq!(last_page.searches.len(), 1);
+    assert_eq!(
+        last_page.searches[0].search.search_id,
+        first_search.search_id
+    );
+    assert!(last_page.next_cursor.is_none());
+
+    get_thread_request.cursor = Some("not-a-cursor".to_string());
+    assert!(get_one_thread(&pool, &user_id, &get_thread_request)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn share_thread_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let search = insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    add_search_sources(
+        &pool,
+        &search,
+        &vec![server::rag::Source {
+            url: "https://example.com/test-source".to_string(),
+            title: "test-source".to_string(),
+            description: "test-description".to_string(),
+            source_type: server::search::SourceType::Url,
+            metadata: HashMap::new(),
+        }],
+    )
+    .await?;
+
+    let mut share_thread_request = ShareThreadRequest {
+        thread_id: search.thread_id,
+        include_sources: true,
+        expires_at: None,
+    };
+    // only the owner can share the thread
+    assert!(
+        share_thread(&pool, &uuid::Uuid::new_v4(), &share_thread_request)
+            .await
+            .is_err()
+    );
+    let shared_thread = share_thread(&pool, &user_id, &share_thread_request).await?;
+    assert!(shared_thread.token.len() >= 43);
+
+    let snapshot = get_shared_thread(&pool, &shared_thread.token).await?;
+    assert_eq!(snapshot.searches.len(), 1);
+    assert_eq!(snapshot.searches[0].sources[0].title, "test-source");
+    let snapshot_json = serde_json::to_string(&snapshot).unwrap();
+    assert!(!snapshot_json.contains(&user_id.to_string()));
+    assert!(!snapshot_json.contains("test-email"));
+
+    // searches made after the link was created are not shared
+    let follow_up_query = SearchQueryRequest {
+        thread_id: Some(search.thread_id),
+        query: "test-follow-up-query".to_string(),
+        ..Default::default()
+    };
+    insert_new_search(&pool, &user_id, &follow_up_query, "test-query").await?;
+    let snapshot = get_shared_thread(&pool, &shared_thread.token).await?;
+    assert_eq!(snapshot.searches.len(), 1);
+
+    revoke_shared_thread(
+        &pool,
+        &user_id,
+        &RevokeSharedThreadRequest {
+            token: shared_thread.token.clone(),
+        },
+    )
+    .await?;
+    assert!(get_shared_thread(&pool, &shared_thread.token)
+        .await
+        .is_err());
+
+    // expired links and links without sources
+    share_thread_request.include_sources = false;
+    let shared_thread = share_thread(&pool, &user_id, &share_thread_request).await?;
+    let snapshot = get_shared_thread(&pool, &shared_thread.token).await?;
+    assert!(snapshot.searches[0].sources.is_empty());
+    share_thread_request.expires_at =
+        Some(time::OffsetDateTime::now_utc() - time::Duration::hours(1));
+    let shared_thread = share_thread(&pool, &user_id, &share_thread_request).await?;
+    assert!(get_shared_thread(&pool, &shared_thread.token)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn export_thread_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let mut search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let first_search = insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    append_search_result(&pool, &first_search, "test-answer [1] [2] [3]").await?;
+    let source = |name: &str| server::rag::Source {
+        url: format!("https://example.com/{}", name),
+        title: name.to_string(),
+        description: "test-description".to_string(),
+        source_type: server::search::SourceType::Url,
+        metadata: HashMap::new(),
+    };
+    // a source already known from another search is cited in the middle of the answer
+    let other_search = insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    add_search_sources(&pool, &other_search, &vec![source("test-known-source")]).await?;
+    add_search_sources(
+        &pool,
+        &first_search,
+        &vec![
+            source("test-source"),
+            source("test-known-source"),
+            source("test-another-source"),
+        ],
+    )
+    .await?;
+    search_query.thread_id = Some(first_search.thread_id);
+    search_query.query = "test-follow-up-query".to_string();
+    insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+
+    let mut export_request = ExportRequest {
+        thread_id: Some(first_search.thread_id),
+        search_id: None,
+        format: ExportFormat::Pdf,
+        citation_style: None,
+    };
+    let document = get_export_document(&pool, &user_id, &export_request).await?;
+    assert_eq!(document.title, "test-query");
+    assert_eq!(document.searches.len(), 2);
+    assert_eq!(document.searches[0].answer, "test-answer [1] [2] [3]");
+    // the sources are in the order they are cited in the answer
+    assert_eq!(
+        document.searches[0]
+            .sources
+            .iter()
+            .map(|source| source.title.as_str())
+            .collect::<Vec<_>>(),
+        vec!["test-source", "test-known-source", "test-another-source"]
+    );
+    assert!(document.searches[1].sources.is_empty());
+    assert!(render_export(export_request.format, &document).starts_with(b"%PDF"));
+
+    export_request.thread_id = None;
+    export_request.search_id = Some(first_search.search_id);
+    export_request.citation_style = Some(CitationStyle::Vancouver);
+    let document = get_export_document(&pool, &user_id, &export_request).await?;
+    assert_eq!(document.searches.len(), 1);
+    let citation = document.searches[0].sources[0].citation.clone().unwrap();
+    assert!(citation.starts_with("test-source [Internet]. [cited "));
+    assert!(citation.ends_with("Available from: https://example.com/test-source"));
+
+    // other users cannot export the thread
+    assert!(
+        get_export_document(&pool, &uuid::Uuid::new_v4(), &export_request)
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn citations_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let search = insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    add_search_sources(
+        &pool,
+        &search,
+        &vec![
+            server::rag::Source {
+                url: "https://pubmed.ncbi.nlm.nih.gov/12345678".to_string(),
+                title: "test-article".to_string(),
+                description: "test-abstract".to_string(),
+                source_type: server::search::SourceType::Url,
+                metadata: HashMap::from_iter(vec![
+                    ("pmid".to_string(), "12345678".to_string()),
+                    ("authors".to_string(), "Smith, Jane".to_string()),
+                    ("journal".to_string(), "test-journal".to_string()),
+                    ("year".to_string(), "2021".to_string()),
+                ]),
+            },
+            server::rag::Source {
+                url: "https://example.com/test-source".to_string(),
+                title: "test-source".to_string(),
+                description: "test-description".to_string(),
+                source_type: server::search::SourceType::Url,
+                metadata: HashMap::new(),
+            },
+        ],
+    )
+    .await?;
+
+    let mut citation_request = CitationExportRequest {
+        search_id: None,
+        thread_id: Some(search.thread_id),
+        collection_id: None,
+        format: CitationFormat::Bibtex,
+    };
+    let sources = get_citation_sources(&pool, &user_id, &citation_request).await?;
+    assert_eq!(sources.len(), 2);
+    let records: Vec<CitationRecord> = sources.iter().map(CitationRecord::from_source).collect();
+    let bibtex = String::from_utf8(render_citations(citation_request.format, &records)).unwrap();
+    assert!(bibtex.contains("@article{smith2021testarticle,"));
+    assert!(bibtex.contains("@misc{testsource,"));
+
+    let search_by_id_request = SearchByIdRequest {
+        search_id: search.search_id,
+        citation_style: Some(CitationStyle::Ama),
+    };
+    let response = get_one_search(&pool, &user_id, &search_by_id_request).await?;
+    assert!(response.citations.unwrap().contains(
+        &"Smith J. test-article. test-journal. 2021. https://pubmed.ncbi.nlm.nih.gov/12345678"
+            .to_string()
+    ));
+
+    // other users cannot cite the thread
+    assert!(
+        get_citation_sources(&pool, &uuid::Uuid::new_v4(), &citation_request)
+            .await
+            .is_err()
+    );
+
+    let collection_id: uuid::Uuid = sqlx::query_scalar(
+        "insert into collections (user_id, name, category) values ($1, 'test-collection', 0) returning collection_id",
+    )
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await?;
+    sqlx::query("insert into collection_searches (collection_id, search_id) values ($1, $2)")
+        .bind(collection_id)
+        .bind(search.search_id)
+        .execute(&pool)
+        .await?;
+
+    citation_request.thread_id = None;
+    citation_request.collection_id = Some(collection_id);
+    let sources = get_citation_sources(&pool, &user_id, &citation_request).await?;
+    assert_eq!(sources.len(), 2);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn import_collection_test(pool: PgPool) -> Result<()> {
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let search = insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    let cited_sources = add_search_sources(
+        &pool,
+        &search,
+        &vec![server::rag::Source {
+            url: "https://pubmed.ncbi.nlm.nih.gov/12345678".to_string(),
+            title: "test-article".to_string(),
+            description: "test-abstract".to_string(),
+            source_type: server::search::SourceType::Url,
+            metadata: HashMap::from_iter(vec![("pmid".to_string(), "12345678".to_string())]),
+        }],
+    )
+    .await?;
+
+    let collection_id: uuid::Uuid = sqlx::query_scalar(
+        "insert into collections (user_id, name, category) values ($1, 'test-collection', 0) returning collection_id",
+    )
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await?;
+
+    let bibtex = r#"
+        @article{known, title = {test-article}, pmid = {12345678}, journal = {test-journal}}
+        @article{new, title = {test-new-article}, doi = {10.1000/test}}
+        @misc{missing, title = {test-without-url}}
+    "#;
+    let import_request = CollectionImportRequest {
+        collection_id,
+        format: CitationFormat::Bibtex,
+    };
+    let report = import_collection_sources(
+        &pool,
+        &user_id,
+        &import_request,
+        parse_bibtex(bibtex),
+        "https://pubmed.ncbi.nlm.nih.gov",
+    )
+    .await?;
+    assert_eq!((report.matched, report.created, report.failed), (1, 1, 1));
+    assert_eq!(
+        report.entries[0].source_id,
+        Some(cited_sources[0].source_id)
+    );
+    assert_eq!(report.entries[1].status, ImportStatus::Created);
+    assert_eq!(report.entries[2].label, "missing");
+
+    // the imported sources are cited with the collection, with the metadata they gained
+    let citation_request = CitationExportRequest {
+        search_id: None,
+        thread_id: None,
+        collection_id: Some(collection_id),
+        format: CitationFormat::Ris,
+    };
+    let sources = get_citation_sources(&pool, &user_id, &citation_request).await?;
+    assert_eq!(sources.len(), 2);
+    let record = CitationRecord::from_source(&sources[0]);
+    assert_eq!(record.journal.as_deref(), Some("test-journal"));
+    assert_eq!(
+        CitationRecord::from_source(&sources[1]).url,
+        "https://doi.org/10.1000/test"
+    );
+
+    // the shared sources are left as they were cited by the search
+    let search_by_id_request = SearchByIdRequest {
+        search_id: search.search_id,
+        citation_style: None,
+    };
+    let response = get_one_search(&pool, &user_id, &search_by_id_request).await?;
+    assert_eq!(
+        response.sources[0].metadata,
+        Some(serde_json::json!({"pmid": "12345678"}))
+    );
+
+    // importing again only matches
+    let report = import_collection_sources(
+        &pool,
+        &user_id,
+        &import_request,
+        parse_bibtex(bibtex),
+        "https://pubmed.ncbi.nlm.nih.gov",
+    )
+    .await?;
+    assert_eq!((report.matched, report.created, report.failed), (2, 0, 1));
+
+    // other users cannot import into the collection
+    assert!(import_collection_sources(
+        &pool,
+        &uuid::Uuid::new_v4(),
+        &import_request,
+        parse_bibtex(bibtex),
+        "https://pubmed.ncbi.nlm.nih.gov",
+    )
+    .await
+    .is_err());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn refresh_pubmed_metadata_test(pool: PgPool) -> Result<()> {
+    let mut settings = Settings::new();
+    let dependencies = Dependencies::new(&settings.resilience)?;
+
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let search = insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    add_search_sources(
+        &pool,
+        &search,
+        &vec![server::rag::Source {
+            url: "https://pubmed.ncbi.nlm.nih.gov/12345678".to_string(),
+            title: "test-article".to_string(),
+            description: "test-abstract".to_string(),
+            source_type: server::search::SourceType::Url,
+            metadata: HashMap::from_iter(vec![("pmid".to_string(), "12345678".to_string())]),
+        }],
+    )
+    .await?;
+
+    // Mock E-utilities server
+    let server = MockServer::start();
+    settings.pubmed_metadata.url = server.url("/efetch.fcgi");
+    let efetch_mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/efetch.fcgi")
+            .x_www_form_urlencoded_tuple("id", "12345678");
+
+        then.status(200).header("content-type", "text/xml").body(
+            "<PubmedArticleSet><PubmedArticle><MedlineCitation><PMID>12345678</PMID>\
+                <Article><Journal><JournalIssue><PubDate><Year>2021</Year></PubDate>\
+                </JournalIssue><Title>test-journal</Title></Journal>\
+                <PublicationTypeList><PublicationType>Meta-Analysis</PublicationType>\
+                </PublicationTypeList></Article></MedlineCitation></PubmedArticle>\
+                </PubmedArticleSet>",
+        );
+    });
+
+    let refreshed =
+        refresh_pubmed_metadata(&pool, &dependencies.pubmed, &settings.pubmed_metadata).await?;
+    assert_eq!(refreshed, 1);
+    efetch_mock.assert();
+
+    let citation_request = CitationExportRequest {
+        search_id: Some(search.search_id),
+        thread_id: None,
+        collection_id: None,
+        format: CitationFormat::CslJson,
+    };
+    let sources = get_citation_sources(&pool, &user_id, &citation_request).await?;
+    let metadata = sources[0].metadata.clone().unwrap();
+    assert_eq!(metadata["journal"], "test-journal");
+    assert_eq!(metadata["publication_types"], "Meta-Analysis");
+    assert!(metadata.get("enriched_at").is_some());
+
+    // the fresh records are left alone until they are stale again
+    let refreshed =
+        refresh_pubmed_metadata(&pool, &dependencies.pubmed, &settings.pubmed_metadata).await?;
+    assert_eq!(refreshed, 0);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn import_retractions_test(pool: PgPool) -> Result<()> {
+    let mut settings = Settings::new();
+
+    let new_user = register(
+        pool.clone(),
+        RegisterUserRequest {
+            email: "test-email".to_string(),
+            password: Some("password".to_string().into()),
+            access_token: Default::default(),
+        },
+    )
+    .await?;
+
+    let user_id = new_user.user_id;
+    let search_query = SearchQueryRequest {
+        thread_id: None,
+        query: "test-query".to_string(),
+        ..Default::default()
+    };
+    let search = insert_new_search(&pool, &user_id, &search_query, "test-query").await?;
+    let retracted_source = server::rag::Source {
+        url: "https://pubmed.ncbi.nlm.nih.gov/32450107".to_string(),
+        title: "test-retracted-article".to_string(),
+        description: "test-abstract".to_string(),
+        source_type: server::search::SourceType::Url,
+        metadata: HashMap::from_iter(vec![("pmid".to_string(), "32450107".to_string())]),
+    };
+    add_search_sources(&pool, &search, &vec![retracted_source.clone()]).await?;
+
+    let dataset_path = std::env::temp_dir().join(format!("retractions-{}.csv", search.search_id));
+    std::fs::write(
+        &dataset_path,
+        "Record ID,RetractionDate,RetractionDOI,OriginalPaperDOI,OriginalPaperPubMedID,RetractionNature,Reason\n\
+            1,6/4/2020 0:00,10.1016/S0140-6736(20)31324-6,10.1016/S0140-6736(20)31180-6,32450107,Retraction,+Concerns/Issues About Data;\n\
+            2,7/14/2021 0:00,unavailable,10.21203/rs.3.rs-100956/v1,0,Expression of concern,+Concerns/Issues About Data;\n",
+    )
+    .unwrap();
+    settings.retractions.enabled = true;
+    settings.retractions.dataset_path = dataset_path.to_string_lossy().to_string();
+
+    let imported = import_retractions(&pool, &settings.retractions).await;
+    std::fs::remove_file(&dataset_path).unwrap();
+    assert_eq!(imported?, 2);
+
+    // the stored sources are flagged on import
+    let citation_request = CitationExportRequest {
+        search_id: Some(search.search_id),
+        thread_id: None,
+        collection_id: None,
+        format: CitationFormat::CslJson,
+    };
+    let sources = get_citation_sources(&pool, &user_id, &citation_request).await?;
+    let metadata = sources[0].metadata.clone().unwrap();
+    assert_eq!(metadata["retraction_status"], "retraction");
+    assert_eq!(
+        metadata["retraction_notice_doi"],
+        "10.1016/S0140-6736(20)31324-6"
+    );
+    assert_eq!(metadata["retraction_date"], "2020-06-04");
+
+    // and the retrieved ones are checked before they are used
+    let retrieved_results = vec![
+        RetrievedResult {
+            text: "test-retracted-text".to_string(),
+            source: retracted_source,
+            similarity: None,
+        },
+        RetrievedResult {
+            text: "test-preprint-text".to_string(),
+            source: server::rag::Source {
+                url: "https://www.researchsquare.com/article/rs-100956/v1".to_string(),
+                title: "test-preprint".to_string(),
+                description: "test-abstract".to_string(),
+                source_type: server::search::SourceType::Url,
+                metadata: HashMap::from_iter(vec![(
+                    "doi".to_string(),
+                    "10.21203/RS.3.RS-100956/v1".to_string(),
+                )]),
+            },
+            similarity: None,
+        },
+    ];
+    let screened =
+        screen_retractions(&pool, &settings.retractions, retrieved_results.clone()).await?;
+    assert_eq!(screened.len(), 1);
+    assert_eq!(
+        screened[0].source.metadata["retraction_status"],
+        "expression_of_concern"
+    );
+
+    settings.retractions.retracted_sources = RetractedSourcePolicy::Flag;
+    let screened = screen_retractions(&pool, &settings.retractions, retrieved_results).await?;
+    assert_eq!(screened.len(), 2);
+    assert_eq!(
+        screened[0].source.metadata["retraction_status"],
+        "retraction"
+    );
+
+    Ok(())
+}
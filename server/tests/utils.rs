@@ -70,9 +70,9 @@ pub async fn agency_server_and_client_stub() -> (
         pubmed_cluster_search_response: PubmedResponse {
             status: 200,
             sources: vec![PubmedSource {
-                pubmed_id: "test-pubmed-id".to_string(),
-                title: "test-title".to_string(),
-                r#abstract: "test-abstract".to_string(),
+                pubmed_id: "test-cluster-pubmed-id".to_string(),
+                title: "test-cluster-title".to_string(),
+                r#abstract: "test-cluster-abstract".to_string(),
                 embeddings: Some(Embeddings {
                     dense_embedding: vec![],
                     sparse_embedding: vec![],